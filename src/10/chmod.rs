@@ -1,24 +1,244 @@
+use getopts::Options;
+use nix::sys::stat::{umask, Mode};
 use std::env;
 use std::fs;
 use std::io;
 use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::process;
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+mod mode;
 
-    if args.len() < 2 {
-        eprintln!("{:?}: no mode given", &args[0]);
+use mode::ModeChange;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Report {
+    Silent,
+    Changes,
+    All,
+}
+
+struct ChmodOptions {
+    change: ModeChange,
+    recursive: bool,
+    report: Report,
+    umask: u32,
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+
+    // "-w" or "-x,g+s" are modes, not options
+    let dash_mode = args[1..]
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .position(|arg| is_dash_mode(arg))
+        .map(|i| args.remove(i + 1));
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("R", "recursive", "change files and directories recursively");
+    opts.optflag(
+        "v",
+        "verbose",
+        "output a diagnostic for every file processed",
+    );
+    opts.optflag(
+        "c",
+        "changes",
+        "like verbose but report only when a change is made",
+    );
+    opts.optopt(
+        "",
+        "reference",
+        "use RFILE's mode instead of specifying MODE values",
+        "RFILE",
+    );
+    opts.optflag("", "preserve-root", "fail to operate recursively on '/'");
+    opts.optflag(
+        "",
+        "no-preserve-root",
+        "do not treat '/' specially (the default)",
+    );
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!(
+            "Usage: {:?} [-Rcv] [--preserve-root] MODE[,MODE]... FILE...\n       {:?} [-Rcv] [--preserve-root] --reference=RFILE FILE...",
+            &args[0], &args[0]
+        );
+        process::exit(0);
+    }
+
+    let mut files = matches.free.clone();
+    let change = if let Some(reference) = matches.opt_str("reference") {
+        match fs::metadata(&reference) {
+            Ok(st) => ModeChange::Absolute(st.permissions().mode() & 0o7777),
+            Err(why) => {
+                eprintln!("{:?}: {:?}", reference, why.to_string());
+                process::exit(1);
+            }
+        }
+    } else {
+        let spec = match dash_mode {
+            Some(spec) => spec,
+            None if !files.is_empty() => files.remove(0),
+            None => {
+                eprintln!("{:?}: no mode given", &args[0]);
+                process::exit(1);
+            }
+        };
+        match ModeChange::parse(&spec) {
+            Ok(change) => change,
+            Err(msg) => {
+                eprintln!("{:?}: {}", &args[0], msg);
+                process::exit(1);
+            }
+        }
+    };
+
+    if files.is_empty() {
+        eprintln!("{:?}: missing operand", &args[0]);
         process::exit(1);
     }
 
-    let mode: u32 = u32::from_str_radix(&args[1], 8).unwrap();
+    let chmod_opts = ChmodOptions {
+        change,
+        recursive: matches.opt_present("R"),
+        report: if matches.opt_present("v") {
+            Report::All
+        } else if matches.opt_present("c") {
+            Report::Changes
+        } else {
+            Report::Silent
+        },
+        umask: current_umask(),
+    };
+    let preserve_root = matches.opt_positions("preserve-root").last()
+        > matches.opt_positions("no-preserve-root").last();
+
+    let mut status = 0;
+    for file in &files {
+        let path = Path::new(file);
+        if chmod_opts.recursive && preserve_root && is_root(path) {
+            eprintln!(
+                "{:?}: it is dangerous to operate recursively on {:?}",
+                &args[0], file
+            );
+            status = 1;
+            continue;
+        }
+        if !do_chmod(path, &chmod_opts) {
+            status = 1;
+        }
+    }
+
+    process::exit(status);
+}
+
+fn is_dash_mode(arg: &str) -> bool {
+    arg.len() > 1
+        && arg.starts_with('-')
+        && arg[1..]
+            .chars()
+            .all(|c| "rwxXstugoa+-=,01234567".contains(c))
+}
+
+fn is_root(path: &Path) -> bool {
+    fs::canonicalize(path)
+        .map(|path| path == Path::new("/"))
+        .unwrap_or(false)
+}
+
+// umask(2) can only be read by setting it
+fn current_umask() -> u32 {
+    let old = umask(Mode::empty());
+    umask(old);
+
+    old.bits() as u32
+}
+
+// Change `path` and, with -R, everything below it. Symbolic links found
+// while recursing are skipped, so nothing outside the tree is touched.
+// Returns false when anything failed.
+fn do_chmod(path: &Path, opts: &ChmodOptions) -> bool {
+    let mut ok = match change_mode(path, opts) {
+        Ok(()) => true,
+        Err(why) => {
+            eprintln!("{:?}: {:?}", path, why.to_string());
+            false
+        }
+    };
+
+    if !opts.recursive || !is_real_dir(path) {
+        return ok;
+    }
+
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(why) => {
+            eprintln!("{:?}: {:?}", path, why.to_string());
+            return false;
+        }
+    };
+    for entry in entries {
+        match entry {
+            Ok(entry) => {
+                let child = entry.path();
+                if entry.file_type().map(|t| t.is_symlink()).unwrap_or(false) {
+                    continue;
+                }
+                ok &= do_chmod(&child, opts);
+            }
+            Err(why) => {
+                eprintln!("{:?}: {:?}", path, why.to_string());
+                ok = false;
+            }
+        }
+    }
+
+    ok
+}
+
+fn is_real_dir(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .map(|st| st.is_dir())
+        .unwrap_or(false)
+}
+
+fn change_mode(path: &Path, opts: &ChmodOptions) -> io::Result<()> {
+    let st = fs::metadata(path)?;
+    let old = st.permissions().mode() & 0o7777;
+    let new = opts.change.apply(old, st.is_dir(), opts.umask);
+
+    if new != old {
+        fs::set_permissions(path, fs::Permissions::from_mode(new))?;
+    }
 
-    for i in 2..args.len() {
-        let mut perms = fs::metadata(&args[i])?.permissions();
-        perms.set_mode(mode);
-        fs::set_permissions(&args[i], perms)?;
+    match opts.report {
+        Report::All if new == old => println!(
+            "mode of {:?} retained as {:04o} ({})",
+            path,
+            old,
+            mode::symbolic(old)
+        ),
+        Report::All | Report::Changes if new != old => println!(
+            "mode of {:?} changed from {:04o} ({}) to {:04o} ({})",
+            path,
+            old,
+            mode::symbolic(old),
+            new,
+            mode::symbolic(new)
+        ),
+        _ => {}
     }
 
-    process::exit(0);
+    Ok(())
 }
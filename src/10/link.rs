@@ -0,0 +1,280 @@
+// ln と symlink の共通実装. symlink は ln -s として振る舞う.
+
+use getopts::{Matches, Options};
+use nix::unistd::{linkat, LinkatFlags};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::resolve::{self, Missing};
+
+struct LnOptions {
+    symbolic: bool,
+    force: bool,
+    relative: bool,
+    verbose: bool,
+    follow: LinkatFlags,
+}
+
+pub fn run(symbolic: bool) {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("s", "symbolic", "make symbolic links instead of hard links");
+    opts.optflag("f", "force", "replace existing destination files");
+    opts.optflag(
+        "r",
+        "relative",
+        "make symbolic links relative to link location",
+    );
+    opts.optflag(
+        "n",
+        "no-dereference",
+        "treat LINK_NAME as a normal file if it is a symbolic link to a directory",
+    );
+    opts.optflag(
+        "T",
+        "no-target-directory",
+        "treat LINK_NAME as a normal file always",
+    );
+    opts.optflag("v", "verbose", "print name of each linked file");
+    opts.optflagmulti(
+        "L",
+        "logical",
+        "dereference TARGETs that are symbolic links",
+    );
+    opts.optflagmulti(
+        "P",
+        "physical",
+        "make hard links directly to symbolic links",
+    );
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!(
+            "Usage: {:?} [-sfrnTvLP] TARGET [LINK_NAME]\n       {:?} [-sfrnvLP] TARGET... DIRECTORY",
+            &args[0], &args[0]
+        );
+        process::exit(0);
+    }
+
+    let ln_opts = LnOptions {
+        symbolic: symbolic || matches.opt_present("s"),
+        force: matches.opt_present("f"),
+        relative: matches.opt_present("r"),
+        verbose: matches.opt_present("v"),
+        // -L と -P は後に指定されたものが優先される
+        follow: match (
+            matches.opt_positions("L").last(),
+            matches.opt_positions("P").last(),
+        ) {
+            (Some(l), Some(p)) if l > p => LinkatFlags::SymlinkFollow,
+            (Some(_), None) => LinkatFlags::SymlinkFollow,
+            _ => LinkatFlags::NoSymlinkFollow,
+        },
+    };
+
+    if ln_opts.relative && !ln_opts.symbolic {
+        eprintln!("{:?}: cannot do --relative without --symbolic", &args[0]);
+        process::exit(1);
+    }
+
+    let jobs = match plan(&matches) {
+        Ok(jobs) => jobs,
+        Err(msg) => {
+            eprintln!("{:?}: {}", &args[0], msg);
+            process::exit(1);
+        }
+    };
+
+    let mut status = 0;
+    for (target, link_name) in jobs {
+        if let Err(why) = do_ln(&target, &link_name, &ln_opts) {
+            eprintln!("{:?}: {:?}", link_name, why.to_string());
+            status = 1;
+        }
+    }
+
+    process::exit(status);
+}
+
+// Turn the operands into (target, link name) pairs.
+fn plan(matches: &Matches) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let free = &matches.free;
+    let no_target = matches.opt_present("T");
+    let no_deref = matches.opt_present("n");
+
+    // -n: a symlink to a directory is replaced rather than entered
+    let is_dir = |path: &str| {
+        let path = Path::new(path);
+        if no_deref && path.read_link().is_ok() {
+            return false;
+        }
+        path.is_dir()
+    };
+
+    match free.len() {
+        0 => Err("missing file operand".to_string()),
+        1 if no_target => Err(format!(
+            "missing destination file operand after {:?}",
+            free[0]
+        )),
+        1 => into_dir(&free[0], ".").map(|job| vec![job]),
+        2 if no_target || !is_dir(&free[1]) => {
+            Ok(vec![(PathBuf::from(&free[0]), PathBuf::from(&free[1]))])
+        }
+        _ if no_target => Err(format!("extra operand {:?}", free[2])),
+        n => {
+            let dir = &free[n - 1];
+            if !is_dir(dir) {
+                return Err(format!("target {:?} is not a directory", dir));
+            }
+            free[..n - 1].iter().map(|src| into_dir(src, dir)).collect()
+        }
+    }
+}
+
+fn into_dir(target: &str, dir: &str) -> Result<(PathBuf, PathBuf), String> {
+    match Path::new(target).file_name() {
+        Some(name) => Ok((PathBuf::from(target), Path::new(dir).join(name))),
+        None => Err(format!("cannot make a link to {:?} in {:?}", target, dir)),
+    }
+}
+
+fn do_ln(target: &Path, link_name: &Path, opts: &LnOptions) -> io::Result<()> {
+    let contents = if opts.relative {
+        relative_target(target, link_name)?
+    } else {
+        target.to_path_buf()
+    };
+
+    if !opts.force {
+        make_link(&contents, link_name, opts)?;
+    } else {
+        if let Ok(dst_st) = fs::symlink_metadata(link_name) {
+            if dst_st.is_dir() {
+                return Err(io::Error::from_raw_os_error(libc::EISDIR));
+            }
+            // rename(2) onto another link of the same inode does nothing
+            if !opts.symbolic {
+                let src_st = match opts.follow {
+                    LinkatFlags::SymlinkFollow => fs::metadata(target)?,
+                    LinkatFlags::NoSymlinkFollow => fs::symlink_metadata(target)?,
+                };
+                if src_st.dev() == dst_st.dev() && src_st.ino() == dst_st.ino() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{:?} and {:?} are the same file", target, link_name),
+                    ));
+                }
+            }
+        }
+
+        // make the link under a temporary name and rename it into place,
+        // so that LINK_NAME never disappears in between
+        let tmp = temporary_name(link_name);
+        make_link(&contents, &tmp, opts)?;
+        if let Err(why) = fs::rename(&tmp, link_name) {
+            fs::remove_file(&tmp).ok();
+            return Err(why);
+        }
+    }
+
+    // as coreutils does: => for hard links, -> for symbolic ones
+    if opts.verbose {
+        let arrow = if opts.symbolic { "->" } else { "=>" };
+        println!("{:?} {} {:?}", link_name, arrow, contents);
+    }
+
+    Ok(())
+}
+
+fn make_link(target: &Path, link_name: &Path, opts: &LnOptions) -> io::Result<()> {
+    if opts.symbolic {
+        symlink(target, link_name)
+    } else {
+        linkat(None, target, None, link_name, opts.follow).map_err(|why| match why.as_errno() {
+            Some(errno) => io::Error::from(errno),
+            None => io::Error::other(why.to_string()),
+        })
+    }
+}
+
+fn temporary_name(link_name: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(link_name.file_name().unwrap_or_default());
+    name.push(format!(".ln{}", process::id()));
+
+    link_name.with_file_name(name)
+}
+
+// The path of `target` as seen from the directory `link_name` is created in.
+fn relative_target(target: &Path, link_name: &Path) -> io::Result<PathBuf> {
+    let target = resolve::canonicalize(target, Missing::Allowed)?;
+    let base = resolve::canonicalize(
+        link_name.parent().unwrap_or_else(|| Path::new(".")),
+        Missing::Allowed,
+    )?;
+
+    Ok(resolve::relative_to(&target, &base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_targets() {
+        let base = env::temp_dir().join(format!("link-test-{}", process::id()));
+        fs::create_dir_all(base.join("a/b")).unwrap();
+        fs::create_dir_all(base.join("c/d")).unwrap();
+        // the temporary directory may itself be behind a symlink
+        let base = fs::canonicalize(&base).unwrap();
+        symlink(base.join("a/b"), base.join("c/to_b")).unwrap();
+        let relative = |target: &str, link_name: &str| {
+            relative_target(&base.join(target), &base.join(link_name)).unwrap()
+        };
+
+        assert_eq!(relative("a/file", "a/link"), Path::new("file"));
+        assert_eq!(
+            relative("a/b/file", "c/d/link"),
+            Path::new("../../a/b/file")
+        );
+        assert_eq!(relative("a", "a/b/link"), Path::new(".."));
+        assert_eq!(relative("a/b", "link"), Path::new("a/b"));
+        // .. and symlinks are resolved on both sides first
+        assert_eq!(
+            relative("c/d/../../a/file", "c/d/../link"),
+            Path::new("../a/file")
+        );
+        assert_eq!(relative("c/to_b/file", "a/link"), Path::new("b/file"));
+        assert_eq!(relative("a/file", "c/to_b/link"), Path::new("../file"));
+        // the target does not need to exist
+        assert_eq!(relative("none/x", "c/link"), Path::new("../none/x"));
+
+        // a bare link name is made in the current directory
+        let cwd = fs::canonicalize(".").unwrap();
+        assert_eq!(
+            relative_target(&cwd.join("x/y"), Path::new("link")).unwrap(),
+            Path::new("x/y")
+        );
+        assert_eq!(
+            relative_target(Path::new("x"), Path::new("link")).unwrap(),
+            Path::new("x")
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
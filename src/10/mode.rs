@@ -0,0 +1,255 @@
+// chmod のモード指定 (8進数とシンボリック表記) の解析と適用.
+//
+// Not every binary uses every item in this module.
+#![allow(dead_code)]
+
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+const S_ISVTX: u32 = 0o1000;
+const S_IRWXU: u32 = 0o700;
+const S_IRWXG: u32 = 0o070;
+const S_IRWXO: u32 = 0o007;
+const S_IXUGO: u32 = 0o111;
+const ALL_BITS: u32 = 0o7777;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Perm {
+    Bits(u32),
+    // X: execute only for directories or files that are already executable
+    ConditionalExec,
+    // u, g, o on the right hand side: copy that class's current bits
+    Copy(u32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Action {
+    // 0 when no class was given: then the umask applies
+    who: u32,
+    op: char,
+    perms: Vec<Perm>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModeChange {
+    Absolute(u32),
+    Symbolic(Vec<Action>),
+}
+
+impl ModeChange {
+    pub fn parse(spec: &str) -> Result<ModeChange, String> {
+        if !spec.is_empty() && spec.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+            return match u32::from_str_radix(spec, 8) {
+                Ok(mode) if mode <= ALL_BITS => Ok(ModeChange::Absolute(mode)),
+                _ => Err(format!("invalid mode: {:?}", spec)),
+            };
+        }
+
+        let mut actions = Vec::new();
+        for clause in spec.split(',') {
+            parse_clause(clause, &mut actions).map_err(|_| format!("invalid mode: {:?}", spec))?;
+        }
+
+        Ok(ModeChange::Symbolic(actions))
+    }
+
+    // New permission bits for a file whose current mode is `mode`.
+    pub fn apply(&self, mode: u32, is_dir: bool, umask: u32) -> u32 {
+        let actions = match self {
+            ModeChange::Absolute(bits) => return *bits,
+            ModeChange::Symbolic(actions) => actions,
+        };
+
+        let mut mode = mode & ALL_BITS;
+        for action in actions {
+            let (affected, mask) = if action.who == 0 {
+                (ALL_BITS, !umask)
+            } else {
+                (action.who, ALL_BITS)
+            };
+
+            let mut value = 0;
+            for perm in &action.perms {
+                value |= match *perm {
+                    Perm::Bits(bits) => bits,
+                    Perm::ConditionalExec if is_dir || mode & S_IXUGO != 0 => S_IXUGO,
+                    Perm::ConditionalExec => 0,
+                    Perm::Copy(class) => spread(mode, class),
+                };
+            }
+            let value = value & affected & mask;
+
+            mode = match action.op {
+                '+' => mode | value,
+                '-' => mode & !value,
+                _ => (mode & !affected) | value,
+            };
+        }
+
+        mode
+    }
+}
+
+fn parse_clause(clause: &str, actions: &mut Vec<Action>) -> Result<(), ()> {
+    let mut chars = clause.chars().peekable();
+
+    let mut who = 0;
+    while let Some(&c) = chars.peek() {
+        who |= match c {
+            'u' => S_ISUID | S_IRWXU,
+            'g' => S_ISGID | S_IRWXG,
+            'o' => S_ISVTX | S_IRWXO,
+            'a' => ALL_BITS,
+            _ => break,
+        };
+        chars.next();
+    }
+
+    // a clause needs at least one operator
+    if chars.peek().is_none() {
+        return Err(());
+    }
+
+    while let Some(op) = chars.next() {
+        if op != '+' && op != '-' && op != '=' {
+            return Err(());
+        }
+
+        let mut perms = Vec::new();
+        while let Some(&c) = chars.peek() {
+            let perm = match c {
+                'r' => Perm::Bits(0o444),
+                'w' => Perm::Bits(0o222),
+                'x' => Perm::Bits(S_IXUGO),
+                'X' => Perm::ConditionalExec,
+                's' => Perm::Bits(S_ISUID | S_ISGID),
+                't' => Perm::Bits(S_ISVTX),
+                'u' => Perm::Copy(S_IRWXU),
+                'g' => Perm::Copy(S_IRWXG),
+                'o' => Perm::Copy(S_IRWXO),
+                _ => break,
+            };
+            // a class to copy from cannot be mixed with other permissions
+            let is_copy = |p: &Perm| matches!(p, Perm::Copy(_));
+            if !perms.is_empty() && (is_copy(&perm) || perms.iter().any(is_copy)) {
+                return Err(());
+            }
+            perms.push(perm);
+            chars.next();
+        }
+
+        actions.push(Action { who, op, perms });
+    }
+
+    Ok(())
+}
+
+// rwx bits of `class` in `mode`, repeated for user, group and other
+fn spread(mode: u32, class: u32) -> u32 {
+    let bits = match class {
+        S_IRWXU => (mode & S_IRWXU) >> 6,
+        S_IRWXG => (mode & S_IRWXG) >> 3,
+        _ => mode & S_IRWXO,
+    };
+
+    bits * 0o111
+}
+
+// ls -l 形式のパーミッション文字列 (ファイル種別の文字は含まない)
+pub fn symbolic(mode: u32) -> String {
+    let mut s = String::with_capacity(9);
+    let classes = [(6, S_ISUID, 's'), (3, S_ISGID, 's'), (0, S_ISVTX, 't')];
+    for &(shift, special, letter) in &classes {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => letter,
+            (false, true) => letter.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(spec: &str, mode: u32) -> u32 {
+        ModeChange::parse(spec).unwrap().apply(mode, false, 0o022)
+    }
+
+    #[test]
+    fn octal() {
+        assert_eq!(apply("755", 0o600), 0o755);
+        assert_eq!(apply("4755", 0o600), 0o4755);
+        assert_eq!(apply("0", 0o777), 0);
+        assert!(ModeChange::parse("17777").is_err());
+        assert!(ModeChange::parse("789").is_err());
+    }
+
+    #[test]
+    fn add_and_remove() {
+        assert_eq!(apply("u+x", 0o644), 0o744);
+        assert_eq!(apply("u+x,go-w", 0o666), 0o744);
+        assert_eq!(apply("go-rwx", 0o777), 0o700);
+        assert_eq!(apply("a-x", 0o755), 0o644);
+        assert_eq!(apply("u+r-w", 0o200), 0o400);
+    }
+
+    #[test]
+    fn assign() {
+        assert_eq!(apply("u=rw", 0o777), 0o677);
+        assert_eq!(apply("a=r", 0o4777), 0o444);
+        assert_eq!(apply("o=", 0o777), 0o770);
+    }
+
+    #[test]
+    fn conditional_exec() {
+        assert_eq!(apply("a=rX", 0o600), 0o444);
+        assert_eq!(apply("a=rX", 0o700), 0o555);
+        let change = ModeChange::parse("a=rX").unwrap();
+        assert_eq!(change.apply(0o600, true, 0o022), 0o555);
+    }
+
+    #[test]
+    fn special_bits() {
+        assert_eq!(apply("+t", 0o777), 0o1777);
+        assert_eq!(apply("g+s", 0o755), 0o2755);
+        assert_eq!(apply("u+s", 0o755), 0o4755);
+        assert_eq!(apply("o+s", 0o755), 0o755);
+        assert_eq!(apply("a-st", 0o7777), 0o777);
+    }
+
+    #[test]
+    fn umask_without_who() {
+        assert_eq!(apply("+w", 0o444), 0o644);
+        assert_eq!(apply("=rwx", 0o000), 0o755);
+        assert_eq!(apply("a+w", 0o444), 0o666);
+    }
+
+    #[test]
+    fn copy_class() {
+        assert_eq!(apply("g=u", 0o740), 0o770);
+        assert_eq!(apply("o+g", 0o750), 0o755);
+        assert_eq!(apply("go=u-w", 0o700), 0o755);
+    }
+
+    #[test]
+    fn invalid() {
+        for spec in &["", "u", "a+q", "z+x", "u+x,", "u+rg", "+gx"] {
+            assert!(ModeChange::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn symbolic_string() {
+        assert_eq!(symbolic(0o755), "rwxr-xr-x");
+        assert_eq!(symbolic(0o4755), "rwsr-xr-x");
+        assert_eq!(symbolic(0o2644), "rw-r-Sr--");
+        assert_eq!(symbolic(0o1777), "rwxrwxrwt");
+        assert_eq!(symbolic(0o1776), "rwxrwxrwT");
+    }
+}
@@ -1,18 +1,775 @@
+use getopts::{Matches, Options};
+use nix::sys::stat::{mknod, utimensat, Mode, SFlag, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
 use std::env;
+use std::ffi::{CString, OsString};
 use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::process;
 
+#[derive(Clone, Copy, PartialEq)]
+enum Overwrite {
+    Force,
+    Interactive,
+    NoClobber,
+}
+
+// renameat2(2) の flags に対応する
+#[derive(Clone, Copy, PartialEq)]
+enum Atomic {
+    Exchange,
+    NoReplace,
+}
+
+struct MvOptions {
+    overwrite: Overwrite,
+    atomic: Option<Atomic>,
+    backup: Option<String>,
+    update: bool,
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let opts = options();
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!(
+            "Usage: {:?} [-finbu] [-S SUFFIX] [-T] [--exchange | --no-clobber-atomic] SOURCE DEST\n       {:?} [-finbu] [-S SUFFIX] SOURCE... DIRECTORY\n       {:?} [-finbu] [-S SUFFIX] -t DIRECTORY SOURCE...",
+            &args[0], &args[0], &args[0]
+        );
+        process::exit(0);
+    }
+
+    let mv_opts = MvOptions {
+        overwrite: overwrite_mode(&matches),
+        atomic: match (
+            matches.opt_present("exchange"),
+            matches.opt_present("no-clobber-atomic"),
+        ) {
+            (true, true) => {
+                eprintln!(
+                    "{:?}: --exchange and --no-clobber-atomic are mutually exclusive",
+                    &args[0]
+                );
+                process::exit(1);
+            }
+            (true, false) => Some(Atomic::Exchange),
+            (false, true) => Some(Atomic::NoReplace),
+            (false, false) => None,
+        },
+        backup: if matches.opt_present("b") || matches.opt_present("S") {
+            Some(matches.opt_str("S").unwrap_or_else(|| "~".to_string()))
+        } else {
+            None
+        },
+        update: matches.opt_present("u"),
+    };
+
+    let jobs = match plan(&matches) {
+        Ok(jobs) => jobs,
+        Err(msg) => {
+            eprintln!("{:?}: {}", &args[0], msg);
+            process::exit(1);
+        }
+    };
+
+    let mut status = 0;
+    for (src, dst) in jobs {
+        if let Err(why) = do_mv(&src, &dst, &mv_opts) {
+            eprintln!("{:?}: {:?}", src, why.to_string());
+            status = 1;
+        }
+    }
+
+    process::exit(status);
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt(
+        "t",
+        "target-directory",
+        "move all SOURCE into DIRECTORY",
+        "DIRECTORY",
+    );
+    opts.optflag("T", "no-target-directory", "treat DEST as a normal file");
+    opts.optflagmulti("f", "force", "do not prompt before overwriting");
+    opts.optflagmulti("i", "interactive", "prompt before overwrite");
+    opts.optflagmulti("n", "no-clobber", "do not overwrite an existing file");
+    opts.optflag("b", "", "make a backup of each existing destination file");
+    opts.optopt("S", "suffix", "override the usual backup suffix", "SUFFIX");
+    opts.optflag(
+        "",
+        "exchange",
+        "atomically exchange SOURCE and DEST (both must exist)",
+    );
+    opts.optflag(
+        "",
+        "no-clobber-atomic",
+        "fail instead of overwriting, checked atomically by the kernel",
+    );
+    opts.optflag(
+        "u",
+        "update",
+        "move only when the SOURCE is newer than the destination or it is missing",
+    );
+
+    opts
+}
+
+// -f, -i, -n は後に指定されたものが優先される
+fn overwrite_mode(matches: &Matches) -> Overwrite {
+    [
+        ("f", Overwrite::Force),
+        ("i", Overwrite::Interactive),
+        ("n", Overwrite::NoClobber),
+    ]
+    .iter()
+    .filter_map(|&(name, mode)| matches.opt_positions(name).last().map(|&pos| (pos, mode)))
+    .max_by_key(|&(pos, _)| pos)
+    .map(|(_, mode)| mode)
+    .unwrap_or(Overwrite::Force)
+}
+
+// Turn the operands into (source, destination) pairs.
+// --exchange swaps the two operands themselves, so it implies -T.
+fn plan(matches: &Matches) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let free = &matches.free;
+    let no_target = matches.opt_present("T") || matches.opt_present("exchange");
+
+    if let Some(dir) = matches.opt_str("t") {
+        if matches.opt_present("T") {
+            return Err(
+                "cannot combine --target-directory (-t) and --no-target-directory (-T)".to_string(),
+            );
+        }
+        if free.is_empty() {
+            return Err("missing file operand".to_string());
+        }
+        if !Path::new(&dir).is_dir() {
+            return Err(format!("target {:?} is not a directory", dir));
+        }
+        return free.iter().map(|src| into_dir(src, &dir)).collect();
+    }
+
+    match free.len() {
+        0 => Err("missing file operand".to_string()),
+        1 => Err(format!(
+            "missing destination file operand after {:?}",
+            free[0]
+        )),
+        2 if no_target || !Path::new(&free[1]).is_dir() => {
+            Ok(vec![(PathBuf::from(&free[0]), PathBuf::from(&free[1]))])
+        }
+        _ if no_target => Err(format!("extra operand {:?}", free[2])),
+        n => {
+            let dir = &free[n - 1];
+            if !Path::new(dir).is_dir() {
+                return Err(format!("target {:?} is not a directory", dir));
+            }
+            free[..n - 1].iter().map(|src| into_dir(src, dir)).collect()
+        }
+    }
+}
+
+fn into_dir(src: &str, dir: &str) -> Result<(PathBuf, PathBuf), String> {
+    match Path::new(src).file_name() {
+        Some(name) => Ok((PathBuf::from(src), Path::new(dir).join(name))),
+        None => Err(format!("cannot move {:?} into {:?}", src, dir)),
+    }
+}
+
+fn do_mv(src: &Path, dst: &Path, opts: &MvOptions) -> io::Result<()> {
+    // the kernel decides about the destination, so skip every check below
+    if let Some(atomic) = opts.atomic {
+        return rename_atomic(src, dst, atomic);
+    }
+
+    let src_st = fs::symlink_metadata(src)?;
+
+    if let Ok(dst_st) = fs::symlink_metadata(dst) {
+        if src_st.dev() == dst_st.dev() && src_st.ino() == dst_st.ino() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} and {:?} are the same file", src, dst),
+            ));
+        }
+        if opts.update
+            && (dst_st.mtime(), dst_st.mtime_nsec()) >= (src_st.mtime(), src_st.mtime_nsec())
+        {
+            return Ok(());
+        }
+        match opts.overwrite {
+            Overwrite::NoClobber => return Ok(()),
+            Overwrite::Interactive if !confirm(dst)? => return Ok(()),
+            _ => {}
+        }
+        if let Some(ref suffix) = opts.backup {
+            let mut backup = OsString::from(dst.as_os_str());
+            backup.push(suffix);
+            fs::rename(dst, backup)?;
+        }
+    }
+
+    match fs::rename(src, dst) {
+        Err(ref why) if why.raw_os_error() == Some(libc::EXDEV) => move_across(src, dst),
+        result => result,
+    }
+}
+
+fn rename_atomic(src: &Path, dst: &Path, atomic: Atomic) -> io::Result<()> {
+    let c_src = cstring(src)?;
+    let c_dst = cstring(dst)?;
+    let (flags, name) = match atomic {
+        Atomic::Exchange => (libc::RENAME_EXCHANGE, "RENAME_EXCHANGE"),
+        Atomic::NoReplace => (libc::RENAME_NOREPLACE, "RENAME_NOREPLACE"),
+    };
+
+    let res = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            c_src.as_ptr(),
+            libc::AT_FDCWD,
+            c_dst.as_ptr(),
+            flags,
+        )
+    };
+    if res == 0 {
+        return Ok(());
+    }
+
+    let why = io::Error::last_os_error();
+    match why.raw_os_error() {
+        // old kernels lack the syscall, and filesystems without support
+        // reject the flag with EINVAL
+        Some(libc::ENOSYS) => Err(io::Error::new(
+            why.kind(),
+            format!("renameat2 is not supported by this kernel ({})", why),
+        )),
+        Some(libc::EINVAL) => Err(io::Error::new(
+            why.kind(),
+            format!("{} is not supported on this filesystem ({})", name, why),
+        )),
+        Some(libc::EXDEV) => Err(io::Error::new(
+            why.kind(),
+            format!("cannot {} across filesystems ({})", name, why),
+        )),
+        Some(libc::EEXIST) => Err(io::Error::new(
+            why.kind(),
+            format!("{:?} already exists", dst),
+        )),
+        _ => Err(why),
+    }
+}
+
+fn confirm(dst: &Path) -> io::Result<bool> {
+    eprint!("mv: overwrite {:?}? ", dst);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(answer.trim_start().starts_with(['y', 'Y']))
+}
+
+// rename(2) cannot cross mount points: copy everything under a temporary
+// name next to the destination, rename that over it, then unlink the source.
+// If the copy fails, the destination is left as it was.
+fn move_across(src: &Path, dst: &Path) -> io::Result<()> {
+    let src_st = fs::symlink_metadata(src)?;
+
+    if let Ok(dst_st) = fs::symlink_metadata(dst) {
+        // keep the same rules rename(2) applies to the destination
+        match (src_st.is_dir(), dst_st.is_dir()) {
+            (true, false) => return Err(io::Error::from_raw_os_error(libc::ENOTDIR)),
+            (false, true) => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+            _ => {}
+        }
+    }
+
+    let tmp = copy_to_temporary(src, dst)?;
+    if let Err(why) = fs::rename(&tmp, dst) {
+        remove_tree(&tmp).ok();
+        return Err(why);
+    }
+
+    remove_tree(src)
+}
+
+// Copy `src` to an unused name in the directory of `dst`.
+fn copy_to_temporary(src: &Path, dst: &Path) -> io::Result<PathBuf> {
+    let dir = match dst.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let name = dst.file_name().unwrap_or(dst.as_os_str());
+
+    for n in 0.. {
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(name);
+        tmp_name.push(format!(".mv-{}-{}", process::id(), n));
+        let tmp = dir.join(tmp_name);
+        if fs::symlink_metadata(&tmp).is_ok() {
+            continue;
+        }
+
+        return match copy_tree(src, &tmp) {
+            Ok(()) => Ok(tmp),
+            Err(why) => {
+                // the partial copy, if copy_tree got as far as creating it
+                if fs::symlink_metadata(&tmp).is_ok() {
+                    remove_tree(&tmp).ok();
+                }
+                Err(why)
+            }
+        };
+    }
+    unreachable!()
+}
+
+fn remove_tree(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    let st = fs::symlink_metadata(src)?;
+    let file_type = st.file_type();
+
+    if file_type.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else if file_type.is_symlink() {
+        symlink(fs::read_link(src)?, dst)?;
+    } else if file_type.is_file() {
+        let mut reader = fs::File::open(src)?;
+        let mut writer = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dst)?;
+        io::copy(&mut reader, &mut writer)?;
+    } else {
+        let kind = if file_type.is_fifo() {
+            SFlag::S_IFIFO
+        } else if file_type.is_char_device() {
+            SFlag::S_IFCHR
+        } else if file_type.is_block_device() {
+            SFlag::S_IFBLK
+        } else {
+            SFlag::S_IFSOCK
+        };
+        mknod(dst, kind, Mode::from_bits_truncate(st.mode()), st.rdev()).map_err(nix_to_io)?;
+    }
 
-    if args.len() != 3 {
-        eprintln!("{:?}: wrong arguments", &args[0]);
-        process::exit(1);
+    copy_attributes(src, &st, dst)
+}
+
+// chown はセットユーザIDビットを落とすので chmod より先に行う
+fn copy_attributes(src: &Path, st: &fs::Metadata, dst: &Path) -> io::Result<()> {
+    // only root may give a file away; keep the copy owned by us then
+    match fchownat(
+        None,
+        dst,
+        Some(Uid::from_raw(st.uid())),
+        Some(Gid::from_raw(st.gid())),
+        FchownatFlags::NoFollowSymlink,
+    ) {
+        Err(nix::Error::Sys(nix::errno::Errno::EPERM)) => {}
+        result => result.map_err(nix_to_io)?,
     }
 
-    if let Err(why) = fs::rename(&args[1], &args[2]) {
-        eprintln!("{:?}: {:?}", &args[1], why.to_string());
+    if !st.file_type().is_symlink() {
+        fs::set_permissions(dst, fs::Permissions::from_mode(st.mode() & 0o7777))?;
+    }
+
+    copy_xattrs(src, dst)?;
+
+    let atime = TimeSpec::from(libc::timespec {
+        tv_sec: st.atime(),
+        tv_nsec: st.atime_nsec(),
+    });
+    let mtime = TimeSpec::from(libc::timespec {
+        tv_sec: st.mtime(),
+        tv_nsec: st.mtime_nsec(),
+    });
+    utimensat(None, dst, &atime, &mtime, UtimensatFlags::NoFollowSymlink).map_err(nix_to_io)
+}
+
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    let c_src = cstring(src)?;
+    let c_dst = cstring(dst)?;
+
+    let size = unsafe { libc::llistxattr(c_src.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return match io::Error::last_os_error() {
+            why if why.raw_os_error() == Some(libc::ENOTSUP) => Ok(()),
+            why => Err(why),
+        };
+    }
+    let mut names = vec![0u8; size as usize];
+    let size =
+        unsafe { libc::llistxattr(c_src.as_ptr(), names.as_mut_ptr() as *mut _, names.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(size as usize);
+
+    // names are NUL terminated and packed back to back
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let c_name = CString::new(name).unwrap();
+
+        let size =
+            unsafe { libc::lgetxattr(c_src.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut value = vec![0u8; size as usize];
+        let size = unsafe {
+            libc::lgetxattr(
+                c_src.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr() as *mut _,
+                value.len(),
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let res = unsafe {
+            libc::lsetxattr(
+                c_dst.as_ptr(),
+                c_name.as_ptr(),
+                value.as_ptr() as *const _,
+                size as usize,
+                0,
+            )
+        };
+        if res < 0 {
+            // the destination filesystem may not support xattrs, and the
+            // trusted./security. namespaces are not writable by everyone
+            match io::Error::last_os_error() {
+                why if why.raw_os_error() == Some(libc::ENOTSUP)
+                    || why.raw_os_error() == Some(libc::EPERM) => {}
+                why => return Err(why),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn nix_to_io(why: nix::Error) -> io::Error {
+    match why.as_errno() {
+        Some(errno) => io::Error::from(errno),
+        None => io::Error::other(why.to_string()),
     }
+}
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("mv-test-{}-{}", name, process::id()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn plan_args(args: &[&str]) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+        plan(&options().parse(args).unwrap())
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(PathBuf, PathBuf)> {
+        pairs
+            .iter()
+            .map(|(src, dst)| (PathBuf::from(src), PathBuf::from(dst)))
+            .collect()
+    }
+
+    #[test]
+    fn plan_operands() {
+        let tmp = TempDir::new("plan");
+        fs::create_dir(tmp.path("dir")).unwrap();
+        let (a, b, dir, file) = (
+            tmp.path("a"),
+            tmp.path("b"),
+            tmp.path("dir"),
+            tmp.path("file"),
+        );
+        fs::write(&file, b"").unwrap();
+        let in_dir = |name: &str| format!("{}/{}", dir, name);
+
+        // a file, or a directory to move into
+        assert_eq!(plan_args(&[&a, &file]).unwrap(), pairs(&[(&a, &file)]));
+        assert_eq!(
+            plan_args(&[&a, &dir]).unwrap(),
+            pairs(&[(&a, &in_dir("a"))])
+        );
+        assert_eq!(
+            plan_args(&[&a, &b, &dir]).unwrap(),
+            pairs(&[(&a, &in_dir("a")), (&b, &in_dir("b"))])
+        );
+        assert!(plan_args(&[&a, &b, &file])
+            .unwrap_err()
+            .contains("not a directory"));
+        assert!(plan_args(&[&a, &b, &tmp.path("none")]).is_err());
 
-    process::exit(0);
+        // -t takes the directory first
+        assert_eq!(
+            plan_args(&["-t", &dir, &a, &b]).unwrap(),
+            pairs(&[(&a, &in_dir("a")), (&b, &in_dir("b"))])
+        );
+        assert!(plan_args(&["-t", &file, &a])
+            .unwrap_err()
+            .contains("not a directory"));
+        assert!(plan_args(&["-t", &dir])
+            .unwrap_err()
+            .contains("missing file operand"));
+        assert!(plan_args(&["-t", &dir, "-T", &a]).is_err());
+
+        // -T and --exchange never move into the directory
+        assert_eq!(plan_args(&["-T", &a, &dir]).unwrap(), pairs(&[(&a, &dir)]));
+        assert_eq!(
+            plan_args(&["--exchange", &a, &dir]).unwrap(),
+            pairs(&[(&a, &dir)])
+        );
+        assert!(plan_args(&["-T", &a, &b, &dir])
+            .unwrap_err()
+            .contains("extra operand"));
+
+        assert!(plan_args(&[]).unwrap_err().contains("missing file operand"));
+        assert!(plan_args(&[&a])
+            .unwrap_err()
+            .contains("missing destination"));
+        assert!(plan_args(&["/", &dir]).unwrap_err().contains("cannot move"));
+    }
+
+    fn set_times(path: &str, secs: i64) {
+        let time = TimeSpec::from(libc::timespec {
+            tv_sec: secs,
+            tv_nsec: 500,
+        });
+        utimensat(None, path, &time, &time, UtimensatFlags::NoFollowSymlink).unwrap();
+    }
+
+    fn set_xattr(path: &str, value: &[u8]) -> bool {
+        let c_path = CString::new(path).unwrap();
+        let res = unsafe {
+            libc::lsetxattr(
+                c_path.as_ptr(),
+                b"user.mv-test\0".as_ptr() as *const _,
+                value.as_ptr() as *const _,
+                value.len(),
+                0,
+            )
+        };
+        res == 0
+    }
+
+    fn get_xattr(path: &str) -> Vec<u8> {
+        let c_path = CString::new(path).unwrap();
+        let mut value = vec![0u8; 64];
+        let size = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                b"user.mv-test\0".as_ptr() as *const _,
+                value.as_mut_ptr() as *mut _,
+                value.len(),
+            )
+        };
+        value.truncate(size.max(0) as usize);
+        value
+    }
+
+    // move_across is what an EXDEV from rename(2) falls back on; it works
+    // within one filesystem as well
+    #[test]
+    fn move_across_keeps_attributes() {
+        let tmp = TempDir::new("across");
+        let src = tmp.path("src");
+        fs::create_dir_all(format!("{}/sub", src)).unwrap();
+        fs::write(format!("{}/sub/file", src), b"data").unwrap();
+        symlink("sub/file", format!("{}/link", src)).unwrap();
+        fs::set_permissions(
+            format!("{}/sub/file", src),
+            fs::Permissions::from_mode(0o4751),
+        )
+        .unwrap();
+        fs::set_permissions(format!("{}/sub", src), fs::Permissions::from_mode(0o700)).unwrap();
+        // some filesystems have no user xattrs
+        let has_xattrs = set_xattr(&format!("{}/sub/file", src), b"value");
+        set_times(&format!("{}/sub/file", src), 1_000_000);
+        set_times(&format!("{}/link", src), 2_000_000);
+        set_times(&format!("{}/sub", src), 3_000_000);
+
+        let dst = tmp.path("dst");
+        move_across(Path::new(&src), Path::new(&dst)).unwrap();
+        assert!(fs::symlink_metadata(&src).is_err());
+
+        let file = format!("{}/sub/file", dst);
+        // before reading it, which may update the access time
+        let st = fs::metadata(&file).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"data");
+        assert_eq!(st.mode() & 0o7777, 0o4751);
+        assert_eq!((st.mtime(), st.mtime_nsec()), (1_000_000, 500));
+        assert_eq!((st.atime(), st.atime_nsec()), (1_000_000, 500));
+        if has_xattrs {
+            assert_eq!(get_xattr(&file), b"value");
+        }
+
+        let link = format!("{}/link", dst);
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("sub/file"));
+        assert_eq!(fs::symlink_metadata(&link).unwrap().mtime(), 2_000_000);
+
+        // the directory's times are set after its entries are copied
+        let st = fs::metadata(format!("{}/sub", dst)).unwrap();
+        assert_eq!(st.mode() & 0o7777, 0o700);
+        assert_eq!(st.mtime(), 3_000_000);
+
+        // nothing of the temporary copy is left behind
+        let names: Vec<OsString> = fs::read_dir(&tmp.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, [OsString::from("dst")]);
+    }
+
+    #[test]
+    fn move_across_replaces_like_rename() {
+        let tmp = TempDir::new("replace");
+        let (file, dir, old) = (tmp.path("file"), tmp.path("dir"), tmp.path("old"));
+        fs::write(&file, b"new").unwrap();
+        fs::write(&old, b"old").unwrap();
+        fs::create_dir(&dir).unwrap();
+
+        let error = |src: &str, dst: &str| {
+            move_across(Path::new(src), Path::new(dst))
+                .unwrap_err()
+                .raw_os_error()
+        };
+        assert_eq!(error(&file, &dir), Some(libc::EISDIR));
+        assert_eq!(error(&dir, &file), Some(libc::ENOTDIR));
+        assert_eq!(error(&tmp.path("none"), &old), Some(libc::ENOENT));
+        assert_eq!(fs::read(&old).unwrap(), b"old");
+
+        move_across(Path::new(&file), Path::new(&old)).unwrap();
+        assert_eq!(fs::read(&old).unwrap(), b"new");
+        assert!(fs::symlink_metadata(&file).is_err());
+
+        // a failed copy leaves no temporary file
+        assert!(copy_to_temporary(Path::new(&tmp.path("none")), Path::new(&old)).is_err());
+        assert_eq!(fs::read_dir(&tmp.0).unwrap().count(), 2);
+    }
+
+    fn mv_options(overwrite: Overwrite, atomic: Option<Atomic>) -> MvOptions {
+        MvOptions {
+            overwrite,
+            atomic,
+            backup: None,
+            update: false,
+        }
+    }
+
+    #[test]
+    fn exchange() {
+        let tmp = TempDir::new("exchange");
+        let (a, dir) = (tmp.path("a"), tmp.path("dir"));
+        fs::write(&a, b"a").unwrap();
+        fs::create_dir(&dir).unwrap();
+        fs::write(format!("{}/in", dir), b"in").unwrap();
+
+        let opts = mv_options(Overwrite::Force, Some(Atomic::Exchange));
+        match do_mv(Path::new(&a), Path::new(&dir), &opts) {
+            Err(ref why) if why.raw_os_error() == Some(libc::EINVAL) => {
+                eprintln!("skipped: RENAME_EXCHANGE is not supported here");
+                return;
+            }
+            result => result.unwrap(),
+        }
+        // even a file and a directory trade places
+        assert_eq!(fs::read(&dir).unwrap(), b"a");
+        assert_eq!(fs::read(format!("{}/in", a)).unwrap(), b"in");
+
+        // both must exist
+        let error = do_mv(Path::new(&a), Path::new(&tmp.path("none")), &opts).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ENOENT));
+        assert!(fs::metadata(&a).unwrap().is_dir());
+    }
+
+    #[test]
+    fn no_clobber() {
+        let tmp = TempDir::new("noclobber");
+        let (a, b, c) = (tmp.path("a"), tmp.path("b"), tmp.path("c"));
+        fs::write(&a, b"a").unwrap();
+        fs::write(&b, b"b").unwrap();
+
+        // -n quietly leaves both alone
+        do_mv(
+            Path::new(&a),
+            Path::new(&b),
+            &mv_options(Overwrite::NoClobber, None),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&a).unwrap(), b"a");
+        assert_eq!(fs::read(&b).unwrap(), b"b");
+
+        // the kernel refuses with EEXIST
+        let opts = mv_options(Overwrite::Force, Some(Atomic::NoReplace));
+        let error = match do_mv(Path::new(&a), Path::new(&b), &opts) {
+            Err(ref why) if why.raw_os_error() == Some(libc::EINVAL) => {
+                eprintln!("skipped: RENAME_NOREPLACE is not supported here");
+                return;
+            }
+            result => result.unwrap_err(),
+        };
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert!(error.to_string().contains("already exists"));
+        assert_eq!(fs::read(&a).unwrap(), b"a");
+        assert_eq!(fs::read(&b).unwrap(), b"b");
+
+        // and renames when the name is free
+        do_mv(Path::new(&a), Path::new(&c), &opts).unwrap();
+        assert!(fs::symlink_metadata(&a).is_err());
+        assert_eq!(fs::read(&c).unwrap(), b"a");
+    }
 }
@@ -0,0 +1,93 @@
+use getopts::Options;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+mod resolve;
+
+use resolve::Missing;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflagmulti(
+        "f",
+        "canonicalize",
+        "canonicalize; every component but the last must exist",
+    );
+    opts.optflagmulti(
+        "e",
+        "canonicalize-existing",
+        "canonicalize; every component must exist",
+    );
+    opts.optflagmulti(
+        "m",
+        "canonicalize-missing",
+        "canonicalize without requirements on components existence",
+    );
+    opts.optflag("n", "no-newline", "do not output the trailing delimiter");
+    opts.optflag("v", "verbose", "report error messages");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!("Usage: {:?} [-f | -e | -m] [-nv] FILE...", &args[0]);
+        process::exit(0);
+    }
+
+    if matches.free.is_empty() {
+        eprintln!("{:?}: no arguments", &args[0]);
+        process::exit(1);
+    }
+
+    // -f, -e, -m は後に指定されたものが優先される
+    let missing = [
+        ("f", Missing::LastOnly),
+        ("e", Missing::Forbidden),
+        ("m", Missing::Allowed),
+    ]
+    .iter()
+    .filter_map(|&(name, mode)| matches.opt_positions(name).last().map(|&pos| (pos, mode)))
+    .max_by_key(|&(pos, _)| pos)
+    .map(|(_, mode)| mode);
+
+    let newline = !matches.opt_present("n") || matches.free.len() > 1;
+
+    let mut status = 0;
+    for path in &matches.free {
+        match do_readlink(Path::new(path), missing) {
+            Ok(resolved) => {
+                print!("{}", resolved.display());
+                if newline {
+                    println!();
+                }
+            }
+            Err(why) => {
+                // like coreutils, stay silent unless asked
+                if matches.opt_present("v") {
+                    eprintln!("{:?}: {:?}", path, why.to_string());
+                }
+                status = 1;
+            }
+        }
+    }
+
+    process::exit(status);
+}
+
+fn do_readlink(path: &Path, missing: Option<Missing>) -> io::Result<PathBuf> {
+    match missing {
+        Some(missing) => resolve::canonicalize(path, missing),
+        None => fs::read_link(path),
+    }
+}
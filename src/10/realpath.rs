@@ -0,0 +1,125 @@
+use getopts::Options;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+mod resolve;
+
+use resolve::Missing;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflagmulti("e", "canonicalize-existing", "all components must exist");
+    opts.optflagmulti("m", "canonicalize-missing", "no path components need exist");
+    opts.optflag(
+        "s",
+        "no-symlinks",
+        "don't expand symlinks, only remove '.' and '..'",
+    );
+    opts.optopt(
+        "",
+        "relative-to",
+        "print the resolved path relative to DIR",
+        "DIR",
+    );
+    opts.optopt(
+        "",
+        "relative-base",
+        "print absolute paths unless paths below DIR",
+        "DIR",
+    );
+    opts.optflag("q", "quiet", "suppress most error messages");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!(
+            "Usage: {:?} [-e | -m] [-sq] [--relative-to=DIR] [--relative-base=DIR] FILE...",
+            &args[0]
+        );
+        process::exit(0);
+    }
+
+    if matches.free.is_empty() {
+        eprintln!("{:?}: no arguments", &args[0]);
+        process::exit(1);
+    }
+
+    // -e と -m は後に指定されたものが優先される
+    let missing = match (
+        matches.opt_positions("e").last(),
+        matches.opt_positions("m").last(),
+    ) {
+        (Some(e), Some(m)) if e > m => Missing::Forbidden,
+        (Some(_), None) => Missing::Forbidden,
+        (_, Some(_)) => Missing::Allowed,
+        (None, None) => Missing::LastOnly,
+    };
+    let resolve = |path: &Path| -> io::Result<PathBuf> {
+        if matches.opt_present("s") {
+            resolve::normalize(path)
+        } else {
+            resolve::canonicalize(path, missing)
+        }
+    };
+
+    let mut dirs = Vec::new();
+    for name in &["relative-to", "relative-base"] {
+        dirs.push(match matches.opt_str(name) {
+            Some(dir) => match resolve(Path::new(&dir)) {
+                Ok(dir) => Some(dir),
+                Err(why) => {
+                    eprintln!("{:?}: {:?}", dir, why.to_string());
+                    process::exit(1);
+                }
+            },
+            None => None,
+        });
+    }
+    let (relative_to, relative_base) = (dirs[0].take(), dirs[1].take());
+
+    let mut status = 0;
+    for path in &matches.free {
+        match resolve(Path::new(path)) {
+            Ok(resolved) => println!(
+                "{}",
+                relative(&resolved, &relative_to, &relative_base).display()
+            ),
+            Err(why) => {
+                if !matches.opt_present("q") {
+                    eprintln!("{:?}: {:?}", path, why.to_string());
+                }
+                status = 1;
+            }
+        }
+    }
+
+    process::exit(status);
+}
+
+// --relative-base limits --relative-to: a path is only made relative when
+// both it and the --relative-to directory lie below the base.
+fn relative(path: &Path, to: &Option<PathBuf>, base: &Option<PathBuf>) -> PathBuf {
+    match (to, base) {
+        (None, None) => path.to_path_buf(),
+        (Some(to), None) => resolve::relative_to(path, to),
+        (to, Some(base)) => {
+            let to = to.as_ref().unwrap_or(base);
+            if path.starts_with(base) && to.starts_with(base) {
+                resolve::relative_to(path, to)
+            } else {
+                path.to_path_buf()
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// シンボリックリンクを1要素ずつ解決するパス正規化. readlink, realpath, ln -r で共有する.
+//
+// Not every binary uses every item in this module.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+// same limit as the kernel's MAXSYMLINKS
+const MAX_SYMLINKS: usize = 40;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Missing {
+    // every component must exist (readlink -e, realpath -e)
+    Forbidden,
+    // only the last component may be missing (readlink -f, realpath)
+    LastOnly,
+    // no component needs to exist (readlink -m, realpath -m)
+    Allowed,
+}
+
+// Make `path` absolute, resolving every symlink on the way.
+pub fn canonicalize(path: &Path, missing: Missing) -> io::Result<PathBuf> {
+    let mut result = start_of(path)?;
+    let mut rest: VecDeque<OsString> = names_of(path);
+    let mut followed = 0;
+    // once a component is missing nothing below it can be a symlink
+    let mut exists = true;
+
+    while let Some(name) = rest.pop_front() {
+        if name == "." {
+            continue;
+        }
+        if name == ".." {
+            result.pop();
+            continue;
+        }
+        result.push(&name);
+        if !exists {
+            continue;
+        }
+
+        match fs::symlink_metadata(&result) {
+            Ok(st) if st.file_type().is_symlink() => {
+                followed += 1;
+                if followed > MAX_SYMLINKS {
+                    return Err(io::Error::from_raw_os_error(libc::ELOOP));
+                }
+
+                let target = fs::read_link(&result)?;
+                result.pop();
+                if target.is_absolute() {
+                    result = PathBuf::from("/");
+                }
+                for name in names_of(&target).into_iter().rev() {
+                    rest.push_front(name);
+                }
+            }
+            Ok(st) => {
+                if !st.is_dir() && rest.iter().any(|name| name != ".") {
+                    if missing != Missing::Allowed {
+                        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+                    }
+                    exists = false;
+                }
+            }
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => match missing {
+                Missing::Forbidden => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+                Missing::LastOnly if !rest.is_empty() => {
+                    return Err(io::Error::from_raw_os_error(libc::ENOENT))
+                }
+                _ => exists = false,
+            },
+            Err(why) => return Err(why),
+        }
+    }
+
+    Ok(result)
+}
+
+// Make `path` absolute and drop `.`/`..` without looking at the filesystem.
+pub fn normalize(path: &Path) -> io::Result<PathBuf> {
+    let mut result = start_of(path)?;
+    for name in names_of(path) {
+        if name == ".." {
+            result.pop();
+        } else if name != "." {
+            result.push(name);
+        }
+    }
+
+    Ok(result)
+}
+
+// `path` as seen from the directory `base`; both must be absolute.
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component.as_os_str());
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+
+    relative
+}
+
+fn start_of(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(PathBuf::from("/"))
+    } else {
+        env::current_dir()
+    }
+}
+
+fn names_of(path: &Path) -> VecDeque<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            Component::CurDir => Some(OsString::from(".")),
+            Component::RootDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn errno(result: io::Result<PathBuf>) -> Option<i32> {
+        result.err().and_then(|why| why.raw_os_error())
+    }
+
+    #[test]
+    fn canonicalize_follows_links() {
+        let base = env::temp_dir().join(format!("resolve-test-{}", std::process::id()));
+        fs::create_dir_all(base.join("dir/sub")).unwrap();
+        // the temporary directory may itself be behind a symlink
+        let base = fs::canonicalize(&base).unwrap();
+        fs::write(base.join("dir/file"), b"").unwrap();
+        symlink("dir/sub", base.join("rel")).unwrap();
+        symlink(base.join("dir"), base.join("abs")).unwrap();
+        symlink("../file", base.join("dir/sub/up")).unwrap();
+        symlink("loop2", base.join("loop1")).unwrap();
+        symlink("loop1", base.join("loop2")).unwrap();
+
+        let resolve = |path: &str, missing| canonicalize(&base.join(path), missing);
+        assert_eq!(
+            resolve("rel/up", Missing::Forbidden).unwrap(),
+            base.join("dir/file")
+        );
+        assert_eq!(
+            resolve("abs/./sub/..", Missing::Forbidden).unwrap(),
+            base.join("dir")
+        );
+        // .. applies to where the link points, not to the link
+        assert_eq!(
+            resolve("rel/..", Missing::Forbidden).unwrap(),
+            base.join("dir")
+        );
+        assert_eq!(errno(resolve("loop1", Missing::Allowed)), Some(libc::ELOOP));
+        assert_eq!(
+            errno(resolve("loop1/x", Missing::LastOnly)),
+            Some(libc::ELOOP)
+        );
+
+        // a missing last component
+        assert_eq!(
+            errno(resolve("dir/none", Missing::Forbidden)),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            resolve("rel/none", Missing::LastOnly).unwrap(),
+            base.join("dir/sub/none")
+        );
+        assert_eq!(
+            resolve("rel/none", Missing::Allowed).unwrap(),
+            base.join("dir/sub/none")
+        );
+        // a missing component in the middle
+        assert_eq!(
+            errno(resolve("none/x", Missing::Forbidden)),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            errno(resolve("none/x", Missing::LastOnly)),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            resolve("none/../abs/x", Missing::Allowed).unwrap(),
+            base.join("abs/x")
+        );
+        // a file in the middle
+        assert_eq!(
+            errno(resolve("dir/file/x", Missing::LastOnly)),
+            Some(libc::ENOTDIR)
+        );
+        assert_eq!(
+            resolve("dir/file/x", Missing::Allowed).unwrap(),
+            base.join("dir/file/x")
+        );
+        assert_eq!(
+            resolve("dir/file/.", Missing::Forbidden).unwrap(),
+            base.join("dir/file")
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn relative_paths() {
+        let relative = |path: &str, base: &str| relative_to(Path::new(path), Path::new(base));
+        assert_eq!(relative("/a/b/c", "/a/b"), Path::new("c"));
+        assert_eq!(relative("/a/b", "/a/b/c/d"), Path::new("../.."));
+        assert_eq!(relative("/a/x/y", "/a/b/c"), Path::new("../../x/y"));
+        assert_eq!(relative("/x", "/a/b"), Path::new("../../x"));
+        assert_eq!(relative("/a/b", "/a/b"), Path::new("."));
+        assert_eq!(relative("/", "/a"), Path::new(".."));
+        assert_eq!(relative("/a", "/"), Path::new("a"));
+        // a common prefix of a name is not a common directory
+        assert_eq!(relative("/ab/c", "/a"), Path::new("../ab/c"));
+    }
+}
@@ -1,19 +1,60 @@
+use getopts::Options;
 use std::env;
 use std::fs;
+use std::path::Path;
 use std::process;
 
+mod xdg_trash;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag(
+        "",
+        "trash",
+        "move files to the trash instead of removing them",
+    );
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!("Usage: {:?} [--trash] FILE...", &args[0]);
+        process::exit(0);
+    }
+
+    if matches.free.is_empty() {
         eprintln!("{:?}: no arguments", &args[0]);
         process::exit(1);
     }
 
-    for i in 1..args.len() {
-        if let Err(why) = fs::remove_file(&args[i]) {
-            eprintln!("{:?}: {:?}", &args[1], why.to_string());
+    let opt_trash = matches.opt_present("trash");
+
+    for path in &matches.free {
+        if opt_trash {
+            if let Err(why) = do_trash(path) {
+                eprintln!("{:?}: {:?}", path, why.to_string());
+            }
+        } else if let Err(why) = fs::remove_file(path) {
+            eprintln!("{:?}: {:?}", path, why.to_string());
         }
     }
     process::exit(0);
 }
+
+// rm --trash は rm と同じくディレクトリを対象にしない
+fn do_trash(path: &str) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        return Err(std::io::Error::from_raw_os_error(libc::EISDIR));
+    }
+    xdg_trash::trash_path(Path::new(path))?;
+
+    Ok(())
+}
@@ -1,23 +1,705 @@
+use chrono::{Local, TimeZone};
+use getopts::Options;
+use nix::sys::stat::{major, minor};
+use nix::unistd::{Gid, Uid};
 use std::env;
+use std::ffi::CString;
 use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
 use std::process;
 
+#[path = "../14/ids.rs"]
+mod ids;
+mod mode;
+
+// same layout as coreutils
+const FILE_FORMAT: &str = "  File: %N\n  Size: %-10s\tBlocks: %-10b IO Block: %-6o %F\nDevice: %Hd,%Ld\tInode: %-10i  Links: %h\nAccess: (%04a/%10.10A)  Uid: (%5u/%8U)   Gid: (%5g/%8G)\nAccess: %x\nModify: %y\nChange: %z\n Birth: %w\n";
+const DEVICE_FORMAT: &str = "  File: %N\n  Size: %-10s\tBlocks: %-10b IO Block: %-6o %F\nDevice: %Hd,%Ld\tInode: %-10i  Links: %-5h Device type: %Hr,%Lr\nAccess: (%04a/%10.10A)  Uid: (%5u/%8U)   Gid: (%5g/%8G)\nAccess: %x\nModify: %y\nChange: %z\n Birth: %w\n";
+const EXTENDED_FORMAT: &str = " Mount: %-10M Attributes: %e\n";
+const FS_FORMAT: &str = "  File: \"%n\"\n    ID: %-8i Namelen: %-7l Type: %T\nBlock size: %-10s Fundamental block size: %S\nBlocks: Total: %-10b Free: %-10f Available: %a\nInodes: Total: %-10c Free: %d\n";
+
+struct FileInfo {
+    dev: u64,
+    ino: u64,
+    mode: u32,
+    nlink: u64,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+    size: u64,
+    blksize: u64,
+    blocks: u64,
+    atime: (i64, i64),
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+    // only known through statx(2), and only on filesystems that record them
+    btime: Option<(i64, i64)>,
+    mnt_id: Option<u64>,
+    // (stx_attributes, stx_attributes_mask)
+    attributes: Option<(u64, u64)>,
+}
+
+struct FsInfo {
+    fsid: u64,
+    fs_type: i64,
+    namelen: i64,
+    bsize: i64,
+    frsize: i64,
+    blocks: u64,
+    bfree: u64,
+    bavail: u64,
+    files: u64,
+    ffree: u64,
+}
+
+enum Value {
+    Num(i128),
+    Str(String),
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        eprintln!("{:?}: wrong arguments", &args[0]);
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("L", "dereference", "follow links");
+    opts.optflag(
+        "f",
+        "file-system",
+        "display file system status instead of file status",
+    );
+    opts.optflag(
+        "x",
+        "extended",
+        "also show the mount id and file attributes reported by statx",
+    );
+    opts.optopt(
+        "c",
+        "format",
+        "use the specified FORMAT instead of the default; output a newline after each use of FORMAT",
+        "FORMAT",
+    );
+    opts.optopt(
+        "",
+        "printf",
+        "like --format, but interpret backslash escapes, and do not output a mandatory trailing newline",
+        "FORMAT",
+    );
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!(
+            "Usage: {:?} [-Lfx] [-c FORMAT | --printf FORMAT] FILE...",
+            &args[0]
+        );
+        process::exit(0);
+    }
+
+    if matches.free.is_empty() {
+        eprintln!("{:?}: missing operand", &args[0]);
         process::exit(1);
     }
 
-    let st = match fs::metadata(&args[1]) {
-        Ok(st) => st,
-        Err(why) => {
-            eprintln!("{:?}: {:?}", &args[1], why.to_string());
-            process::exit(1);
+    let format = match (matches.opt_str("c"), matches.opt_str("printf")) {
+        (_, Some(format)) => Some(unescape(&format)),
+        (Some(format), None) => Some((format + "\n").into_bytes()),
+        (None, None) => None,
+    };
+    // %N in a format given by the user is always quoted; the default
+    // display only quotes names that need it
+    let always_quote = format.is_some();
+    let dereference = matches.opt_present("L");
+    let file_format = |mode: u32| {
+        let mut format = String::from(if is_device(mode) {
+            DEVICE_FORMAT
+        } else {
+            FILE_FORMAT
+        });
+        if matches.opt_present("x") {
+            format.push_str(EXTENDED_FORMAT);
+        }
+        format
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut status = 0;
+    for path in &matches.free {
+        let result = if matches.opt_present("f") {
+            fs_info(Path::new(path)).map(|info| {
+                let format = format.as_deref().unwrap_or(FS_FORMAT.as_bytes());
+                render(format, |conv| fs_field(conv, path, always_quote, &info))
+            })
+        } else {
+            file_info(Path::new(path), dereference).map(|info| {
+                let format = format
+                    .clone()
+                    .unwrap_or_else(|| file_format(info.mode).into_bytes());
+                render(&format, |conv| {
+                    file_field(conv, path, dereference, always_quote, &info)
+                })
+            })
+        };
+
+        match result {
+            Ok(text) => out
+                .write_all(&text)
+                .unwrap_or_else(|why| panic!("error while writing: {}", why)),
+            Err(why) => {
+                eprintln!("{:?}: {:?}", path, why.to_string());
+                status = 1;
+            }
+        }
+    }
+    out.flush()
+        .unwrap_or_else(|why| panic!("error while writing: {}", why));
+
+    process::exit(status);
+}
+
+fn file_info(path: &Path, dereference: bool) -> io::Result<FileInfo> {
+    match statx(path, dereference) {
+        // kernels before 4.11 do not have statx(2)
+        Err(ref why) if why.raw_os_error() == Some(libc::ENOSYS) => {}
+        result => return result,
+    }
+
+    let st = if dereference {
+        fs::metadata(path)?
+    } else {
+        fs::symlink_metadata(path)?
+    };
+
+    Ok(FileInfo {
+        dev: st.dev(),
+        ino: st.ino(),
+        mode: st.mode(),
+        nlink: st.nlink(),
+        uid: st.uid(),
+        gid: st.gid(),
+        rdev: st.rdev(),
+        size: st.size(),
+        blksize: st.blksize(),
+        blocks: st.blocks(),
+        atime: (st.atime(), st.atime_nsec()),
+        mtime: (st.mtime(), st.mtime_nsec()),
+        ctime: (st.ctime(), st.ctime_nsec()),
+        btime: None,
+        mnt_id: None,
+        attributes: None,
+    })
+}
+
+fn statx(path: &Path, dereference: bool) -> io::Result<FileInfo> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+    let mut flags = libc::AT_STATX_SYNC_AS_STAT;
+    if !dereference {
+        flags |= libc::AT_SYMLINK_NOFOLLOW;
+    }
+    let mask = libc::STATX_BASIC_STATS | libc::STATX_BTIME | libc::STATX_MNT_ID;
+
+    let mut stx = MaybeUninit::<libc::statx>::uninit();
+    if unsafe {
+        libc::statx(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            flags,
+            mask,
+            stx.as_mut_ptr(),
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let stx = unsafe { stx.assume_init() };
+
+    // the kernel only fills in what the filesystem supports
+    let has = |bit: u32| stx.stx_mask & bit != 0;
+    let time = |ts: libc::statx_timestamp| (ts.tv_sec, i64::from(ts.tv_nsec));
+
+    Ok(FileInfo {
+        dev: libc::makedev(stx.stx_dev_major, stx.stx_dev_minor),
+        ino: stx.stx_ino,
+        mode: u32::from(stx.stx_mode),
+        nlink: u64::from(stx.stx_nlink),
+        uid: stx.stx_uid,
+        gid: stx.stx_gid,
+        rdev: libc::makedev(stx.stx_rdev_major, stx.stx_rdev_minor),
+        size: stx.stx_size,
+        blksize: u64::from(stx.stx_blksize),
+        blocks: stx.stx_blocks,
+        atime: time(stx.stx_atime),
+        mtime: time(stx.stx_mtime),
+        ctime: time(stx.stx_ctime),
+        btime: if has(libc::STATX_BTIME) {
+            Some(time(stx.stx_btime))
+        } else {
+            None
+        },
+        mnt_id: if has(libc::STATX_MNT_ID) {
+            Some(stx.stx_mnt_id)
+        } else {
+            None
+        },
+        attributes: if stx.stx_attributes_mask != 0 {
+            Some((stx.stx_attributes, stx.stx_attributes_mask))
+        } else {
+            None
+        },
+    })
+}
+
+// the field types of statfs/statvfs differ between architectures
+#[allow(clippy::unnecessary_cast)]
+fn fs_info(path: &Path) -> io::Result<FsInfo> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+
+    let mut sfs = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(c_path.as_ptr(), sfs.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sfs = unsafe { sfs.assume_init() };
+
+    // f_fsid of struct statfs is opaque, statvfs has it as a number
+    let mut svfs = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(c_path.as_ptr(), svfs.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let svfs = unsafe { svfs.assume_init() };
+
+    Ok(FsInfo {
+        // coreutils prints the two 32-bit halves of fsid_t the other way round
+        fsid: (svfs.f_fsid as u64).rotate_left(32),
+        fs_type: sfs.f_type as i64,
+        namelen: sfs.f_namelen as i64,
+        bsize: sfs.f_bsize as i64,
+        frsize: sfs.f_frsize as i64,
+        blocks: sfs.f_blocks as u64,
+        bfree: sfs.f_bfree as u64,
+        bavail: sfs.f_bavail as u64,
+        files: sfs.f_files as u64,
+        ffree: sfs.f_ffree as u64,
+    })
+}
+
+fn file_field(
+    conv: &str,
+    name: &str,
+    dereference: bool,
+    always_quote: bool,
+    info: &FileInfo,
+) -> Option<Value> {
+    let value = match conv {
+        "a" => Value::Str(format!("{:o}", info.mode & 0o7777)),
+        "A" => Value::Str(format!(
+            "{}{}",
+            type_char(info.mode),
+            mode::symbolic(info.mode)
+        )),
+        "b" => Value::Num(i128::from(info.blocks)),
+        "B" => Value::Num(512),
+        "d" => Value::Num(i128::from(info.dev)),
+        "D" => Value::Str(format!("{:x}", info.dev)),
+        "Hd" => Value::Num(i128::from(major(info.dev))),
+        "Ld" => Value::Num(i128::from(minor(info.dev))),
+        "f" => Value::Str(format!("{:x}", info.mode)),
+        "F" => Value::Str(type_name(info.mode, info.size).to_string()),
+        "g" => Value::Num(i128::from(info.gid)),
+        "G" => Value::Str(ids::group_name(Gid::from_raw(info.gid))),
+        "h" => Value::Num(i128::from(info.nlink)),
+        "i" => Value::Num(i128::from(info.ino)),
+        "n" => Value::Str(name.to_string()),
+        "N" => {
+            let mut quoted = quote(name, always_quote);
+            if !dereference && info.mode & libc::S_IFMT == libc::S_IFLNK {
+                if let Ok(target) = fs::read_link(name) {
+                    let target = quote(&target.to_string_lossy(), always_quote);
+                    quoted = format!("{} -> {}", quoted, target);
+                }
+            }
+            Value::Str(quoted)
+        }
+        "o" => Value::Num(i128::from(info.blksize)),
+        "r" => Value::Num(i128::from(info.rdev)),
+        "Hr" => Value::Num(i128::from(major(info.rdev))),
+        "Lr" => Value::Num(i128::from(minor(info.rdev))),
+        "R" => Value::Str(format!("{:x}", info.rdev)),
+        "s" => Value::Num(i128::from(info.size)),
+        "t" => Value::Str(format!("{:x}", major(info.rdev))),
+        "T" => Value::Str(format!("{:x}", minor(info.rdev))),
+        "u" => Value::Num(i128::from(info.uid)),
+        "U" => Value::Str(ids::user_name(Uid::from_raw(info.uid))),
+        "w" => Value::Str(match info.btime {
+            Some(btime) => human_time(btime),
+            None => "-".to_string(),
+        }),
+        "W" => Value::Num(i128::from(info.btime.map_or(0, |(sec, _)| sec))),
+        "M" => match info.mnt_id {
+            Some(mnt_id) => Value::Num(i128::from(mnt_id)),
+            None => Value::Str("-".to_string()),
+        },
+        "e" => Value::Str(match info.attributes {
+            Some((attributes, mask)) => attribute_flags(attributes, mask),
+            None => "-".to_string(),
+        }),
+        "E" => Value::Str(match info.attributes {
+            Some((_, mask)) => format!("{:x}", mask),
+            None => "-".to_string(),
+        }),
+        "x" => Value::Str(human_time(info.atime)),
+        "X" => Value::Num(i128::from(info.atime.0)),
+        "y" => Value::Str(human_time(info.mtime)),
+        "Y" => Value::Num(i128::from(info.mtime.0)),
+        "z" => Value::Str(human_time(info.ctime)),
+        "Z" => Value::Num(i128::from(info.ctime.0)),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn fs_field(conv: &str, name: &str, always_quote: bool, info: &FsInfo) -> Option<Value> {
+    let value = match conv {
+        "a" => Value::Num(i128::from(info.bavail)),
+        "b" => Value::Num(i128::from(info.blocks)),
+        "c" => Value::Num(i128::from(info.files)),
+        "d" => Value::Num(i128::from(info.ffree)),
+        "f" => Value::Num(i128::from(info.bfree)),
+        "i" => Value::Str(format!("{:x}", info.fsid)),
+        "l" => Value::Num(i128::from(info.namelen)),
+        "n" => Value::Str(name.to_string()),
+        "N" => Value::Str(quote(name, always_quote)),
+        "s" => Value::Num(i128::from(info.bsize)),
+        "S" => Value::Num(i128::from(info.frsize)),
+        "t" => Value::Str(format!("{:x}", info.fs_type)),
+        "T" => Value::Str(fs_type_name(info.fs_type)),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+// Expand a stat format: %[-0][WIDTH][.PRECISION]CONVERSION, where the
+// conversion may carry an H or L prefix (%Hd, %Lr). Bytes outside the
+// conversions, as --printf escapes may produce, are copied as they are.
+fn render<F>(format: &[u8], field: F) -> Vec<u8>
+where
+    F: Fn(&str) -> Option<Value>,
+{
+    let mut out = Vec::new();
+    let mut bytes = format.iter().copied().peekable();
+
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+
+        let mut spec = String::from("%");
+        let mut left = false;
+        let mut zero = false;
+        while let Some(&flag) = bytes.peek() {
+            match flag {
+                b'-' => left = true,
+                b'0' => zero = true,
+                b'#' | b'+' | b' ' | b'\'' => {}
+                _ => break,
+            }
+            spec.push(flag as char);
+            bytes.next();
+        }
+        let mut width = String::new();
+        while let Some(&d) = bytes.peek().filter(|d| d.is_ascii_digit()) {
+            width.push(d as char);
+            bytes.next();
+        }
+        let mut precision = None;
+        if bytes.peek() == Some(&b'.') {
+            bytes.next();
+            let mut digits = String::new();
+            while let Some(&d) = bytes.peek().filter(|d| d.is_ascii_digit()) {
+                digits.push(d as char);
+                bytes.next();
+            }
+            precision = Some(digits.parse().unwrap_or(0));
+        }
+        spec.push_str(&width);
+
+        let mut conv = Vec::new();
+        match bytes.next() {
+            Some(b'%') => {
+                out.push(b'%');
+                continue;
+            }
+            Some(prefix @ b'H') | Some(prefix @ b'L') => {
+                conv.push(prefix);
+                conv.extend(bytes.next());
+            }
+            Some(b) => conv.push(b),
+            None => {
+                out.extend_from_slice(spec.as_bytes());
+                break;
+            }
         }
+
+        let text = match field(&String::from_utf8_lossy(&conv)) {
+            Some(Value::Num(n)) => n.to_string(),
+            Some(Value::Str(s)) => match precision {
+                Some(p) => s.chars().take(p).collect(),
+                None => s,
+            },
+            // unknown conversions are printed as given
+            None => {
+                out.extend_from_slice(spec.as_bytes());
+                out.extend_from_slice(&conv);
+                continue;
+            }
+        };
+        let width: usize = width.parse().unwrap_or(0);
+        out.extend_from_slice(pad(&text, width, left, zero).as_bytes());
+    }
+
+    out
+}
+
+fn pad(text: &str, width: usize, left: bool, zero: bool) -> String {
+    let len = text.chars().count();
+    if len >= width {
+        return text.to_string();
+    }
+
+    let fill = width - len;
+    if left {
+        format!("{}{}", text, " ".repeat(fill))
+    } else if zero && text.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        match text.strip_prefix('-') {
+            Some(digits) => format!("-{}{}", "0".repeat(fill), digits),
+            None => format!("{}{}", "0".repeat(fill), text),
+        }
+    } else {
+        format!("{}{}", " ".repeat(fill), text)
+    }
+}
+
+// backslash escapes for --printf. \NNN and \xHH give single bytes, which
+// need not be valid UTF-8.
+fn unescape(s: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('r') => out.push(b'\r'),
+            Some('a') => out.push(0x07),
+            Some('b') => out.push(0x08),
+            Some('f') => out.push(0x0c),
+            Some('v') => out.push(0x0b),
+            Some('e') => out.push(0x1b),
+            Some('\\') => out.push(b'\\'),
+            Some('"') => out.push(b'"'),
+            Some(c @ '0'..='7') => {
+                let mut digits = c.to_string();
+                while digits.len() < 3 {
+                    match chars.peek() {
+                        Some(&d @ '0'..='7') => {
+                            digits.push(d);
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                // \777 wraps around as in coreutils
+                out.push(u16::from_str_radix(&digits, 8).unwrap_or(0) as u8);
+            }
+            Some('x') => {
+                let mut digits = String::new();
+                while digits.len() < 2 {
+                    match chars.peek() {
+                        Some(&d) if d.is_ascii_hexdigit() => {
+                            digits.push(d);
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(b) => out.push(b),
+                    Err(_) => out.extend_from_slice(b"\\x"),
+                }
+            }
+            Some(c) => {
+                out.push(b'\\');
+                let mut buf = [0; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            None => out.push(b'\\'),
+        }
+    }
+
+    out
+}
+
+// coreutils' shell-escape style, which quotes only names that need it, or
+// with `always` its shell-escape-always style
+fn quote(name: &str, always: bool) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_./,:@%+=-".contains(c);
+    if !always && !name.is_empty() && name.chars().all(safe) {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "'\\''"))
+    }
+}
+
+fn human_time((sec, nsec): (i64, i64)) -> String {
+    match Local.timestamp_opt(sec, nsec as u32).single() {
+        Some(dt) => dt.format("%Y-%m-%d %H:%M:%S.%f %z").to_string(),
+        None => "-".to_string(),
+    }
+}
+
+// chattr(1) style letters: the letter when set, '-' when the filesystem
+// supports the attribute but it is not set, '?' when it is not supported
+fn attribute_flags(attributes: u64, mask: u64) -> String {
+    let flags = [
+        (libc::STATX_ATTR_IMMUTABLE, 'i'),
+        (libc::STATX_ATTR_APPEND, 'a'),
+        (libc::STATX_ATTR_COMPRESSED, 'c'),
+        (libc::STATX_ATTR_ENCRYPTED, 'E'),
+        (libc::STATX_ATTR_NODUMP, 'd'),
+        (libc::STATX_ATTR_VERITY, 'V'),
+        (libc::STATX_ATTR_DAX, 'x'),
+    ];
+
+    flags
+        .iter()
+        .map(|&(bit, letter)| {
+            let bit = bit as u64;
+            if mask & bit == 0 {
+                '?'
+            } else if attributes & bit != 0 {
+                letter
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn is_device(mode: u32) -> bool {
+    let fmt = mode & libc::S_IFMT;
+    fmt == libc::S_IFCHR || fmt == libc::S_IFBLK
+}
+
+fn type_char(mode: u32) -> char {
+    match mode & libc::S_IFMT {
+        libc::S_IFDIR => 'd',
+        libc::S_IFLNK => 'l',
+        libc::S_IFIFO => 'p',
+        libc::S_IFSOCK => 's',
+        libc::S_IFCHR => 'c',
+        libc::S_IFBLK => 'b',
+        _ => '-',
+    }
+}
+
+fn type_name(mode: u32, size: u64) -> &'static str {
+    match mode & libc::S_IFMT {
+        libc::S_IFREG if size == 0 => "regular empty file",
+        libc::S_IFREG => "regular file",
+        libc::S_IFDIR => "directory",
+        libc::S_IFLNK => "symbolic link",
+        libc::S_IFIFO => "fifo",
+        libc::S_IFSOCK => "socket",
+        libc::S_IFCHR => "character special file",
+        libc::S_IFBLK => "block special file",
+        _ => "weird file",
+    }
+}
+
+// names for the magic numbers in <linux/magic.h> that are common enough
+fn fs_type_name(fs_type: i64) -> String {
+    let name = match fs_type {
+        0xEF53 => "ext2/ext3",
+        0x0102_1994 => "tmpfs",
+        0x9FA0 => "proc",
+        0x6265_6572 => "sysfs",
+        0x1CD1 => "devpts",
+        0x9123_683E => "btrfs",
+        0x5846_5342 => "xfs",
+        0x794C_7630 => "overlayfs",
+        0x6969 => "nfs",
+        0x6367_7270 => "cgroup2fs",
+        0x0027_E0EB => "cgroupfs",
+        0x4D44 => "msdos",
+        0xF2F5_2010 => "f2fs",
+        0x2FC1_2FC1 => "zfs",
+        0x6573_5546 => "fuseblk",
+        0x7371_7368 => "squashfs",
+        0x8584_58F6 => "ramfs",
+        0x9660 => "isofs",
+        0x6462_6720 => "debugfs",
+        0x7363_6673 => "securityfs",
+        0xCAFE_4A11 => "bpf_fs",
+        _ => return format!("UNKNOWN (0x{:x})", fs_type),
     };
-    println!("{:?}", st);
 
-    process::exit(0);
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(conv: &str) -> Option<Value> {
+        match conv {
+            "s" => Some(Value::Num(42)),
+            "n" => Some(Value::Str("name".to_string())),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn unescape_bytes() {
+        assert_eq!(unescape("\\377\\x41\\n"), vec![0xff, b'A', b'\n']);
+        assert_eq!(unescape("\\0\\101"), vec![0, b'A']);
+        assert_eq!(unescape("\\xg\\q\\"), b"\\xg\\q\\".to_vec());
+        assert_eq!(unescape("é"), "é".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn render_conversions() {
+        assert_eq!(render(b"%s %n", field), b"42 name");
+        assert_eq!(render(b"[%5s|%-5s|%05s]", field), b"[   42|42   |00042]");
+        assert_eq!(render(b"%.2n %% %Z", field), b"na % %Z");
+        assert_eq!(render(b"\xff%s\xfe", field), b"\xff42\xfe");
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("/etc/passwd", false), "/etc/passwd");
+        assert_eq!(quote("/etc/passwd", true), "'/etc/passwd'");
+        assert_eq!(quote("a b", false), "'a b'");
+        assert_eq!(quote("it's", true), "'it'\\''s'");
+    }
 }
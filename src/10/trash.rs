@@ -0,0 +1,96 @@
+use getopts::Options;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+mod xdg_trash;
+
+use xdg_trash::TrashEntry;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        println!("Usage: {:?} list | restore FILE... | empty", &args[0]);
+        process::exit(0);
+    }
+
+    let entries = match collect_entries() {
+        Ok(entries) => entries,
+        Err(why) => {
+            eprintln!("{:?}: {:?}", &args[0], why.to_string());
+            process::exit(1);
+        }
+    };
+
+    let mut status = 0;
+    match matches.free[0].as_str() {
+        "list" => {
+            for entry in &entries {
+                println!("{} {}", entry.deleted, entry.original.display());
+            }
+        }
+        "restore" => {
+            for target in &matches.free[1..] {
+                let result = match find_entry(&entries, target) {
+                    Some(entry) => entry.restore(),
+                    None => Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "not in trash",
+                    )),
+                };
+                if let Err(why) = result {
+                    eprintln!("{:?}: {:?}", target, why.to_string());
+                    status = 1;
+                }
+            }
+        }
+        "empty" => {
+            for entry in &entries {
+                if let Err(why) = entry.purge() {
+                    eprintln!("{:?}: {:?}", entry.trashed_path(), why.to_string());
+                    status = 1;
+                }
+            }
+        }
+        command => {
+            eprintln!("{:?}: unknown command {:?}", &args[0], command);
+            status = 1;
+        }
+    }
+
+    process::exit(status);
+}
+
+// sorted by deletion date, oldest first
+fn collect_entries() -> std::io::Result<Vec<TrashEntry>> {
+    let mut entries = Vec::new();
+    for trash in xdg_trash::all_trashes()? {
+        entries.extend(trash.entries()?);
+    }
+    entries.sort_by(|a, b| a.deleted.cmp(&b.deleted));
+
+    Ok(entries)
+}
+
+// A target is either the original path of a trashed file or its name inside
+// the trash. When the same path was trashed several times the newest wins.
+fn find_entry<'a>(entries: &'a [TrashEntry], target: &str) -> Option<&'a TrashEntry> {
+    let original: Option<PathBuf> = xdg_trash::absolute_path(Path::new(target)).ok();
+
+    entries
+        .iter()
+        .rev()
+        .find(|entry| Some(&entry.original) == original.as_ref() || entry.name == *target)
+}
@@ -0,0 +1,537 @@
+// freedesktop.org Trash specification (1.0) の実装. rm --trash と trash で共有する.
+// https://specifications.freedesktop.org/trash-spec/trashspec-1.0.html
+//
+// Not every binary uses every item in this module.
+#![allow(dead_code)]
+
+use chrono::Local;
+use nix::unistd::getuid;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{symlink, DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+const INFO_EXT: &str = ".trashinfo";
+
+#[derive(Clone, Debug)]
+pub struct TrashDir {
+    pub root: PathBuf,
+    // None for the home trash, otherwise the top directory of the volume
+    // (paths in .trashinfo are stored relative to it)
+    pub topdir: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct TrashEntry {
+    pub trash: TrashDir,
+    pub name: OsString,
+    pub original: PathBuf,
+    pub deleted: String,
+}
+
+impl TrashDir {
+    pub fn files(&self) -> PathBuf {
+        self.root.join("files")
+    }
+
+    pub fn info(&self) -> PathBuf {
+        self.root.join("info")
+    }
+
+    fn info_file(&self, name: &OsStr) -> PathBuf {
+        let mut file = name.to_os_string();
+        file.push(INFO_EXT);
+        self.info().join(file)
+    }
+
+    fn ensure(&self) -> io::Result<()> {
+        let mut builder = DirBuilder::new();
+        builder.recursive(true).mode(0o700);
+        builder.create(self.files())?;
+        builder.create(self.info())
+    }
+
+    pub fn entries(&self) -> io::Result<Vec<TrashEntry>> {
+        let mut entries = Vec::new();
+        let info_dir = match fs::read_dir(self.info()) {
+            Ok(dir) => dir,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(entries),
+            Err(why) => return Err(why),
+        };
+
+        for entry in info_dir {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let bytes = file_name.as_bytes();
+            if !bytes.ends_with(INFO_EXT.as_bytes()) {
+                continue;
+            }
+            let name = OsStr::from_bytes(&bytes[..bytes.len() - INFO_EXT.len()]);
+
+            let contents = fs::read_to_string(entry.path())?;
+            if let Some((original, deleted)) = parse_info(&contents) {
+                let original = match self.topdir {
+                    Some(ref topdir) if original.is_relative() => topdir.join(original),
+                    _ => original,
+                };
+                entries.push(TrashEntry {
+                    trash: self.clone(),
+                    name: name.to_os_string(),
+                    original,
+                    deleted,
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+impl TrashEntry {
+    pub fn trashed_path(&self) -> PathBuf {
+        self.trash.files().join(&self.name)
+    }
+
+    pub fn restore(&self) -> io::Result<()> {
+        if fs::symlink_metadata(&self.original).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", self.original.display()),
+            ));
+        }
+        if let Some(parent) = self.original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(&self.trashed_path(), &self.original)?;
+        fs::remove_file(self.trash.info_file(&self.name))
+    }
+
+    pub fn purge(&self) -> io::Result<()> {
+        let path = self.trashed_path();
+        match fs::symlink_metadata(&path) {
+            Ok(st) if st.is_dir() => fs::remove_dir_all(&path)?,
+            Ok(_) => fs::remove_file(&path)?,
+            // an orphaned info file: only the record is left
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => {}
+            Err(why) => return Err(why),
+        }
+        fs::remove_file(self.trash.info_file(&self.name))
+    }
+}
+
+pub fn home_trash() -> io::Result<TrashDir> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local/share"),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "neither XDG_DATA_HOME nor HOME is set",
+                ))
+            }
+        },
+    };
+
+    Ok(TrashDir {
+        root: data_home.join("Trash"),
+        topdir: None,
+    })
+}
+
+fn volume_trash(topdir: &Path) -> TrashDir {
+    TrashDir {
+        root: topdir.join(format!(".Trash-{}", getuid())),
+        topdir: Some(topdir.to_path_buf()),
+    }
+}
+
+// Every trash directory that currently exists: the home trash followed by
+// the per-volume trashes of mounted filesystems.
+pub fn all_trashes() -> io::Result<Vec<TrashDir>> {
+    let mut trashes = vec![home_trash()?];
+
+    let mounts = fs::read("/proc/self/mounts")?;
+    for line in mounts.split(|&b| b == b'\n') {
+        let mount_point = match line.split(|&b| b == b' ').nth(1) {
+            Some(field) => unescape_mount_field(field),
+            None => continue,
+        };
+        let trash = volume_trash(Path::new(OsStr::from_bytes(&mount_point)));
+        if trash.root.is_dir() && !trashes.iter().any(|t| t.root == trash.root) {
+            trashes.push(trash);
+        }
+    }
+
+    Ok(trashes)
+}
+
+// Move `path` to the trash of the volume it lives on and return where it went.
+pub fn trash_path(path: &Path) -> io::Result<PathBuf> {
+    let original = absolute_path(path)?;
+    let dev = fs::symlink_metadata(&original)?.dev();
+
+    let home = home_trash()?;
+    let trash = if nearest_device(&home.root)? == dev {
+        home
+    } else {
+        // when $topdir/.Trash-$uid cannot be created, the spec allows
+        // copying to the home trash instead
+        let volume = volume_trash(&mount_point(&original, dev)?);
+        match volume.ensure() {
+            Ok(()) => volume,
+            Err(_) => home,
+        }
+    };
+    trash.ensure()?;
+
+    let recorded = match trash.topdir {
+        Some(ref topdir) => original.strip_prefix(topdir).unwrap_or(&original),
+        None => &original,
+    };
+    let deleted = Local::now().format("%Y-%m-%dT%H:%M:%S").to_string();
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(recorded.as_os_str().as_bytes()),
+        deleted
+    );
+
+    // reserve a unique name by creating the info file exclusively
+    let base = original.file_name().unwrap().to_os_string();
+    let mut name = base.clone();
+    let mut info_file = {
+        let mut n = 1;
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(trash.info_file(&name))
+            {
+                // a leftover in files/ without its record also occupies the name
+                Ok(_) if fs::symlink_metadata(trash.files().join(&name)).is_ok() => {
+                    fs::remove_file(trash.info_file(&name))?;
+                    n += 1;
+                    name = base.clone();
+                    name.push(format!(".{}", n));
+                }
+                Ok(file) => break file,
+                Err(ref why) if why.kind() == io::ErrorKind::AlreadyExists => {
+                    n += 1;
+                    name = base.clone();
+                    name.push(format!(".{}", n));
+                }
+                Err(why) => return Err(why),
+            }
+        }
+    };
+
+    let trashed = trash.files().join(&name);
+    let result = info_file
+        .write_all(info.as_bytes())
+        .and_then(|_| move_path(&original, &trashed));
+    if let Err(why) = result {
+        fs::remove_file(trash.info_file(&name)).ok();
+        return Err(why);
+    }
+
+    Ok(trashed)
+}
+
+// rename(2), or a copy followed by removing `src` when the two paths are on
+// different filesystems. A partial copy is removed again.
+fn move_path(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::rename(src, dst) {
+        Err(ref why) if why.raw_os_error() == Some(libc::EXDEV) => {}
+        result => return result,
+    }
+
+    if let Err(why) = copy_tree(src, dst) {
+        remove_tree(dst).ok();
+        return Err(why);
+    }
+    remove_tree(src)
+}
+
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    let st = fs::symlink_metadata(src)?;
+    let file_type = st.file_type();
+
+    if file_type.is_dir() {
+        DirBuilder::new().mode(0o700).create(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dst, fs::Permissions::from_mode(st.mode() & 0o7777))
+    } else if file_type.is_symlink() {
+        symlink(fs::read_link(src)?, dst)
+    } else if file_type.is_file() {
+        // fs::copy keeps the permission bits
+        fs::copy(src, dst).map(|_| ())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot copy special file {}", src.display()),
+        ))
+    }
+}
+
+fn remove_tree(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+// Make `path` absolute without following a symlink in its last component,
+// so that trashing a link moves the link itself.
+pub fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    let file_name = match path.components().next_back() {
+        Some(Component::Normal(name)) => name.to_os_string(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("refusing to trash {}", path.display()),
+            ))
+        }
+    };
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    Ok(fs::canonicalize(parent)?.join(file_name))
+}
+
+// device of `path`, or of its nearest existing ancestor
+fn nearest_device(path: &Path) -> io::Result<u64> {
+    let mut current = path;
+    loop {
+        match fs::metadata(current) {
+            Ok(st) => return Ok(st.dev()),
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => match current.parent() {
+                Some(parent) => current = parent,
+                None => return Err(io::Error::from(io::ErrorKind::NotFound)),
+            },
+            Err(why) => return Err(why),
+        }
+    }
+}
+
+// the highest ancestor of `path` that still lives on device `dev`
+fn mount_point(path: &Path, dev: u64) -> io::Result<PathBuf> {
+    let mut top = path.parent().unwrap_or(path);
+    while let Some(parent) = top.parent() {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        top = parent;
+    }
+
+    Ok(top.to_path_buf())
+}
+
+fn parse_info(contents: &str) -> Option<(PathBuf, String)> {
+    let mut lines = contents.lines();
+    if lines.next()?.trim() != "[Trash Info]" {
+        return None;
+    }
+
+    let mut original = None;
+    let mut deleted = String::new();
+    for line in lines {
+        if let Some(value) = line.strip_prefix("Path=") {
+            original = Some(PathBuf::from(OsString::from_vec(percent_decode(value))));
+        } else if let Some(value) = line.strip_prefix("DeletionDate=") {
+            deleted = value.to_string();
+        }
+    }
+
+    original.map(|path| (path, deleted))
+}
+
+fn percent_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = hex_byte(&bytes[i + 1..i + 3]) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+// /proc/self/mounts escapes space, tab, newline and backslash as \ooo
+fn unescape_mount_field(field: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(field.len());
+    let mut i = 0;
+    while i < field.len() {
+        if field[i] == b'\\' && i + 3 < field.len() {
+            if let Some(b) = octal_byte(&field[i + 1..i + 4]) {
+                out.push(b);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(field[i]);
+        i += 1;
+    }
+    out
+}
+
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+fn octal_byte(digits: &[u8]) -> Option<u8> {
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_round_trip() {
+        for name in &[
+            "/home/u/plain.txt",
+            "/tmp/with space/and%percent",
+            "/tmp/日本語のファイル",
+            "/tmp/tab\tnewline\n#?&=",
+        ] {
+            let encoded = percent_encode(name.as_bytes());
+            assert!(encoded.is_ascii());
+            assert!(!encoded.contains(' '));
+            assert_eq!(percent_decode(&encoded), name.as_bytes());
+        }
+        assert_eq!(percent_encode(b"a b%c"), "a%20b%25c");
+        assert_eq!(percent_encode(&[0xff]), "%FF");
+    }
+
+    #[test]
+    fn percent_decode_malformed() {
+        assert_eq!(percent_decode("%zz"), b"%zz");
+        assert_eq!(percent_decode("a%2"), b"a%2");
+        assert_eq!(percent_decode("%"), b"%");
+        assert_eq!(percent_decode("%41%4a%4A"), b"AJJ");
+    }
+
+    #[test]
+    fn parse_info_keys() {
+        let (path, date) =
+            parse_info("[Trash Info]\nPath=/tmp/a%20b\nDeletionDate=2004-08-31T22:32:08\n")
+                .unwrap();
+        assert_eq!(path, PathBuf::from("/tmp/a b"));
+        assert_eq!(date, "2004-08-31T22:32:08");
+
+        // unknown keys are ignored, a missing date is empty
+        let (path, date) = parse_info("[Trash Info]\nFoo=bar\nPath=rel/x\nX-Extra=1\n").unwrap();
+        assert_eq!(path, PathBuf::from("rel/x"));
+        assert_eq!(date, "");
+
+        assert!(parse_info("[Trash Info]\nDeletionDate=2004-08-31T22:32:08\n").is_none());
+        assert!(parse_info("Path=/tmp/a\n").is_none());
+        assert!(parse_info("").is_none());
+    }
+
+    #[test]
+    fn mount_field() {
+        assert_eq!(unescape_mount_field(b"/mnt/my\\040disk"), b"/mnt/my disk");
+        assert_eq!(unescape_mount_field(b"/a\\011b\\134c"), b"/a\tb\\c");
+        // not an escape
+        assert_eq!(unescape_mount_field(b"/a\\04"), b"/a\\04");
+        assert_eq!(unescape_mount_field(b"/a\\999b"), b"/a\\999b");
+    }
+
+    fn make_tree(src: &Path) {
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/file"), b"data").unwrap();
+        symlink("sub/file", src.join("link")).unwrap();
+        fs::set_permissions(src.join("sub"), fs::Permissions::from_mode(0o750)).unwrap();
+    }
+
+    fn check_tree(dst: &Path) {
+        assert_eq!(fs::read(dst.join("sub/file")).unwrap(), b"data");
+        assert_eq!(
+            fs::read_link(dst.join("link")).unwrap(),
+            PathBuf::from("sub/file")
+        );
+        let mode = fs::metadata(dst.join("sub")).unwrap().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+    }
+
+    // what move_path falls back on when rename(2) gives EXDEV
+    #[test]
+    fn copy_and_remove_trees() {
+        let base = env::temp_dir().join(format!("xdg-trash-test-copy-{}", std::process::id()));
+        let src = base.join("src");
+        make_tree(&src);
+
+        let dst = base.join("dst");
+        copy_tree(&src, &dst).unwrap();
+        remove_tree(&src).unwrap();
+        check_tree(&dst);
+        assert!(fs::symlink_metadata(&src).is_err());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn move_path_moves_trees() {
+        let base = env::temp_dir().join(format!("xdg-trash-test-move-{}", std::process::id()));
+        let src = base.join("src");
+        make_tree(&src);
+
+        let dst = base.join("dst");
+        move_path(&src, &dst).unwrap();
+        check_tree(&dst);
+        assert!(fs::symlink_metadata(&src).is_err());
+
+        // a failed move leaves the source alone
+        let error = move_path(&dst, &base.join("none/dst")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        check_tree(&dst);
+
+        // across filesystems when /dev/shm is another one
+        let shm = Path::new("/dev/shm");
+        let other_fs = fs::metadata(shm)
+            .map(|st| st.dev() != fs::metadata(&base).unwrap().dev())
+            .unwrap_or(false);
+        if other_fs {
+            let far = shm.join(format!("xdg-trash-test-{}", std::process::id()));
+            move_path(&dst, &far).unwrap();
+            assert!(fs::symlink_metadata(&dst).is_err());
+            check_tree(&far);
+            move_path(&far, &dst).unwrap();
+            check_tree(&dst);
+        } else {
+            eprintln!("skipped moving across filesystems: no /dev/shm of its own");
+        }
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
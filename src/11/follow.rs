@@ -0,0 +1,288 @@
+// tail -f / -F: 末尾に追記されたデータを出力し続ける.
+//
+// inotify はファイルの変化を待つためだけに使い, 起こされるたびに全ファイルを
+// 確認し直す. inotify が使えない場合は -s の間隔でポーリングする.
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+use nix::Error;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+// the shortest wait between checks, whatever -s says
+const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FollowMode {
+    // keep reading the file that was opened, wherever it is moved to
+    Descriptor,
+    // keep reading whatever file currently has the name, reopening it
+    // when the file is rotated or recreated
+    Name,
+}
+
+pub struct FollowOptions {
+    pub mode: FollowMode,
+    pub sleep: Duration,
+    pub pid: Option<Pid>,
+    // keep trying to open files that are missing or become inaccessible
+    pub retry: bool,
+}
+
+struct Watched {
+    path: String,
+    file: Option<File>,
+    // (st_dev, st_ino) of `file`
+    id: (u64, u64),
+    pos: u64,
+    // given up on: inaccessible without --retry
+    removed: bool,
+}
+
+struct Follower<'a> {
+    watched: Vec<Watched>,
+    opts: &'a FollowOptions,
+    inotify: Option<Inotify>,
+    headers: bool,
+    // index of the file whose data was printed last
+    last: Option<usize>,
+}
+
+// Follow `files`, each already positioned where output should continue;
+// a file that could not be opened is None and is waited for. With
+// `headers`, a header is printed whenever the output switches files.
+// Returns when the process given with --pid has exited or when no file is
+// left to follow.
+pub fn follow(
+    files: Vec<(String, Option<File>)>,
+    opts: &FollowOptions,
+    headers: bool,
+) -> io::Result<()> {
+    // the initial output printed a header for each file that was opened
+    let last = files.iter().rposition(|(_, file)| file.is_some());
+
+    let mut watched = Vec::new();
+    for (path, file) in files {
+        let (id, pos) = match file {
+            Some(ref file) => {
+                let st = file.metadata()?;
+                ((st.dev(), st.ino()), (&*file).stream_position()?)
+            }
+            None => ((0, 0), 0),
+        };
+        watched.push(Watched {
+            path,
+            file,
+            id,
+            pos,
+            removed: false,
+        });
+    }
+
+    let inotify = watch_all(&watched, opts.mode);
+    let mut follower = Follower {
+        watched,
+        opts,
+        inotify,
+        headers,
+        last,
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    loop {
+        // check the writer first so that nothing it wrote before exiting is lost
+        let writer_gone = opts.pid.map(|pid| !is_alive(pid)).unwrap_or(false);
+
+        for i in 0..follower.watched.len() {
+            if follower.watched[i].removed {
+                continue;
+            }
+            if opts.mode == FollowMode::Name || follower.watched[i].file.is_none() {
+                follower.check_name(i, &mut out)?;
+            }
+            follower.copy_new_data(i, &mut out)?;
+        }
+        out.flush()?;
+
+        if writer_gone {
+            return Ok(());
+        }
+        if follower.watched.iter().all(|w| w.removed) {
+            eprintln!("no files remaining");
+            return Ok(());
+        }
+
+        // -s 0 would otherwise keep a CPU busy
+        let sleep = opts.sleep.max(MIN_SLEEP);
+        match inotify {
+            Some(inotify) => wait_events(inotify, sleep),
+            None => thread::sleep(sleep),
+        }
+    }
+}
+
+fn watch_all(watched: &[Watched], mode: FollowMode) -> Option<Inotify> {
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).ok()?;
+    for w in watched {
+        // standard input can only be followed by descriptor, through its /dev entry
+        let path = if w.path == "-" { "/dev/stdin" } else { &w.path };
+        let watch_dir = mode == FollowMode::Name || w.file.is_none();
+        if add_watches(inotify, path, watch_dir).is_err() {
+            // e.g. a filesystem without inotify support: poll everything
+            return None;
+        }
+    }
+
+    Some(inotify)
+}
+
+// Watch `path` itself if it exists and, with `watch_dir`, its directory,
+// where a file being created or renamed into place shows up.
+fn add_watches(inotify: Inotify, path: &str, watch_dir: bool) -> nix::Result<()> {
+    let file_events = AddWatchFlags::IN_MODIFY
+        | AddWatchFlags::IN_ATTRIB
+        | AddWatchFlags::IN_DELETE_SELF
+        | AddWatchFlags::IN_MOVE_SELF;
+    match inotify.add_watch(path, file_events) {
+        Ok(_) | Err(Error::Sys(Errno::ENOENT)) => {}
+        Err(why) => return Err(why),
+    }
+
+    if watch_dir {
+        let dir = match Path::new(path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir_events = AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM;
+        inotify.add_watch(dir, dir_events)?;
+    }
+
+    Ok(())
+}
+
+// Sleep until an inotify event arrives. The timeout keeps --pid working
+// even when the files never change.
+fn wait_events(inotify: Inotify, timeout: Duration) {
+    let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+    // rounded up, so that a timeout under a millisecond still waits
+    let millis = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+    if let Ok(n) = poll(&mut fds, millis) {
+        if n > 0 {
+            // the events themselves do not matter: every file is checked anyway
+            while let Ok(events) = inotify.read_events() {
+                if events.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn is_alive(pid: Pid) -> bool {
+    !matches!(kill(pid, None), Err(Error::Sys(Errno::ESRCH)))
+}
+
+impl<'a> Follower<'a> {
+    // Notice when the name now refers to another file, or to none at all.
+    // Without -F this only waits for a missing file to appear.
+    fn check_name(&mut self, i: usize, out: &mut impl Write) -> io::Result<()> {
+        let st = match fs::metadata(&self.watched[i].path) {
+            Ok(st) => st,
+            Err(_) => return self.lost(i, out),
+        };
+
+        let id = (st.dev(), st.ino());
+        if self.watched[i].file.is_some() && id == self.watched[i].id {
+            return Ok(());
+        }
+
+        let file = match File::open(&self.watched[i].path) {
+            Ok(file) => file,
+            Err(_) => return self.lost(i, out),
+        };
+        let name = crate::display_name(&self.watched[i].path).to_string();
+        if self.watched[i].file.is_some() {
+            // whatever was appended just before the rotation still belongs to the output
+            self.copy_new_data(i, out)?;
+            eprintln!("{:?} has been replaced; following new file", name);
+        } else {
+            eprintln!("{:?} has appeared; following new file", name);
+        }
+
+        let w = &mut self.watched[i];
+        w.file = Some(file);
+        w.id = id;
+        w.pos = 0;
+        // the old watch stays on the old inode
+        if let Some(inotify) = self.inotify {
+            add_watches(inotify, &w.path, self.opts.mode == FollowMode::Name).ok();
+        }
+
+        Ok(())
+    }
+
+    // The file can no longer be opened by name: print what is left of the
+    // old one, then wait for it to come back or, without --retry, give up.
+    fn lost(&mut self, i: usize, out: &mut impl Write) -> io::Result<()> {
+        if self.watched[i].file.is_none() {
+            return Ok(());
+        }
+
+        self.copy_new_data(i, out)?;
+        let w = &mut self.watched[i];
+        w.file = None;
+        if self.opts.retry {
+            eprintln!("{:?} has become inaccessible", crate::display_name(&w.path));
+        } else {
+            eprintln!(
+                "{:?} has become inaccessible; giving up on this name",
+                crate::display_name(&w.path)
+            );
+            w.removed = true;
+        }
+
+        Ok(())
+    }
+
+    fn copy_new_data(&mut self, i: usize, out: &mut impl Write) -> io::Result<()> {
+        let w = &mut self.watched[i];
+        let file = match w.file.as_mut() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let st = file.metadata()?;
+        if st.is_file() && st.len() < w.pos {
+            eprintln!("{:?}: file truncated", crate::display_name(&w.path));
+            w.pos = file.seek(SeekFrom::Start(0))?;
+        }
+
+        let mut data = Vec::new();
+        w.pos += file.read_to_end(&mut data)? as u64;
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if self.headers && self.last != Some(i) {
+            let header = crate::header(&w.path, self.last.is_none());
+            out.write_all(header.as_bytes())?;
+        }
+        self.last = Some(i);
+
+        out.write_all(&data)
+    }
+}
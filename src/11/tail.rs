@@ -1,74 +1,544 @@
-use getopts::Options;
+use getopts::{Matches, Options};
+use nix::unistd::Pid;
 use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, SeekFrom};
+use std::os::unix::io::AsFd;
 use std::process;
+use std::time::Duration;
+
+mod follow;
+
+use follow::{FollowMode, FollowOptions};
+
+const BLOCK_SIZE: usize = 8192;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unit {
+    Lines,
+    Bytes,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Count {
+    // the last N lines or bytes
+    Last(u64),
+    // everything from the Nth line or byte on (+N), counting from 1
+    From(u64),
+}
+
+struct TailOptions {
+    unit: Unit,
+    count: Count,
+    // line terminator: '\n', or NUL with -z
+    delimiter: u8,
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let mut opts = Options::new();
-    opts.optopt("n", "lines", "print this help menu", "NAME");
+    opts.optflag("h", "help", "print this help menu");
+    opts.optmulti(
+        "n",
+        "lines",
+        "output the last NUM lines, or use +NUM to output starting with line NUM",
+        "NUM",
+    );
+    opts.optmulti(
+        "c",
+        "bytes",
+        "output the last NUM bytes, or use +NUM to output starting with byte NUM",
+        "NUM",
+    );
+    opts.optflag("q", "quiet", "never output headers giving file names");
+    opts.optflag("v", "verbose", "always output headers giving file names");
+    opts.optflag("z", "zero-terminated", "line delimiter is NUL, not newline");
+    opts.optflag("f", "", "output appended data as the file grows");
+    opts.optflagopt(
+        "",
+        "follow",
+        "like -f; HOW is 'name' or 'descriptor' (the default)",
+        "HOW",
+    );
+    opts.optflag("F", "", "same as --follow=name --retry");
+    opts.optflag(
+        "",
+        "retry",
+        "with -f, keep trying to open files that are missing or become inaccessible",
+    );
+    opts.optopt(
+        "s",
+        "sleep-interval",
+        "with -f, check the files every N seconds when inotify is not available (default 1.0)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "pid",
+        "with -f, terminate after process PID dies",
+        "PID",
+    );
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => {
-            panic!(f.to_string())
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!(
+            "Usage: {:?} [-fFqvz] [-s N] [--pid=PID] [--retry] [-n [+]LINES | -c [+]BYTES] [FILE...]",
+            &args[0]
+        );
+        process::exit(0);
+    }
+
+    let tail_opts = match parse_count(&matches) {
+        Ok((unit, count)) => TailOptions {
+            unit,
+            count,
+            delimiter: if matches.opt_present("z") {
+                b'\0'
+            } else {
+                b'\n'
+            },
+        },
+        Err(msg) => {
+            eprintln!("{:?}: {}", &args[0], msg);
+            process::exit(1);
         }
     };
 
-    let nlines: usize = match matches.opt_str("n") {
-        Some(n) => n.parse().unwrap(),
-        None => {
-            eprintln!("Usage: {:?} [-n LINES] [FILE...]", &args[0]);
+    let follow_opts = match parse_follow(&matches) {
+        Ok(follow_opts) => follow_opts,
+        Err(msg) => {
+            eprintln!("{:?}: {}", &args[0], msg);
             process::exit(1);
         }
     };
 
-    for i in 0..matches.free.len() {
-        do_tail(&matches.free[i], nlines);
+    // 引数がなければ標準入力を読む
+    let inputs = if matches.free.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        matches.free.clone()
+    };
+    // -q と -v は後に指定されたものが優先される
+    let headers = match (
+        matches.opt_positions("q").last(),
+        matches.opt_positions("v").last(),
+    ) {
+        (Some(q), Some(v)) => v > q,
+        (None, Some(_)) => true,
+        (Some(_), None) => false,
+        (None, None) => inputs.len() > 1,
+    };
+
+    let retry = follow_opts.as_ref().map(|f| f.retry).unwrap_or(false);
+
+    let mut status = 0;
+    let mut first = true;
+    let mut opened = Vec::new();
+    for input in &inputs {
+        let header = if headers {
+            Some(header(input, first))
+        } else {
+            None
+        };
+
+        match do_tail(input, &tail_opts, header) {
+            Ok(file) => {
+                first = false;
+                opened.push((input.clone(), Some(file)));
+            }
+            Err(why) => {
+                eprintln!("{:?}: {:?}", display_name(input), why.to_string());
+                status = 1;
+                // --retry: follow it once it appears
+                if retry && input != "-" {
+                    opened.push((input.clone(), None));
+                }
+            }
+        }
+    }
+
+    if let Some(follow_opts) = follow_opts {
+        // standard input has no name to follow, and a pipe never grows again
+        opened.retain(|(input, file)| {
+            input != "-"
+                || (follow_opts.mode == FollowMode::Descriptor
+                    && file
+                        .as_ref()
+                        .and_then(|file| file.metadata().ok())
+                        .map(|st| st.is_file())
+                        .unwrap_or(false))
+        });
+        if !opened.is_empty() {
+            if let Err(why) = follow::follow(opened, &follow_opts, headers) {
+                eprintln!("{:?}: {:?}", &args[0], why.to_string());
+                status = 1;
+            }
+        }
+    }
+
+    process::exit(status);
+}
+
+fn display_name(input: &str) -> &str {
+    if input == "-" {
+        "standard input"
+    } else {
+        input
+    }
+}
+
+// `==> name <==`, separated by a blank line from any earlier output.
+fn header(input: &str, first: bool) -> String {
+    format!(
+        "{}==> {} <==\n",
+        if first { "" } else { "\n" },
+        display_name(input)
+    )
+}
+
+// -n と -c は後に指定されたものが優先される. どちらもなければ最後の10行.
+fn parse_count(matches: &Matches) -> Result<(Unit, Count), String> {
+    let lines = matches.opt_positions("n").last().copied();
+    let bytes = matches.opt_positions("c").last().copied();
+    let (unit, spec, what) = match (lines, bytes) {
+        (None, None) => return Ok((Unit::Lines, Count::Last(10))),
+        (Some(n), Some(c)) if c > n => (Unit::Bytes, matches.opt_strs("c"), "bytes"),
+        (None, Some(_)) => (Unit::Bytes, matches.opt_strs("c"), "bytes"),
+        _ => (Unit::Lines, matches.opt_strs("n"), "lines"),
+    };
+
+    let spec = spec.last().unwrap();
+    let count = if let Some(n) = spec.strip_prefix('+') {
+        // +0 is the same as +1: everything
+        n.parse().map(|n: u64| Count::From(n.max(1)))
+    } else {
+        spec.strip_prefix('-')
+            .unwrap_or(spec)
+            .parse()
+            .map(Count::Last)
+    };
+
+    match count {
+        Ok(count) => Ok((unit, count)),
+        Err(_) => Err(format!("invalid number of {}: {:?}", what, spec)),
     }
+}
+
+// None when tail should exit after printing the last lines.
+fn parse_follow(matches: &Matches) -> Result<Option<FollowOptions>, String> {
+    let mode = if matches.opt_present("F") {
+        FollowMode::Name
+    } else {
+        match matches.opt_default("follow", "descriptor").as_deref() {
+            Some("name") => FollowMode::Name,
+            Some("descriptor") => FollowMode::Descriptor,
+            Some(how) => return Err(format!("invalid argument {:?} for --follow", how)),
+            None if matches.opt_present("f") => FollowMode::Descriptor,
+            None => return Ok(None),
+        }
+    };
 
-    process::exit(0);
+    let sleep = match matches.opt_str("s") {
+        Some(secs) => match secs.parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs >= 0.0 => Duration::from_secs_f64(secs),
+            _ => return Err(format!("invalid number of seconds: {:?}", secs)),
+        },
+        None => Duration::from_secs(1),
+    };
+
+    let pid = match matches.opt_str("pid") {
+        Some(pid) => match pid.parse::<i32>() {
+            Ok(pid) if pid > 0 => Some(Pid::from_raw(pid)),
+            _ => return Err(format!("invalid PID: {:?}", pid)),
+        },
+        None => None,
+    };
+
+    Ok(Some(FollowOptions {
+        mode,
+        sleep,
+        pid,
+        retry: matches.opt_present("retry") || matches.opt_present("F"),
+    }))
 }
 
-fn do_tail(path: &str, n: usize) {
-    let f = match File::open(path) {
-        Ok(file) => file,
-        Err(why) => panic!("couln't open {}: {}", path, why.to_string()),
+// Print the requested part of `input` ("-" is standard input) after
+// `header`. The file is returned positioned at the end of what was read,
+// for -f to carry on from.
+fn do_tail(input: &str, opts: &TailOptions, header: Option<String>) -> io::Result<File> {
+    let mut f = if input == "-" {
+        File::from(io::stdin().as_fd().try_clone_to_owned()?)
+    } else {
+        File::open(input)?
     };
-    let mut buf_f = BufReader::new(f);
 
-    let mut tails: VecDeque<String> = VecDeque::new();
+    let stdout = io::stdout();
+    let mut buf_out = BufWriter::new(stdout.lock());
+    if let Some(header) = header {
+        buf_out.write_all(header.as_bytes())?;
+    }
+
+    // pipes and terminals cannot be read backwards
+    if f.metadata()?.is_file() {
+        tail_seek(&mut f, opts, &mut buf_out)?;
+    } else {
+        tail_stream(&mut f, opts, &mut buf_out)?;
+    }
+    buf_out.flush()?;
+
+    Ok(f)
+}
+
+// Find where the output starts without reading more than needed: blocks
+// are read backwards from EOF for the last N lines, and +N bytes is a
+// plain seek. Only +N lines has to read from the current position on.
+fn tail_seek<W: Write>(f: &mut File, opts: &TailOptions, out: &mut W) -> io::Result<()> {
+    let pos = f.stream_position()?;
+    let len = f.seek(SeekFrom::End(0))?.max(pos);
+    let start = match (opts.unit, opts.count) {
+        (Unit::Lines, Count::Last(n)) => start_of_last_lines(f, pos, len, n, opts.delimiter)?,
+        (Unit::Bytes, Count::Last(n)) => len.saturating_sub(n).max(pos),
+        (Unit::Bytes, Count::From(n)) => pos.saturating_add(n - 1).min(len),
+        (Unit::Lines, Count::From(_)) => {
+            f.seek(SeekFrom::Start(pos))?;
+            return tail_stream(f, opts, out);
+        }
+    };
+
+    f.seek(SeekFrom::Start(start))?;
+    io::copy(&mut f.take(len - start), out)?;
+
+    Ok(())
+}
+
+// Offset of the first of the last `n` lines between `pos` and `len`.
+fn start_of_last_lines(f: &mut File, pos: u64, len: u64, n: u64, delimiter: u8) -> io::Result<u64> {
+    if n == 0 {
+        return Ok(len);
+    }
+
+    let mut buf = vec![0; BLOCK_SIZE];
+    let mut found = 0;
+    // the last byte is either the last line's terminator or part of that
+    // line; in neither case does it start a new line
+    let mut end = len.saturating_sub(1);
+    while end > pos {
+        let start = end.saturating_sub(BLOCK_SIZE as u64).max(pos);
+        let block = &mut buf[..(end - start) as usize];
+        f.seek(SeekFrom::Start(start))?;
+        f.read_exact(block)?;
+
+        for (i, &b) in block.iter().enumerate().rev() {
+            if b == delimiter {
+                found += 1;
+                if found == n {
+                    return Ok(start + i as u64 + 1);
+                }
+            }
+        }
+        end = start;
+    }
+
+    Ok(pos)
+}
+
+// Read the whole input front to back, for pipes and for +N lines.
+fn tail_stream<R: Read, W: Write>(input: R, opts: &TailOptions, out: &mut W) -> io::Result<()> {
+    let mut buf_f = BufReader::new(input);
+
+    match (opts.unit, opts.count) {
+        (Unit::Lines, Count::Last(n)) => last_lines(&mut buf_f, n, opts.delimiter, out),
+        (Unit::Bytes, Count::Last(n)) => last_bytes(&mut buf_f, n, out),
+        (Unit::Lines, Count::From(n)) => {
+            let mut line = Vec::new();
+            for _ in 1..n {
+                line.clear();
+                if buf_f.read_until(opts.delimiter, &mut line)? == 0 {
+                    return Ok(());
+                }
+            }
+            io::copy(&mut buf_f, out).map(|_| ())
+        }
+        (Unit::Bytes, Count::From(n)) => {
+            io::copy(&mut buf_f.by_ref().take(n - 1), &mut io::sink())?;
+            io::copy(&mut buf_f, out).map(|_| ())
+        }
+    }
+}
+
+// Keep the last `n` lines in a ring buffer while reading the whole input.
+fn last_lines<R: BufRead, W: Write>(
+    input: &mut R,
+    n: u64,
+    delimiter: u8,
+    out: &mut W,
+) -> io::Result<()> {
+    let mut tails: VecDeque<Vec<u8>> = VecDeque::new();
 
     loop {
-        let mut buf_str = String::new();
-        let num_bytes = buf_f
-            .read_line(&mut buf_str)
-            .unwrap_or_else(|why| panic!("error while reading file: {}", why.to_string()));
+        let mut buf = Vec::new();
+        let num_bytes = input.read_until(delimiter, &mut buf)?;
 
         if num_bytes == 0 {
             break;
         }
+        if n == 0 {
+            continue;
+        }
 
-        if tails.len() == n {
+        if tails.len() as u64 == n {
             tails.pop_front().unwrap();
         }
         // cloneするより所有権を移してしまうほうが早い
-        tails.push_back(buf_str);
+        tails.push_back(buf);
     }
 
-    let stdout = io::stdout();
-    let mut buf_out = BufWriter::new(stdout.lock());
+    for line in tails {
+        out.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+fn last_bytes<R: Read, W: Write>(input: &mut R, n: u64, out: &mut W) -> io::Result<()> {
+    let n = usize::try_from(n).unwrap_or(usize::MAX);
+    let mut tail = Vec::new();
+    let mut buf = vec![0; BLOCK_SIZE];
 
-    let all_lines = tails
-        .into_iter()
-        .fold(String::new(), |before, line| before + &line);
+    loop {
+        let num_bytes = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(num_bytes) => num_bytes,
+            Err(ref why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => return Err(why),
+        };
+        tail.extend_from_slice(&buf[..num_bytes]);
+        // trim only once the buffer has doubled, so each byte is moved at most once
+        if tail.len() >= n.saturating_mul(2).max(BLOCK_SIZE) {
+            tail.drain(..tail.len() - n);
+        }
+    }
 
-    buf_out
-        .write_all(all_lines.as_bytes())
-        .unwrap_or_else(|why| panic!("error while writing input string: {}", why.to_string()));
+    let start = tail.len().saturating_sub(n);
+    out.write_all(&tail[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const COUNTS: [u64; 7] = [0, 1, 2, 10, 1000, 5000, 100_000];
+
+    // Reading backwards and reading the whole file must give the same output.
+    fn check(name: &str, contents: &[u8]) {
+        let path = env::temp_dir().join(format!("tail-test-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+
+        for &unit in &[Unit::Lines, Unit::Bytes] {
+            for &delimiter in b"\n\0" {
+                for &n in &COUNTS {
+                    for &count in &[Count::Last(n), Count::From(n.max(1))] {
+                        let opts = TailOptions {
+                            unit,
+                            count,
+                            delimiter,
+                        };
+
+                        let mut seek_out = Vec::new();
+                        let mut f = File::open(&path).unwrap();
+                        tail_seek(&mut f, &opts, &mut seek_out).unwrap();
+
+                        let mut stream_out = Vec::new();
+                        tail_stream(File::open(&path).unwrap(), &opts, &mut stream_out).unwrap();
+
+                        assert!(
+                            seek_out == stream_out,
+                            "{}: {:?} {:?} delimiter {}",
+                            name,
+                            unit,
+                            count,
+                            delimiter
+                        );
+                    }
+                }
+            }
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn numbered_lines(count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn empty_file() {
+        check("empty", b"");
+        check("newline", b"\n");
+        check("blank-lines", b"\n\n\n");
+    }
+
+    #[test]
+    fn many_lines() {
+        // spans several blocks
+        check("many", &numbered_lines(3000));
+    }
+
+    #[test]
+    fn without_final_newline() {
+        let mut contents = numbered_lines(3000);
+        contents.extend_from_slice(b"no newline");
+        check("unterminated", &contents);
+        check("single", b"no newline");
+    }
+
+    #[test]
+    fn lines_longer_than_a_block() {
+        let mut contents = Vec::new();
+        for len in &[
+            1,
+            BLOCK_SIZE - 1,
+            BLOCK_SIZE,
+            BLOCK_SIZE + 1,
+            3 * BLOCK_SIZE,
+            5,
+        ] {
+            contents.extend(std::iter::repeat_n(b'x', *len));
+            contents.push(b'\n');
+        }
+        check("long", &contents);
+    }
+
+    #[test]
+    fn newline_on_block_boundary() {
+        let mut contents = vec![b'a'; BLOCK_SIZE * 2];
+        contents[BLOCK_SIZE - 1] = b'\n';
+        contents[BLOCK_SIZE] = b'\n';
+        contents[2 * BLOCK_SIZE - 1] = b'\n';
+        check("boundary", &contents);
+    }
+
+    #[test]
+    fn not_utf8() {
+        let mut contents = Vec::new();
+        for i in 0..2000u32 {
+            contents.extend_from_slice(&[0xff, 0xfe, (i % 256) as u8, 0, b'\n']);
+        }
+        check("binary", &contents);
+    }
 }
@@ -0,0 +1,447 @@
+// sh の算術展開. $(( )) の中の式を C の演算子の優先順位で 64 ビット整数として評価する.
+
+use crate::vars::Variables;
+
+#[derive(Clone)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// longest first
+const OPERATORS: [&str; 38] = [
+    "<<=", ">>=", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=",
+    "+=", "-=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?",
+    ":", "=", "(", ")", ",",
+];
+
+const ASSIGNMENTS: [&str; 11] = [
+    "=", "*=", "/=", "%=", "+=", "-=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+// the binary operators from the lowest precedence to the highest
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a mut Variables,
+}
+
+// Evaluate an expression whose parameters have been expanded. A NAME in it
+// is the value of the variable, 0 if it is unset or empty.
+pub fn evaluate(expr: &str, vars: &mut Variables) -> Result<i64, String> {
+    let fail = |msg: String| format!("{}: {}", expr.trim(), msg);
+    let tokens = tokenize(expr).map_err(fail)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut evaluator = Evaluator {
+        tokens,
+        pos: 0,
+        vars,
+    };
+    let value = evaluator.comma(true).map_err(fail)?;
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(token) => Err(fail(format!(
+            "syntax error in expression (error token is \"{}\")",
+            token
+        ))),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let end = (i..chars.len())
+                .find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '_'))
+                .unwrap_or(chars.len());
+            let word: String = chars[i..end].iter().collect();
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(parse_number(&word)?)
+            } else {
+                Token::Name(word)
+            });
+            i = end;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| {
+                    format!(
+                        "syntax error: invalid arithmetic operator (error token is \"{}\")",
+                        c
+                    )
+                })?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+// 0x1f in hexadecimal, 017 in octal, 15 in decimal
+fn parse_number(word: &str) -> Result<i64, String> {
+    let result = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if word.len() > 1 && word.starts_with('0') {
+        i64::from_str_radix(&word[1..], 8)
+    } else {
+        word.parse()
+    };
+    result.map_err(|why| match why.kind() {
+        std::num::IntErrorKind::PosOverflow => format!("{}: integer overflow", word),
+        _ => format!("{}: invalid number", word),
+    })
+}
+
+impl<'a> Evaluator<'a> {
+    // `active` is false in the operand that && || or ?: does not evaluate:
+    // it is still parsed, but neither assigns nor fails.
+    fn comma(&mut self, active: bool) -> Result<i64, String> {
+        let mut value = self.assignment(active)?;
+        while self.eat(",") {
+            value = self.assignment(active)?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self, active: bool) -> Result<i64, String> {
+        if let (Some(Token::Name(name)), Some(&Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if ASSIGNMENTS.contains(&op) {
+                let name = name.clone();
+                self.pos += 2;
+                let right = self.assignment(active)?;
+                if !active {
+                    return Ok(0);
+                }
+                let value = match op {
+                    "=" => right,
+                    _ => apply(&op[..op.len() - 1], self.variable(&name)?, right)?,
+                };
+                self.vars.set(&name, &value.to_string());
+                return Ok(value);
+            }
+        }
+        self.conditional(active)
+    }
+
+    fn conditional(&mut self, active: bool) -> Result<i64, String> {
+        let condition = self.binary(0, active)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.comma(active && condition != 0)?;
+        self.expect(":")?;
+        let otherwise = self.conditional(active && condition == 0)?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn binary(&mut self, level: usize, active: bool) -> Result<i64, String> {
+        if level == LEVELS.len() {
+            return self.unary(active);
+        }
+
+        let mut left = self.binary(level + 1, active)?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos).cloned() {
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right_active = match op {
+                "&&" => active && left != 0,
+                "||" => active && left == 0,
+                _ => active,
+            };
+            let right = self.binary(level + 1, right_active)?;
+            left = if active { apply(op, left, right)? } else { 0 };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self, active: bool) -> Result<i64, String> {
+        let op = match self.tokens.get(self.pos) {
+            Some(&Token::Op(op)) if ["+", "-", "!", "~", "++", "--"].contains(&op) => op,
+            _ => return self.postfix(active),
+        };
+        self.pos += 1;
+
+        if op == "++" || op == "--" {
+            let name = self.name()?;
+            if !active {
+                return Ok(0);
+            }
+            let value = apply(&op[..1], self.variable(&name)?, 1)?;
+            self.vars.set(&name, &value.to_string());
+            return Ok(value);
+        }
+
+        let value = self.unary(active)?;
+        match op {
+            "-" if active => value.checked_neg().ok_or_else(overflow),
+            "!" => Ok((value == 0) as i64),
+            "~" => Ok(!value),
+            _ => Ok(value),
+        }
+    }
+
+    fn postfix(&mut self, active: bool) -> Result<i64, String> {
+        let name = match self.tokens.get(self.pos) {
+            Some(Token::Name(name)) => name.clone(),
+            _ => return self.primary(active),
+        };
+        self.pos += 1;
+        let value = self.variable(&name)?;
+
+        for op in &["++", "--"] {
+            if self.eat(op) {
+                if active {
+                    let next = apply(&op[..1], value, 1)?;
+                    self.vars.set(&name, &next.to_string());
+                }
+                break;
+            }
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self, active: bool) -> Result<i64, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let value = self.comma(active)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(token) => Err(format!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token
+            )),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        match self.vars.get(name) {
+            Some(value) if !value.trim().is_empty() => {
+                let value = value.trim();
+                match value.strip_prefix('-') {
+                    Some(digits) => parse_number(digits)?.checked_neg().ok_or_else(overflow),
+                    None => parse_number(value.strip_prefix('+').unwrap_or(value)),
+                }
+            }
+            _ => Ok(0),
+        }
+    }
+
+    // the NAME that ++ or -- applies to
+    fn name(&mut self) -> Result<String, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                Ok(name.clone())
+            }
+            _ => Err("syntax error: variable expected".to_string()),
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(Token::Op(other)) if *other == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            Err(format!("syntax error: `{}' expected", op))
+        }
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    let value = match op {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_string()),
+        "/" => left.checked_div(right),
+        "%" => left.checked_rem(right),
+        "<<" | ">>" if !(0..64).contains(&right) => {
+            return Err(format!("{}: shift count out of range", right))
+        }
+        // bits shifted out of the sign are an overflow too
+        "<<" => Some(left << right).filter(|shifted| shifted >> right == left),
+        ">>" => Some(left >> right),
+        "<" => Some((left < right) as i64),
+        "<=" => Some((left <= right) as i64),
+        ">" => Some((left > right) as i64),
+        ">=" => Some((left >= right) as i64),
+        "==" => Some((left == right) as i64),
+        "!=" => Some((left != right) as i64),
+        "&" => Some(left & right),
+        "^" => Some(left ^ right),
+        "|" => Some(left | right),
+        "&&" => Some((left != 0 && right != 0) as i64),
+        "||" => Some((left != 0 || right != 0) as i64),
+        _ => unreachable!(),
+    };
+    value.ok_or_else(overflow)
+}
+
+fn overflow() -> String {
+    "integer overflow".to_string()
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str) -> Result<i64, String> {
+        evaluate(expr, &mut Variables::from_env())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("5 - 3 - 1"), Ok(1));
+        assert_eq!(eval("2 + 3 << 1"), Ok(10));
+        assert_eq!(eval("1 < 2 == 1"), Ok(1));
+        assert_eq!(eval("7 & 3 | 8 ^ 1"), Ok(3 | 9));
+        assert_eq!(eval("1 || 0 && 0"), Ok(1));
+        assert_eq!(eval("-2 * -3 % 4"), Ok(2));
+        assert_eq!(eval("!0 + ~0"), Ok(0));
+        assert_eq!(eval("0 ? 1 : 0 ? 2 : 3"), Ok(3));
+        assert_eq!(eval("1, 2"), Ok(2));
+        assert_eq!(eval("0x1f + 017 + 10"), Ok(31 + 15 + 10));
+        assert_eq!(eval(""), Ok(0));
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(eval("9223372036854775807"), Ok(i64::MAX));
+        assert!(eval("9223372036854775808").is_err());
+        assert_eq!(
+            eval("(-9223372036854775807 - 1) / -1"),
+            Err("(-9223372036854775807 - 1) / -1: integer overflow".to_string())
+        );
+        assert!(eval("(-9223372036854775807 - 1) % -1").is_err());
+        assert!(eval("9223372036854775807 + 1").is_err());
+        assert!(eval("-(-9223372036854775807 - 1)").is_err());
+        assert_eq!(eval("-1 << 63"), Ok(i64::MIN));
+        assert!(eval("1 << 63").is_err());
+        assert!(eval("3 << 62").is_err());
+        assert_eq!(
+            eval("1 << 64"),
+            Err("1 << 64: 64: shift count out of range".to_string())
+        );
+        assert!(eval("1 >> -1").is_err());
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0"), Err("1 / 0: division by 0".to_string()));
+        assert!(eval("1 % 0").is_err());
+        let mut vars = Variables::from_env();
+        vars.set("arith_x", "4");
+        assert!(evaluate("arith_x /= 0", &mut vars).is_err());
+        assert_eq!(vars.get("arith_x").as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn short_circuit() {
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+        assert_eq!(eval("1 || 1 / 0"), Ok(1));
+        assert_eq!(eval("1 ? 2 : 1 / 0"), Ok(2));
+        assert_eq!(eval("0 ? 1 / 0 : 3"), Ok(3));
+        assert!(eval("1 && 1 / 0").is_err());
+
+        let mut vars = Variables::from_env();
+        vars.set("arith_x", "1");
+        assert_eq!(evaluate("0 && (arith_x = 5)", &mut vars), Ok(0));
+        assert_eq!(evaluate("1 || arith_x++", &mut vars), Ok(1));
+        assert_eq!(evaluate("0 ? arith_x += 1 : 0", &mut vars), Ok(0));
+        assert_eq!(vars.get("arith_x").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn assignments() {
+        let mut vars = Variables::from_env();
+        let mut step = |expr: &str| evaluate(expr, &mut vars).unwrap();
+        assert_eq!(step("arith_x = 5"), 5);
+        assert_eq!(step("arith_x += 2"), 7);
+        assert_eq!(step("arith_x -= 1"), 6);
+        assert_eq!(step("arith_x *= 3"), 18);
+        assert_eq!(step("arith_x /= 4"), 4);
+        assert_eq!(step("arith_x %= 3"), 1);
+        assert_eq!(step("arith_x <<= 4"), 16);
+        assert_eq!(step("arith_x >>= 1"), 8);
+        assert_eq!(step("arith_x |= 3"), 11);
+        assert_eq!(step("arith_x &= 6"), 2);
+        assert_eq!(step("arith_x ^= 7"), 5);
+        assert_eq!(step("arith_y = arith_x = 1"), 1);
+        assert_eq!(step("arith_x++ + ++arith_y"), 3);
+        assert_eq!(step("arith_x-- * 10 + arith_y"), 22);
+        assert_eq!(step("--arith_x"), 0);
+        assert_eq!(vars.get("arith_y").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn variables() {
+        let mut vars = Variables::from_env();
+        vars.set("arith_x", " -0x10 ");
+        vars.set("arith_empty", "");
+        vars.set("arith_bad", "12abc");
+        assert_eq!(evaluate("arith_x + 1", &mut vars), Ok(-15));
+        assert_eq!(evaluate("arith_empty + arith_unset", &mut vars), Ok(0));
+        assert!(evaluate("arith_bad", &mut vars).is_err());
+        assert!(evaluate("1 +", &mut vars).is_err());
+        assert!(evaluate("(1", &mut vars).is_err());
+        assert!(evaluate("1 2", &mut vars).is_err());
+        assert!(evaluate("1 @ 2", &mut vars).is_err());
+    }
+}
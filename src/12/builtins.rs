@@ -0,0 +1,337 @@
+// sh の組み込みコマンド. シェル自身の状態を変えるものはここで実行する.
+
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::{chdir, tcsetpgrp, Pid};
+use std::convert::TryFrom;
+use std::process::exit;
+use std::str::FromStr;
+
+use crate::jobs::State;
+use crate::{describe, vars, Shell};
+
+pub const NAMES: [&str; 10] = [
+    "cd", "exit", "export", "unset", "set", "jobs", "fg", "bg", "wait", "kill",
+];
+
+// the special builtins: NAME=value in front of one of them stays set
+const SPECIAL: [&str; 4] = ["exit", "export", "unset", "set"];
+
+pub fn is_builtin(name: &str) -> bool {
+    NAMES.contains(&name)
+}
+
+pub fn is_special(name: &str) -> bool {
+    SPECIAL.contains(&name)
+}
+
+impl Shell {
+    pub fn run_builtin(&mut self, args: &[String]) -> i32 {
+        match args.first().map(|arg| arg.as_str()) {
+            Some("cd") => exec_chdir(args),
+            Some("exit") => exec_exit(args, self.vars.status),
+            Some("export") => self.exec_export(args),
+            Some("unset") => self.exec_unset(args),
+            Some("set") => self.exec_set(args),
+            Some("jobs") => self.exec_jobs(args),
+            Some("fg") => self.exec_fg(args),
+            Some("bg") => self.exec_bg(args),
+            Some("wait") => self.exec_wait(args),
+            Some("kill") => self.exec_kill(args),
+            _ => 0,
+        }
+    }
+
+    // export [NAME[=value]...]: without a NAME, list the exported variables
+    fn exec_export(&mut self, args: &[String]) -> i32 {
+        if args.len() == 1 {
+            for (name, value) in self.vars.exported() {
+                println!("export {}='{}'", name, value.replace('\'', "'\\''"));
+            }
+            return 0;
+        }
+
+        let mut status = 0;
+        for arg in &args[1..] {
+            let (name, value) = match arg.find('=') {
+                Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+                None => (arg.as_str(), None),
+            };
+            if !vars::is_name(name) {
+                eprintln!("{}: `{}': not a valid identifier", args[0], arg);
+                status = 1;
+                continue;
+            }
+            if let Some(value) = value {
+                self.vars.set(name, value);
+            }
+            self.vars.export(name);
+        }
+
+        status
+    }
+
+    fn exec_unset(&mut self, args: &[String]) -> i32 {
+        for name in &args[1..] {
+            self.vars.unset(name);
+        }
+        0
+    }
+
+    // set [-o | +o] OPTION...: without an OPTION, list the options
+    fn exec_set(&mut self, args: &[String]) -> i32 {
+        if args.len() == 1 || args[1..] == ["-o"] {
+            println!(
+                "nullglob       {}",
+                if self.vars.nullglob { "on" } else { "off" }
+            );
+            return 0;
+        }
+
+        let mut status = 0;
+        for pair in args[1..].chunks(2) {
+            let on = match pair[0].as_str() {
+                "-o" => true,
+                "+o" => false,
+                flag => {
+                    eprintln!("{}: {}: invalid option", args[0], flag);
+                    return 2;
+                }
+            };
+            match pair.get(1).map(|name| name.as_str()) {
+                Some("nullglob") => self.vars.nullglob = on,
+                Some(name) => {
+                    eprintln!("{}: {}: invalid option name", args[0], name);
+                    status = 1;
+                }
+                None => {
+                    eprintln!("{}: usage: set [-o | +o] option...", args[0]);
+                    return 2;
+                }
+            }
+        }
+
+        status
+    }
+
+    // jobs [%job...]
+    fn exec_jobs(&mut self, args: &[String]) -> i32 {
+        self.jobs.poll();
+        let ids = if args.len() == 1 {
+            self.jobs.ids()
+        } else {
+            match self.find_jobs(&args[0], &args[1..]) {
+                Some(ids) => ids,
+                None => return 1,
+            }
+        };
+
+        for id in ids {
+            println!("{}", self.jobs.report(id));
+        }
+        0
+    }
+
+    // fg [%job]: continue a job in the foreground and wait for it
+    fn exec_fg(&mut self, args: &[String]) -> i32 {
+        if !self.job_control {
+            eprintln!("{}: no job control", args[0]);
+            return 1;
+        }
+        let id = match self.find_jobs(&args[0], args.get(1..2).unwrap_or(&[])) {
+            Some(ids) => ids[0],
+            None => return 1,
+        };
+
+        let mut job = self.jobs.remove(id).unwrap();
+        println!("{}", job.text);
+        // the terminal first, so that the job does not stop again on reading it
+        tcsetpgrp(0, job.pgid).ok();
+        killpg(job.pgid, Signal::SIGCONT).ok();
+        job.continued();
+
+        self.wait_foreground(job)
+    }
+
+    // bg [%job...]: continue stopped jobs in the background
+    fn exec_bg(&mut self, args: &[String]) -> i32 {
+        if !self.job_control {
+            eprintln!("{}: no job control", args[0]);
+            return 1;
+        }
+        let ids = match self.find_jobs(&args[0], &args[1..]) {
+            Some(ids) => ids,
+            None => return 1,
+        };
+
+        for id in ids {
+            let job = self.jobs.get_mut(id).unwrap();
+            killpg(job.pgid, Signal::SIGCONT).ok();
+            job.continued();
+            println!("[{}] {} &", id, job.text);
+        }
+        0
+    }
+
+    // wait [%job | pid...]: without an argument, wait for every job
+    fn exec_wait(&mut self, args: &[String]) -> i32 {
+        let ids = if args.len() == 1 {
+            self.jobs.ids()
+        } else {
+            let mut ids = Vec::new();
+            for arg in &args[1..] {
+                let id = match arg.parse::<i32>() {
+                    Ok(pid) => self.jobs.by_pid(Pid::from_raw(pid)),
+                    Err(_) => self.jobs.find(arg).ok(),
+                };
+                match id {
+                    Some(id) => ids.push(id),
+                    None => {
+                        eprintln!("{}: {}: no such job or child of this shell", args[0], arg);
+                        return 127;
+                    }
+                }
+            }
+            ids
+        };
+
+        let mut status = 0;
+        for id in ids {
+            let job = self.jobs.get_mut(id).unwrap();
+            job.wait(true);
+            let state = job.state();
+            if state != State::Stopped {
+                self.jobs.remove(id);
+            }
+            status = state.status();
+        }
+
+        // without arguments the status is 0, whatever the jobs exited with
+        if args.len() == 1 {
+            0
+        } else {
+            status
+        }
+    }
+
+    // kill [-s SIGNAL | -SIGNAL] %job | pid...
+    fn exec_kill(&mut self, args: &[String]) -> i32 {
+        let (signal, targets) = match args.get(1).map(|arg| arg.as_str()) {
+            Some("-s") => (args.get(2).map(|name| name.as_str()), args.get(3..)),
+            Some(arg) if arg.starts_with('-') && arg.len() > 1 => (Some(&arg[1..]), args.get(2..)),
+            _ => (Some("TERM"), args.get(1..)),
+        };
+        let targets = match targets {
+            Some(targets) if !targets.is_empty() => targets,
+            _ => {
+                eprintln!(
+                    "{}: usage: kill [-s SIGNAL | -SIGNAL] pid | %job ...",
+                    args[0]
+                );
+                return 2;
+            }
+        };
+        let signal = match signal.map(parse_signal) {
+            Some(Some(signal)) => signal,
+            _ => {
+                eprintln!("{}: {}: invalid signal", args[0], signal.unwrap_or(""));
+                return 1;
+            }
+        };
+
+        let mut status = 0;
+        for target in targets {
+            let result = if target.starts_with('%') {
+                self.jobs.find(target).and_then(|id| {
+                    let job = self.jobs.get_mut(id).unwrap();
+                    killpg(job.pgid, signal).map_err(describe)?;
+                    // a stopped job only gets the signal once it runs again
+                    if job.state() == State::Stopped && signal != Some(Signal::SIGKILL) {
+                        killpg(job.pgid, Signal::SIGCONT).ok();
+                    }
+                    Ok(())
+                })
+            } else {
+                match target.parse::<i32>() {
+                    Ok(pid) => kill(Pid::from_raw(pid), signal).map_err(describe),
+                    Err(_) => Err("arguments must be process or job IDs".to_string()),
+                }
+            };
+
+            if let Err(msg) = result {
+                eprintln!("{}: {}: {}", args[0], target, msg);
+                status = 1;
+            }
+        }
+
+        status
+    }
+
+    // The jobs named by `specs`, or the current job if there are none.
+    fn find_jobs(&self, builtin: &str, specs: &[String]) -> Option<Vec<usize>> {
+        let current = ["%+".to_string()];
+        let specs = if specs.is_empty() {
+            &current[..]
+        } else {
+            specs
+        };
+
+        let mut ids = Vec::new();
+        for spec in specs {
+            match self.jobs.find(spec) {
+                Ok(id) => ids.push(id),
+                Err(msg) => {
+                    eprintln!("{}: {}", builtin, msg);
+                    return None;
+                }
+            }
+        }
+        Some(ids)
+    }
+}
+
+// 9, KILL or SIGKILL; 0 only checks that the process exists
+fn parse_signal(name: &str) -> Option<Option<Signal>> {
+    if let Ok(number) = name.parse::<i32>() {
+        return match number {
+            0 => Some(None),
+            _ => Signal::try_from(number).ok().map(Some),
+        };
+    }
+
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    Signal::from_str(&name).ok().map(Some)
+}
+
+fn exec_chdir(args: &[String]) -> i32 {
+    match args.len() {
+        1 => 0,
+        2 => match chdir(args[1].as_str()) {
+            Ok(()) => 0,
+            Err(why) => {
+                eprintln!("{}: {}: {}", args[0], args[1], describe(why));
+                1
+            }
+        },
+        _ => {
+            eprintln!("{}: too many arguments", args[0]);
+            1
+        }
+    }
+}
+
+// exit [n]: without n, exit with the status of the last command
+fn exec_exit(args: &[String], status: i32) -> ! {
+    let code = match args.get(1) {
+        None => status,
+        Some(arg) => arg.parse::<i32>().map(|n| n & 0xff).unwrap_or_else(|_| {
+            eprintln!("{}: {}: numeric argument required", args[0], arg);
+            2
+        }),
+    };
+    exit(code);
+}
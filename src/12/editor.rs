@@ -0,0 +1,788 @@
+// sh の行編集. 端末では raw モードで 1 文字ずつ読み, Emacs 風のキーで編集する.
+// 端末でなければ 1 行ずつそのまま読む.
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::termios::{
+    tcgetattr, tcsetattr, InputFlags, LocalFlags, SetArg, SpecialCharacterIndices, Termios,
+};
+use nix::unistd::{isatty, read};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+
+use crate::builtins;
+
+const HISTORY_FILE: &str = ".rust_sh_history";
+const HISTORY_SIZE: usize = 1000;
+// how long to wait for the rest of an escape sequence after ESC
+const ESCAPE_TIMEOUT_MS: i32 = 50;
+
+pub enum Input {
+    // a line with its newline
+    Line(String),
+    // the line was thrown away: ^C, or a !n that is not in the history
+    Cancelled,
+    Eof,
+}
+
+pub struct Editor {
+    tty: bool,
+    reader: BufReader<io::Stdin>,
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+    // the text last killed with ^K, ^U or ^W, for ^Y
+    killed: Vec<char>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    // ^A is Ctrl('a')
+    Ctrl(char),
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Unknown,
+}
+
+// the line being edited
+struct Line<'a> {
+    prompt: &'a str,
+    chars: Vec<char>,
+    cursor: usize,
+    // the history entry shown, history.len() for the line being typed
+    index: usize,
+    // the line being typed while going through the history
+    saved: Vec<char>,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        let tty = isatty(0).unwrap_or(false);
+        let mut editor = Editor {
+            tty,
+            reader: BufReader::new(io::stdin()),
+            history: Vec::new(),
+            history_path: None,
+            killed: Vec::new(),
+        };
+        if tty {
+            editor.load_history();
+        }
+        editor
+    }
+
+    fn load_history(&mut self) {
+        let path = match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(HISTORY_FILE),
+            None => return,
+        };
+        if let Ok(text) = fs::read_to_string(&path) {
+            self.history = text.lines().map(|line| line.to_string()).collect();
+            // sessions only append: cut the file back to what is kept
+            let excess = self.history.len().saturating_sub(HISTORY_SIZE);
+            if excess > 0 {
+                self.history.drain(..excess);
+                let mut text = self.history.join("\n");
+                text.push('\n');
+                fs::write(&path, text).ok();
+            }
+        }
+        self.history_path = Some(path);
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(|last| last.as_str()) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+
+        if let Some(ref path) = self.history_path {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o600)
+                .open(path);
+            if let Ok(mut file) = file {
+                writeln!(file, "{}", line).ok();
+            }
+        }
+    }
+
+    // Print the prompt and read a line. `path` is $PATH, for completing
+    // command names.
+    pub fn read_line(&mut self, prompt: &str, path: &str) -> io::Result<Input> {
+        if !self.tty {
+            print!("{}", prompt);
+            io::stdout().flush()?;
+            let mut line = String::new();
+            return match self.reader.read_line(&mut line)? {
+                0 => Ok(Input::Eof),
+                _ => Ok(Input::Line(line)),
+            };
+        }
+
+        let cooked = tcgetattr(0).map_err(nix_to_io)?;
+        tcsetattr(0, SetArg::TCSADRAIN, &raw_mode(&cooked)).map_err(nix_to_io)?;
+        let result = self.edit(prompt, path);
+        // back in cooked mode before an error from edit is returned, as
+        // the shell exits on one
+        tcsetattr(0, SetArg::TCSADRAIN, &cooked).map_err(nix_to_io)?;
+
+        let line = match result? {
+            Input::Line(line) => line,
+            input => return Ok(input),
+        };
+        let line = match self.expand_history(&line) {
+            Ok(Some(expanded)) => {
+                // show what is run
+                println!("{}", expanded);
+                expanded
+            }
+            Ok(None) => line,
+            Err(msg) => {
+                eprintln!("sh: {}", msg);
+                return Ok(Input::Cancelled);
+            }
+        };
+        self.add_history(&line);
+
+        Ok(Input::Line(line + "\n"))
+    }
+
+    // Edit a line in raw mode. The line comes without its newline.
+    fn edit(&mut self, prompt: &str, path: &str) -> io::Result<Input> {
+        let mut line = Line {
+            prompt,
+            chars: Vec::new(),
+            cursor: 0,
+            index: self.history.len(),
+            saved: Vec::new(),
+        };
+        line.refresh()?;
+
+        loop {
+            let mut key = read_key()?;
+            if key == Key::Ctrl('r') {
+                key = self.search(&mut line)?;
+            }
+
+            match key {
+                Key::Enter => {
+                    line.cursor = line.chars.len();
+                    line.refresh()?;
+                    put("\r\n")?;
+                    return Ok(Input::Line(line.chars.iter().collect()));
+                }
+                Key::Ctrl('c') => {
+                    put("^C\r\n")?;
+                    return Ok(Input::Cancelled);
+                }
+                Key::Ctrl('d') if line.chars.is_empty() => {
+                    put("\r\n")?;
+                    return Ok(Input::Eof);
+                }
+                Key::Char(c) => {
+                    line.chars.insert(line.cursor, c);
+                    line.cursor += 1;
+                }
+                Key::Ctrl('a') | Key::Home => line.cursor = 0,
+                Key::Ctrl('e') | Key::End => line.cursor = line.chars.len(),
+                Key::Ctrl('b') | Key::Left => line.cursor = line.cursor.saturating_sub(1),
+                Key::Ctrl('f') | Key::Right => {
+                    line.cursor = (line.cursor + 1).min(line.chars.len())
+                }
+                Key::Alt('b') => line.cursor = line.word_start(),
+                Key::Alt('f') => line.cursor = line.word_end(),
+                Key::Ctrl('h') | Key::Backspace if line.cursor > 0 => {
+                    line.cursor -= 1;
+                    line.chars.remove(line.cursor);
+                }
+                Key::Ctrl('d') | Key::Delete if line.cursor < line.chars.len() => {
+                    line.chars.remove(line.cursor);
+                }
+                Key::Ctrl('k') => self.killed = line.chars.split_off(line.cursor),
+                Key::Ctrl('u') => {
+                    self.killed = line.chars.drain(..line.cursor).collect();
+                    line.cursor = 0;
+                }
+                Key::Ctrl('w') => {
+                    let start = line.word_start();
+                    self.killed = line.chars.drain(start..line.cursor).collect();
+                    line.cursor = start;
+                }
+                Key::Alt('d') => {
+                    let end = line.word_end();
+                    self.killed = line.chars.drain(line.cursor..end).collect();
+                }
+                Key::Ctrl('y') => {
+                    for &c in self.killed.iter().rev() {
+                        line.chars.insert(line.cursor, c);
+                    }
+                    line.cursor += self.killed.len();
+                }
+                Key::Ctrl('t') if line.cursor > 0 && line.chars.len() > 1 => {
+                    // swap the two characters before the cursor, or around it
+                    if line.cursor == line.chars.len() {
+                        line.cursor -= 1;
+                    }
+                    line.chars.swap(line.cursor - 1, line.cursor);
+                    line.cursor += 1;
+                }
+                Key::Ctrl('l') => put("\x1b[H\x1b[2J")?,
+                Key::Ctrl('p') | Key::Up if line.index > 0 => {
+                    let index = line.index - 1;
+                    self.show_history(&mut line, index)
+                }
+                Key::Ctrl('n') | Key::Down if line.index < self.history.len() => {
+                    let index = line.index + 1;
+                    self.show_history(&mut line, index)
+                }
+                Key::Tab => self.complete(&mut line, path)?,
+                Key::Unknown => {}
+                _ => put("\x07")?,
+            }
+            line.refresh()?;
+        }
+    }
+
+    fn show_history(&self, line: &mut Line, index: usize) {
+        if line.index == self.history.len() {
+            line.saved = line.chars.clone();
+        }
+        line.chars = match self.history.get(index) {
+            Some(entry) => entry.chars().collect(),
+            None => line.saved.clone(),
+        };
+        line.index = index;
+        line.cursor = line.chars.len();
+    }
+
+    // ^R: search the history backwards for what is typed. Returns the key
+    // that ended the search, to be handled as usual on the line found.
+    fn search(&self, line: &mut Line) -> io::Result<Key> {
+        let original = (line.chars.clone(), line.cursor);
+        let mut query = String::new();
+        // the entry found, and where in it
+        let mut found: Option<(usize, usize)> = None;
+
+        loop {
+            let entry = found.map(|(index, _)| self.history[index].as_str());
+            let prefix: String = match (entry, found) {
+                (Some(entry), Some((_, pos))) => entry[..pos].to_string(),
+                _ => String::new(),
+            };
+            let status = format!("(reverse-i-search)`{}': ", query);
+            put(&format!(
+                "\r{}{}\x1b[K\r\x1b[{}C",
+                status,
+                entry.unwrap_or(""),
+                status.chars().count() + prefix.chars().count()
+            ))?;
+
+            // where to search from, the newest entry first
+            let from = match read_key()? {
+                Key::Char(c) => {
+                    query.push(c);
+                    found.map(|(index, _)| index).unwrap_or(self.history.len())
+                }
+                Key::Backspace | Key::Ctrl('h') => {
+                    query.pop();
+                    self.history.len()
+                }
+                Key::Ctrl('r') => found
+                    .map(|(index, _)| index.saturating_sub(1))
+                    .unwrap_or(self.history.len()),
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    line.chars = original.0;
+                    line.cursor = original.1;
+                    line.refresh()?;
+                    return Ok(Key::Unknown);
+                }
+                key => {
+                    if let Some((index, _)) = found {
+                        line.chars = self.history[index].chars().collect();
+                        line.cursor = line.chars.len();
+                        line.index = index;
+                    }
+                    line.refresh()?;
+                    return Ok(key);
+                }
+            };
+
+            let end = (from + 1).min(self.history.len());
+            found = self.history[..end]
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(index, entry)| entry.rfind(&query).map(|pos| (index, pos)))
+                .or(found);
+        }
+    }
+
+    // Tab: complete the word before the cursor, as a command name where a
+    // command starts and as a file name elsewhere. When it cannot be made
+    // longer, list what it could become.
+    fn complete(&self, line: &mut Line, path: &str) -> io::Result<()> {
+        let start = line.chars[..line.cursor]
+            .iter()
+            .rposition(|&c| " \t;|&<>(".contains(c))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word: String = line.chars[start..line.cursor]
+            .iter()
+            .filter(|&&c| c != '\\')
+            .collect();
+        let before: String = line.chars[..start].iter().collect();
+        let command =
+            before.trim_end().is_empty() || before.trim_end().ends_with(|c| ";|&(".contains(c));
+
+        let candidates = if command && !word.contains('/') {
+            complete_command(&word, path)
+        } else {
+            complete_file(&word)
+        };
+        if candidates.is_empty() {
+            return put("\x07");
+        }
+
+        let mut common = common_prefix(&candidates);
+        if candidates.len() == 1 && !common.ends_with('/') {
+            common.push(' ');
+        }
+
+        if common.len() > word.len() {
+            let replacement: Vec<char> = escape(&common).chars().collect();
+            let end = line.cursor;
+            line.cursor = start + replacement.len();
+            line.chars.splice(start..end, replacement);
+            return Ok(());
+        }
+        if candidates.len() == 1 {
+            return Ok(());
+        }
+
+        // only the part after the directory, as ls shows it
+        let names: Vec<&str> = candidates
+            .iter()
+            .map(|candidate| {
+                let trimmed = candidate.trim_end_matches('/');
+                match trimmed.rfind('/') {
+                    Some(i) => &candidate[i + 1..],
+                    None => candidate.as_str(),
+                }
+            })
+            .collect();
+        put(&format!("\r\n{}\r\n", names.join("  ")))
+    }
+
+    // !! is the previous command, !n command n of the history and !-n the
+    // n-th last. Returns the line with them replaced, if there were any.
+    fn expand_history(&self, line: &str) -> Result<Option<String>, String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut expanded = String::new();
+        let mut changed = false;
+        let mut quoted = false;
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\'' => quoted = !quoted,
+                '\\' if !quoted => {
+                    expanded.extend(chars.get(i..i + 2).unwrap_or(&chars[i..]));
+                    i += 2;
+                    continue;
+                }
+                '!' if !quoted => {
+                    let end = match chars.get(i + 1) {
+                        Some('!') => i + 2,
+                        Some('-') => i + 2 + digits(&chars[i + 2..]),
+                        _ => i + 1 + digits(&chars[i + 1..]),
+                    };
+                    let event: String = chars[i..end].iter().collect();
+                    let index = match &event[1..] {
+                        "" | "-" => None,
+                        "!" => self.history.len().checked_sub(1),
+                        number => match number.parse::<isize>() {
+                            Ok(n) if n < 0 => self.history.len().checked_sub(-n as usize),
+                            Ok(n) => (n as usize).checked_sub(1),
+                            Err(_) => None,
+                        },
+                    };
+                    if event.len() > 1 && event != "!-" {
+                        match index.and_then(|index| self.history.get(index)) {
+                            Some(entry) => expanded.push_str(entry),
+                            None => return Err(format!("{}: event not found", event)),
+                        }
+                        changed = true;
+                        i = end;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            expanded.push(chars[i]);
+            i += 1;
+        }
+
+        Ok(Some(expanded).filter(|_| changed))
+    }
+}
+
+impl<'a> Line<'a> {
+    // Redraw the line and put the cursor back where it is.
+    fn refresh(&self) -> io::Result<()> {
+        let text: String = self.chars.iter().collect();
+        let column = self.prompt.chars().count() + self.cursor;
+        let mut out = format!("\r{}{}\x1b[K\r", self.prompt, text);
+        if column > 0 {
+            out.push_str(&format!("\x1b[{}C", column));
+        }
+        put(&out)
+    }
+
+    // the start of the word before the cursor, skipping blanks
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.chars.len() && self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+}
+
+// No echo, no line buffering and no signals from ^C or ^Z: the editor
+// handles every key itself.
+fn raw_mode(cooked: &Termios) -> Termios {
+    let mut raw = cooked.clone();
+    raw.input_flags &= !(InputFlags::ICRNL
+        | InputFlags::IXON
+        | InputFlags::BRKINT
+        | InputFlags::INPCK
+        | InputFlags::ISTRIP);
+    raw.local_flags &=
+        !(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+    raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+    raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+    raw
+}
+
+fn read_byte() -> io::Result<u8> {
+    let mut buf = [0; 1];
+    loop {
+        match read(0, &mut buf) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => return Ok(buf[0]),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(why) => return Err(nix_to_io(why)),
+        }
+    }
+}
+
+// Whether more input follows soon, as it does after the ESC of an escape
+// sequence but not after the Esc key.
+fn pending() -> bool {
+    let mut fds = [PollFd::new(0, PollFlags::POLLIN)];
+    matches!(poll(&mut fds, ESCAPE_TIMEOUT_MS), Ok(n) if n > 0)
+}
+
+fn read_key() -> io::Result<Key> {
+    let byte = read_byte()?;
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f => Key::Backspace,
+        0x1b => {
+            if !pending() {
+                return Ok(Key::Unknown);
+            }
+            match read_byte()? {
+                b'[' => read_csi()?,
+                b'O' => match read_byte()? {
+                    b'A' => Key::Up,
+                    b'B' => Key::Down,
+                    b'C' => Key::Right,
+                    b'D' => Key::Left,
+                    b'H' => Key::Home,
+                    b'F' => Key::End,
+                    _ => Key::Unknown,
+                },
+                c if c.is_ascii_alphabetic() => Key::Alt(c.to_ascii_lowercase() as char),
+                _ => Key::Unknown,
+            }
+        }
+        0..=0x1f => Key::Ctrl((byte + 0x60) as char),
+        _ => {
+            // the rest of a UTF-8 sequence
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match String::from_utf8(bytes) {
+                Ok(text) => Key::Char(text.chars().next().unwrap()),
+                Err(_) => Key::Unknown,
+            }
+        }
+    };
+    Ok(key)
+}
+
+// ESC [ then parameters and a final byte: ESC [ A is up, ESC [ 3 ~ delete.
+fn read_csi() -> io::Result<Key> {
+    let mut params = String::new();
+    loop {
+        let byte = read_byte()?;
+        if (0x40..=0x7e).contains(&byte) {
+            return Ok(match (params.as_str(), byte) {
+                ("", b'A') => Key::Up,
+                ("", b'B') => Key::Down,
+                ("", b'C') => Key::Right,
+                ("", b'D') => Key::Left,
+                ("", b'H') | ("1", b'~') | ("7", b'~') => Key::Home,
+                ("", b'F') | ("4", b'~') | ("8", b'~') => Key::End,
+                ("3", b'~') => Key::Delete,
+                _ => Key::Unknown,
+            });
+        }
+        params.push(byte as char);
+    }
+}
+
+fn put(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()
+}
+
+// builtins and the executables in $PATH that start with `word`
+fn complete_command(word: &str, path: &str) -> Vec<String> {
+    let mut names: Vec<String> = builtins::NAMES
+        .iter()
+        .filter(|name| name.starts_with(word))
+        .map(|name| name.to_string())
+        .collect();
+
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(word) {
+                continue;
+            }
+            let executable = entry
+                .path()
+                .metadata()
+                .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if executable {
+                names.push(name);
+            }
+        }
+    }
+
+    names.sort();
+    names.dedup();
+    names
+}
+
+// The paths that start with `word`, with a / after directories. Hidden
+// files only when `word` names them.
+fn complete_file(word: &str) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..i + 1], &word[i + 1..]),
+        None => ("", word),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut paths: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+// a backslash before the characters the shell would otherwise take apart
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in text.char_indices() {
+        // the space added after a complete word stays a separator
+        if " \t\\'\"$&;|<>()*?[]{}~#`!".contains(c) && !(c == ' ' && i == text.len() - 1) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// what all the candidates start with
+fn common_prefix(candidates: &[String]) -> String {
+    let mut common = candidates[0].clone();
+    for candidate in &candidates[1..] {
+        let len = common
+            .chars()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum();
+        common.truncate(len);
+    }
+    common
+}
+
+fn digits(chars: &[char]) -> usize {
+    chars.iter().take_while(|c| c.is_ascii_digit()).count()
+}
+
+fn nix_to_io(why: nix::Error) -> io::Error {
+    match why.as_errno() {
+        Some(errno) => io::Error::from_raw_os_error(errno as i32),
+        None => io::Error::other(why.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(history: &[&str]) -> Editor {
+        Editor {
+            tty: false,
+            reader: BufReader::new(io::stdin()),
+            history: history.iter().map(|entry| entry.to_string()).collect(),
+            history_path: None,
+            killed: Vec::new(),
+        }
+    }
+
+    fn line(text: &str, cursor: usize) -> Line<'static> {
+        Line {
+            prompt: "$ ",
+            chars: text.chars().collect(),
+            cursor,
+            index: 0,
+            saved: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn history_events() {
+        let editor = editor(&["ls", "cd /tmp", "make"]);
+        let expand = |line: &str| editor.expand_history(line);
+        assert_eq!(expand("!!"), Ok(Some("make".to_string())));
+        assert_eq!(
+            expand("sudo !! && !1"),
+            Ok(Some("sudo make && ls".to_string()))
+        );
+        assert_eq!(expand("!2x"), Ok(Some("cd /tmpx".to_string())));
+        assert_eq!(expand("!-2"), Ok(Some("cd /tmp".to_string())));
+        assert_eq!(expand("!-1!-3"), Ok(Some("makels".to_string())));
+    }
+
+    #[test]
+    fn history_not_expanded() {
+        let editor = editor(&["ls"]);
+        let expand = |line: &str| editor.expand_history(line);
+        assert_eq!(expand("echo '!!'"), Ok(None));
+        assert_eq!(expand("echo \\!!"), Ok(None));
+        assert_eq!(expand("[ a != b ]"), Ok(None));
+        assert_eq!(expand("echo ! !-"), Ok(None));
+        assert_eq!(expand("echo '!!' !!"), Ok(Some("echo '!!' ls".to_string())));
+    }
+
+    #[test]
+    fn event_not_found() {
+        let one = editor(&["ls"]);
+        assert_eq!(
+            one.expand_history("!2"),
+            Err("!2: event not found".to_string())
+        );
+        assert_eq!(
+            one.expand_history("!-2"),
+            Err("!-2: event not found".to_string())
+        );
+        assert_eq!(
+            one.expand_history("!0"),
+            Err("!0: event not found".to_string())
+        );
+        assert!(editor(&[]).expand_history("!!").is_err());
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("plain.txt"), "plain.txt");
+        assert_eq!(escape("a b"), "a\\ b");
+        assert_eq!(escape("it's (1)"), "it\\'s\\ \\(1\\)");
+        // the space after a completed word
+        assert_eq!(escape("a b "), "a\\ b ");
+        assert_eq!(escape("$x*"), "\\$x\\*");
+    }
+
+    #[test]
+    fn words() {
+        let typed = line("git commit  -m msg", 12);
+        assert_eq!(typed.word_start(), 4);
+        assert_eq!(typed.word_end(), 14);
+        assert_eq!(line("abc", 0).word_start(), 0);
+        assert_eq!(line("abc", 3).word_end(), 3);
+        assert_eq!(line("a-b", 3).word_start(), 2);
+        assert_eq!(line("  é1 x", 0).word_end(), 4);
+    }
+
+    #[test]
+    fn common_prefixes() {
+        let strings =
+            |items: &[&str]| -> Vec<String> { items.iter().map(|item| item.to_string()).collect() };
+        assert_eq!(common_prefix(&strings(&["cargo"])), "cargo");
+        assert_eq!(common_prefix(&strings(&["cat", "cargo", "cal"])), "ca");
+        assert_eq!(common_prefix(&strings(&["dir/a", "dir/b"])), "dir/");
+        assert_eq!(common_prefix(&strings(&["x", "y"])), "");
+        assert_eq!(common_prefix(&strings(&["éa", "éb"])), "é");
+    }
+}
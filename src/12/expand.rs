@@ -0,0 +1,839 @@
+// sh の単語展開. ブレース展開, チルダ展開, パラメータ展開, フィールド分割,
+// パス名展開, 引用符の除去を行う.
+
+use nix::unistd::{getuid, User};
+
+use crate::vars::is_name;
+use crate::{arith, glob, pattern, Shell};
+
+// the longest {x..y} that is expanded: a longer one is left as it is
+// rather than taking up all the memory
+const MAX_SEQUENCE: u128 = 100_000;
+
+struct Expander<'a> {
+    // for the variables and to run $(command)
+    shell: &'a mut Shell,
+    // split the results of unquoted expansions on $IFS
+    split: bool,
+    fields: Vec<String>,
+    current: String,
+    // the current field has a quoted part: "" is a field, nothing is not
+    quoted: bool,
+    // the current field as a pattern, with its quoted characters escaped,
+    // and whether it has a * ? or [ that is not quoted
+    pattern: String,
+    glob: bool,
+    // NAME=word, where a ~ after an unquoted : is expanded too
+    assignment: bool,
+}
+
+// Expand a word as typed into the fields passed to the command.
+pub fn expand_word(word: &str, shell: &mut Shell) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    for word in braces(word) {
+        let mut expander = Expander::new(shell, true);
+        expander.word(&word, false)?;
+        fields.extend(expander.finish());
+    }
+    Ok(fields)
+}
+
+// Expand the word in NAME=word, without field splitting. A ~ after an
+// unquoted : is expanded as well as one at the start: PATH=~/bin:~/sbin.
+pub fn expand_assignment(value: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut expander = Expander::new(shell, false);
+    expander.assignment = true;
+    expander.word(value, false)?;
+    Ok(expander.finish().join(" "))
+}
+
+// The body of a here-document whose delimiter was not quoted. Quotes are
+// ordinary characters there; a backslash only escapes $ ` \ and newline.
+pub fn expand_here_doc(body: &str, shell: &mut Shell) -> Result<String, String> {
+    let mut expander = Expander::new(shell, false);
+    let chars: Vec<char> = body.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                match chars.get(i + 1) {
+                    Some('\n') => {}
+                    Some(&next) if "$`\\".contains(next) => expander.current.push(next),
+                    Some(&next) => {
+                        expander.current.push('\\');
+                        expander.current.push(next);
+                    }
+                    None => expander.current.push('\\'),
+                }
+                i += 2;
+            }
+            '$' => i = expander.parameter(&chars, i, true)?,
+            '`' => i = expander.backquoted(&chars, i, true)?,
+            c => {
+                expander.current.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    Ok(expander.finish().join(" "))
+}
+
+impl<'a> Expander<'a> {
+    fn new(shell: &'a mut Shell, split: bool) -> Expander<'a> {
+        Expander {
+            shell,
+            split,
+            fields: Vec::new(),
+            current: String::new(),
+            quoted: false,
+            pattern: String::new(),
+            glob: false,
+            assignment: false,
+        }
+    }
+
+    fn word(&mut self, word: &str, mut in_double: bool) -> Result<(), String> {
+        let chars: Vec<char> = word.chars().collect();
+        // where a ~ may start, at the start or after an unquoted :
+        let mut tilde_at = 0;
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if in_double => {
+                    match chars.get(i + 1) {
+                        // inside double quotes a backslash only escapes these
+                        Some(&next) if "$`\"\\".contains(next) => self.literal(next),
+                        Some(&next) => {
+                            self.literal('\\');
+                            self.literal(next);
+                        }
+                        None => self.literal('\\'),
+                    }
+                    i += 2;
+                }
+                '\\' => {
+                    if let Some(&next) = chars.get(i + 1) {
+                        self.literal(next);
+                    }
+                    i += 2;
+                }
+                '\'' if !in_double => {
+                    let end = find(&chars, i + 1, '\'');
+                    for &c in &chars[i + 1..end] {
+                        self.literal(c);
+                    }
+                    self.quoted = true;
+                    i = end + 1;
+                }
+                '~' if i == tilde_at && !in_double => i = self.tilde(&chars, i),
+                '"' => {
+                    in_double = !in_double;
+                    self.quoted = true;
+                    i += 1;
+                }
+                '$' => i = self.parameter(&chars, i, in_double)?,
+                '`' => i = self.backquoted(&chars, i, in_double)?,
+                c if in_double => {
+                    self.literal(c);
+                    i += 1;
+                }
+                c => {
+                    self.unquoted(c);
+                    i += 1;
+                    if c == ':' && self.assignment {
+                        tilde_at = i;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.end_field();
+        self.fields
+    }
+
+    fn end_field(&mut self) {
+        if !self.current.is_empty() || self.quoted {
+            self.push_field();
+            self.quoted = false;
+        }
+    }
+
+    // Add the current field, or the paths it matches as a pattern. One that
+    // matches nothing is left as it is, unless nullglob is set.
+    fn push_field(&mut self) {
+        let field = std::mem::take(&mut self.current);
+        let pattern = std::mem::take(&mut self.pattern);
+        if self.split && std::mem::take(&mut self.glob) {
+            let paths = glob::glob(&pattern);
+            if !paths.is_empty() || self.shell.vars.nullglob {
+                self.fields.extend(paths);
+                return;
+            }
+        }
+        self.fields.push(field);
+    }
+
+    // a character that stands for itself
+    fn literal(&mut self, c: char) {
+        self.current.push(c);
+        if "*?[]\\".contains(c) {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+    }
+
+    fn unquoted(&mut self, c: char) {
+        self.current.push(c);
+        self.pattern.push(c);
+        if "*?[".contains(c) {
+            self.glob = true;
+        }
+    }
+
+    // Add the value of an expansion to the current field.
+    fn insert(&mut self, value: &str, in_double: bool) {
+        if in_double || !self.split {
+            for c in value.chars() {
+                self.literal(c);
+            }
+            return;
+        }
+
+        // IFS white space only separates fields, where any other IFS
+        // character ends one even if it is empty: a::b is a, "" and b.
+        // White space next to such a character belongs to the same separator.
+        let ifs = self
+            .shell
+            .vars
+            .get("IFS")
+            .unwrap_or_else(|| " \t\n".to_string());
+        let mut after_space = false;
+        for c in value.chars() {
+            if !ifs.contains(c) {
+                self.unquoted(c);
+                after_space = false;
+            } else if " \t\n".contains(c) {
+                if !self.current.is_empty() || self.quoted {
+                    self.end_field();
+                    after_space = true;
+                }
+            } else if after_space {
+                after_space = false;
+            } else {
+                self.push_field();
+                self.quoted = false;
+            }
+        }
+    }
+
+    // ~ is $HOME and ~user the home directory of user, up to the first /,
+    // or : in an assignment. Returns the index after it.
+    fn tilde(&mut self, chars: &[char], start: usize) -> usize {
+        let end = (start + 1..chars.len())
+            .find(|&i| chars[i] == '/' || self.assignment && chars[i] == ':')
+            .unwrap_or(chars.len());
+        let user: String = chars[start + 1..end].iter().collect();
+        // ~"user" is not expanded
+        let home = if user.contains(|c| "'\"\\$`".contains(c)) {
+            None
+        } else if user.is_empty() {
+            self.shell
+                .vars
+                .get("HOME")
+                .or_else(|| home_dir(User::from_uid(getuid())))
+        } else {
+            home_dir(User::from_name(&user))
+        };
+
+        match home {
+            Some(home) => {
+                for c in home.chars() {
+                    self.literal(c);
+                }
+                end
+            }
+            None => {
+                self.literal('~');
+                start + 1
+            }
+        }
+    }
+
+    // Expand the parameter whose $ is at `start`. Returns the index after it.
+    fn parameter(
+        &mut self,
+        chars: &[char],
+        start: usize,
+        in_double: bool,
+    ) -> Result<usize, String> {
+        let i = start + 1;
+        match chars.get(i) {
+            Some('{') => {
+                let end = matching_brace(chars, i, in_double)?;
+                let inner: String = chars[i + 1..end].iter().collect();
+                self.braced(&inner, in_double)?;
+                Ok(end + 1)
+            }
+            Some('(') => {
+                let end = matching_paren(chars, i)?;
+                // $((expression)) if the inner ( closes right before the outer one
+                let value = if chars.get(i + 1) == Some(&'(')
+                    && matching_paren(chars, i + 1).ok() == Some(end - 1)
+                {
+                    let expr: String = chars[i + 2..end - 1].iter().collect();
+                    let expr = self.sub_word(&expr, true)?;
+                    arith::evaluate(&expr, &mut self.shell.vars)?.to_string()
+                } else {
+                    let command: String = chars[i + 1..end].iter().collect();
+                    self.shell.substitute(&command)?
+                };
+                self.insert(&value, in_double);
+                Ok(end + 1)
+            }
+            Some('@') if in_double => {
+                // "$@": each positional parameter is a field of its own
+                let args = self.shell.vars.positional().to_vec();
+                for (n, arg) in args.iter().enumerate() {
+                    if n > 0 {
+                        self.push_field();
+                    }
+                    for c in arg.chars() {
+                        self.literal(c);
+                    }
+                }
+                if args.is_empty() {
+                    self.quoted = false;
+                }
+                Ok(i + 1)
+            }
+            Some(&c) if "?$!#@*0123456789".contains(c) => {
+                let value = self.shell.vars.get(&c.to_string()).unwrap_or_default();
+                self.insert(&value, in_double);
+                Ok(i + 1)
+            }
+            Some(&c) if c == '_' || c.is_ascii_alphabetic() => {
+                let end = (i..chars.len())
+                    .find(|&j| !(chars[j] == '_' || chars[j].is_ascii_alphanumeric()))
+                    .unwrap_or(chars.len());
+                let name: String = chars[i..end].iter().collect();
+                let value = self.shell.vars.get(&name).unwrap_or_default();
+                self.insert(&value, in_double);
+                Ok(end)
+            }
+            // a $ that starts nothing is itself
+            _ => {
+                self.literal('$');
+                Ok(i)
+            }
+        }
+    }
+
+    // `command`, the old form of $(command): a backslash inside only escapes
+    // $ ` and \.
+    fn backquoted(
+        &mut self,
+        chars: &[char],
+        start: usize,
+        in_double: bool,
+    ) -> Result<usize, String> {
+        let end = find_backquote(chars, start + 1);
+        let mut command = String::new();
+        let mut i = start + 1;
+        while i < end {
+            match (chars[i], chars.get(i + 1)) {
+                ('\\', Some(&next)) if "$`\\".contains(next) => {
+                    command.push(next);
+                    i += 2;
+                }
+                (c, _) => {
+                    command.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        let output = self.shell.substitute(&command)?;
+        self.insert(&output, in_double);
+        Ok(end + 1)
+    }
+
+    // ${NAME}, ${#NAME}, ${NAME:-word}, ${NAME:=word}, ${NAME:+word},
+    // ${NAME%pattern} and the like.
+    fn braced(&mut self, inner: &str, in_double: bool) -> Result<(), String> {
+        let bad = || format!("${{{}}}: bad substitution", inner);
+
+        if let Some(name) = inner.strip_prefix('#').filter(|name| is_parameter(name)) {
+            let value = self.shell.vars.get(name).unwrap_or_default();
+            self.insert(&value.chars().count().to_string(), in_double);
+            return Ok(());
+        }
+
+        let name_len = parameter_len(inner);
+        if name_len == 0 {
+            return Err(bad());
+        }
+        let (name, rest) = inner.split_at(name_len);
+        let value = self.shell.vars.get(name);
+
+        // with a colon, an empty value counts as unset
+        let (colon, rest) = match rest.strip_prefix(':') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let set = match value {
+            Some(ref value) => !(colon && value.is_empty()),
+            None => false,
+        };
+
+        let value = match rest.chars().next() {
+            None if !colon => value.unwrap_or_default(),
+            Some('-') if set => value.unwrap_or_default(),
+            Some('-') => self.sub_word(&rest[1..], in_double)?,
+            Some('=') if set => value.unwrap_or_default(),
+            Some('=') => {
+                if !is_name(name) {
+                    return Err(format!("${}: cannot assign in this way", name));
+                }
+                let value = self.sub_word(&rest[1..], in_double)?;
+                self.shell.vars.set(name, &value);
+                value
+            }
+            Some('+') if set => self.sub_word(&rest[1..], in_double)?,
+            Some('+') => String::new(),
+            Some(op @ '%') | Some(op @ '#') if !colon => {
+                // %% と ## は最長一致, % と # は最短一致
+                let longest = rest[1..].starts_with(op);
+                let pattern = self.sub_word(&rest[if longest { 2 } else { 1 }..], in_double)?;
+                remove_match(&value.unwrap_or_default(), &pattern, op == '#', longest)
+            }
+            _ => return Err(bad()),
+        };
+
+        self.insert(&value, in_double);
+        Ok(())
+    }
+
+    // The word after the operator in ${NAME:-word}, which is quoted if the
+    // whole expansion is.
+    fn sub_word(&mut self, word: &str, in_double: bool) -> Result<String, String> {
+        let mut expander = Expander::new(self.shell, false);
+        expander.word(word, in_double)?;
+        Ok(expander.finish().join(" "))
+    }
+}
+
+// {a,b}c becomes ac bc, and {1..3} 1 2 3, before any other expansion. A
+// brace that is quoted or part of ${ is left alone.
+fn braces(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' if !in_double => i = find(&chars, i + 1, '\''),
+            '"' => in_double = !in_double,
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                i = matching_brace(&chars, i + 1, in_double).unwrap_or(chars.len())
+            }
+            '$' if chars.get(i + 1) == Some(&'(') => {
+                i = matching_paren(&chars, i + 1).unwrap_or(chars.len())
+            }
+            '`' => i = find_backquote(&chars, i + 1),
+            '{' if !in_double => {
+                if let Some((alternatives, close)) = brace_alternatives(&chars, i) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[close + 1..].iter().collect();
+                    return alternatives
+                        .iter()
+                        .flat_map(|alternative| {
+                            braces(&format!("{}{}{}", prefix, alternative, suffix))
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    vec![word.to_string()]
+}
+
+// The alternatives of the {a,b} or {x..y} at `open`, and where it closes.
+// None if it is neither.
+fn brace_alternatives(chars: &[char], open: usize) -> Option<(Vec<String>, usize)> {
+    let mut depth = 0;
+    let mut in_double = false;
+    // the top-level commas
+    let mut commas = Vec::new();
+    let mut i = open;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' if !in_double => i = find(chars, i + 1, '\''),
+            '"' => in_double = !in_double,
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                i = matching_brace(chars, i + 1, in_double).ok()?
+            }
+            '$' if chars.get(i + 1) == Some(&'(') => i = matching_paren(chars, i + 1).ok()?,
+            '`' => i = find_backquote(chars, i + 1),
+            '{' if !in_double => depth += 1,
+            '}' if !in_double => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            ',' if !in_double && depth == 1 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    if i >= chars.len() {
+        return None;
+    }
+
+    if commas.is_empty() {
+        let inner: String = chars[open + 1..i].iter().collect();
+        return sequence(&inner).map(|items| (items, i));
+    }
+    let mut bounds = vec![open];
+    bounds.extend(commas);
+    bounds.push(i);
+    let alternatives = bounds
+        .windows(2)
+        .map(|pair| chars[pair[0] + 1..pair[1]].iter().collect())
+        .collect();
+    Some((alternatives, i))
+}
+
+// 1..10, 10..1, 1..10..2, 01..10 (padded to the same width) or a..e.
+fn sequence(inner: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = inner.split("..").collect();
+    let step = match parts.len() {
+        2 => 1,
+        3 => parts[2].parse::<i64>().ok()?.checked_abs()?.max(1),
+        _ => return None,
+    };
+
+    if let (Ok(first), Ok(last)) = (parts[0].parse::<i64>(), parts[1].parse::<i64>()) {
+        let padded = |part: &str| {
+            let digits = part.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(parts[0]) || padded(parts[1]) {
+            parts[0].len().max(parts[1].len())
+        } else {
+            0
+        };
+        return Some(
+            range(first, last, step)?
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        );
+    }
+
+    let letter = |part: &str| match part.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Some(*c as i64),
+        _ => None,
+    };
+    let (first, last) = (letter(parts[0])?, letter(parts[1])?);
+    Some(
+        range(first, last, step)?
+            .map(|c| (c as u8 as char).to_string())
+            .collect(),
+    )
+}
+
+// first, first + step, ... up to last, counting down if last is smaller.
+// None if that is more than MAX_SEQUENCE numbers.
+fn range(first: i64, last: i64, step: i64) -> Option<impl Iterator<Item = i64>> {
+    let count = (first as i128 - last as i128).unsigned_abs() / step as u128 + 1;
+    if count > MAX_SEQUENCE {
+        return None;
+    }
+    let step = if last < first { -step } else { step };
+    Some((0..count as i64).map(move |n| first + n * step))
+}
+
+fn home_dir(user: nix::Result<Option<User>>) -> Option<String> {
+    user.ok()
+        .flatten()
+        .map(|user| user.dir.to_string_lossy().into_owned())
+}
+
+// A special parameter, a positional parameter or a NAME.
+fn is_parameter(name: &str) -> bool {
+    !name.is_empty() && parameter_len(name) == name.len()
+}
+
+// The length of the parameter name at the start of `inner`.
+fn parameter_len(inner: &str) -> usize {
+    let mut chars = inner.chars();
+    match chars.next() {
+        Some(c) if "?$!#@*".contains(c) => 1,
+        Some(c) if c.is_ascii_digit() => 1 + chars.take_while(|c| c.is_ascii_digit()).count(),
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            1 + chars
+                .take_while(|&c| c == '_' || c.is_ascii_alphanumeric())
+                .count()
+        }
+        _ => 0,
+    }
+}
+
+// ${NAME#pattern} removes a matching prefix, ${NAME%pattern} a suffix.
+fn remove_match(value: &str, pattern: &str, prefix: bool, longest: bool) -> String {
+    let mut bounds: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    bounds.push(value.len());
+    // shortest match first
+    if prefix == longest {
+        bounds.reverse();
+    }
+
+    for &i in &bounds {
+        let (matched, rest) = if prefix {
+            (&value[..i], &value[i..])
+        } else {
+            (&value[i..], &value[..i])
+        };
+        if pattern::matches(pattern, matched) {
+            return rest.to_string();
+        }
+    }
+
+    value.to_string()
+}
+
+// The } that closes the { at `open`, skipping quoted text. Inside double
+// quotes a ' is an ordinary character.
+fn matching_brace(chars: &[char], open: usize, in_double: bool) -> Result<usize, String> {
+    let mut depth = 0;
+    let mut i = open;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' if !in_double => i = find(chars, i + 1, '\''),
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    Err("bad substitution: no closing `}'".to_string())
+}
+
+// The ) that closes the ( at `open`. Quotes inside are those of the
+// command, whatever the quoting around it.
+fn matching_paren(chars: &[char], open: usize) -> Result<usize, String> {
+    let mut depth = 0;
+    let mut i = open;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i = find(chars, i + 1, '\''),
+            '`' => i = find_backquote(chars, i + 1),
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    Err("bad substitution: no closing `)'".to_string())
+}
+
+// the ` that ends a `command`, skipping escaped ones
+fn find_backquote(chars: &[char], from: usize) -> usize {
+    let mut i = from;
+    while i < chars.len() && chars[i] != '`' {
+        if chars[i] == '\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    i.min(chars.len())
+}
+
+// the lexer has checked that quotes are closed; the end of the word otherwise
+fn find(chars: &[char], from: usize, target: char) -> usize {
+    chars[from.min(chars.len())..]
+        .iter()
+        .position(|&c| c == target)
+        .map(|pos| from + pos)
+        .unwrap_or(chars.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(word: &str, shell: &mut Shell) -> Vec<String> {
+        expand_word(word, shell).unwrap()
+    }
+
+    #[test]
+    fn default_and_assign() {
+        let mut sh = Shell::for_test(&[("empty", "")]);
+        sh.vars.unset("x");
+        assert_eq!(expand("${x:-}", &mut sh), Vec::<String>::new());
+        assert_eq!(expand("\"${x:-}\"", &mut sh), [""]);
+        assert_eq!(expand("${x:-a b}", &mut sh), ["a", "b"]);
+        assert_eq!(expand("${empty:-d}", &mut sh), ["d"]);
+        assert_eq!(expand("${empty-d}", &mut sh), Vec::<String>::new());
+        assert_eq!(
+            expand("${empty:+alt}${x+alt}", &mut sh),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(expand("${x:=}", &mut sh), Vec::<String>::new());
+        assert_eq!(sh.vars.get("x").as_deref(), Some(""));
+        assert_eq!(expand("${x:=v}", &mut sh), ["v"]);
+        assert_eq!(sh.vars.get("x").as_deref(), Some("v"));
+        assert!(expand_word("${1:=v}", &mut sh).is_err());
+    }
+
+    #[test]
+    fn remove_patterns() {
+        let mut sh = Shell::for_test(&[("x", "dir/file.tar.gz")]);
+        assert_eq!(expand("${x#}", &mut sh), ["dir/file.tar.gz"]);
+        assert_eq!(expand("${x%%}", &mut sh), ["dir/file.tar.gz"]);
+        assert_eq!(expand("${x#*/}", &mut sh), ["file.tar.gz"]);
+        assert_eq!(expand("${x##*.}", &mut sh), ["gz"]);
+        assert_eq!(expand("${x%.*}", &mut sh), ["dir/file.tar"]);
+        assert_eq!(expand("${x%%.*}", &mut sh), ["dir/file"]);
+        assert_eq!(expand("${x%\"*\"}", &mut sh), ["dir/file.tar.gz"]);
+    }
+
+    #[test]
+    fn length() {
+        let mut sh = Shell::for_test(&[("x", "héllo"), ("empty", "")]);
+        sh.vars.unset("unset");
+        assert_eq!(expand("${#x}", &mut sh), ["5"]);
+        assert_eq!(expand("${#empty}${#unset}", &mut sh), ["00"]);
+        assert!(expand_word("${x:}", &mut sh).is_err());
+    }
+
+    #[test]
+    fn field_splitting() {
+        let mut sh = Shell::for_test(&[("v", "  a  b\tc\n")]);
+        assert_eq!(expand("$v", &mut sh), ["a", "b", "c"]);
+        assert_eq!(expand("x${v}y", &mut sh), ["x", "a", "b", "c", "y"]);
+        assert_eq!(expand("\"$v\"", &mut sh), ["  a  b\tc\n"]);
+
+        // other IFS characters end a field even if it is empty
+        sh.vars.set("IFS", ":");
+        sh.vars.set("v", "a::b:");
+        assert_eq!(expand("$v", &mut sh), ["a", "", "b"]);
+        sh.vars.set("v", ":a");
+        assert_eq!(expand("$v", &mut sh), ["", "a"]);
+
+        // with white space around them
+        sh.vars.set("IFS", " :");
+        sh.vars.set("v", " a : b  c :: d ");
+        assert_eq!(expand("$v", &mut sh), ["a", "b", "c", "", "d"]);
+
+        sh.vars.set("IFS", "");
+        sh.vars.set("v", "a b");
+        assert_eq!(expand("$v", &mut sh), ["a b"]);
+    }
+
+    #[test]
+    fn tilde() {
+        let mut sh = Shell::for_test(&[("HOME", "/home/me")]);
+        assert_eq!(expand("~/x", &mut sh), ["/home/me/x"]);
+        assert_eq!(expand("a~", &mut sh), ["a~"]);
+        assert_eq!(expand("'~'", &mut sh), ["~"]);
+        // outside an assignment, ":~" is the name of a user
+        assert_eq!(expand("~:~", &mut sh), ["~:~"]);
+
+        let assign = |value: &str, sh: &mut Shell| expand_assignment(value, sh).unwrap();
+        assert_eq!(assign("~", &mut sh), "/home/me");
+        assert_eq!(assign("~/bin:~:a~b", &mut sh), "/home/me/bin:/home/me:a~b");
+        assert_eq!(assign("a\\:~:\"~\"", &mut sh), "a:~:~");
+    }
+
+    #[test]
+    fn brace_lists() {
+        assert_eq!(braces("{a,b{c,d}}"), ["a", "bc", "bd"]);
+        assert_eq!(braces("x{a,b}y"), ["xay", "xby"]);
+        assert_eq!(braces("{a,}"), ["a", ""]);
+        // not a list: no comma, quoted or part of ${
+        assert_eq!(braces("{a}"), ["{a}"]);
+        assert_eq!(braces("'{a,b}'"), ["'{a,b}'"]);
+        assert_eq!(braces("${x}{1,2}"), ["${x}1", "${x}2"]);
+    }
+
+    #[test]
+    fn brace_sequences() {
+        assert_eq!(braces("{1..3}"), ["1", "2", "3"]);
+        assert_eq!(braces("{3..1}"), ["3", "2", "1"]);
+        assert_eq!(
+            braces("{01..10}"),
+            ["01", "02", "03", "04", "05", "06", "07", "08", "09", "10"]
+        );
+        assert_eq!(braces("{a..e..2}"), ["a", "c", "e"]);
+        assert_eq!(braces("{-4..4..4}"), ["-4", "0", "4"]);
+        assert_eq!(braces("{1..a}"), ["{1..a}"]);
+        // too long to expand
+        assert_eq!(braces("{1..999999999}"), ["{1..999999999}"]);
+        assert_eq!(braces("{1..100000}").len(), 100_000);
+    }
+
+    #[test]
+    fn nullglob() {
+        let dir = std::env::temp_dir().join(format!("sh-nullglob-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        let mut sh = Shell::for_test(&[]);
+
+        let found = format!("{}/*.txt", dir.display());
+        let missing = format!("{}/*.none", dir.display());
+        assert_eq!(
+            expand(&found, &mut sh),
+            [format!("{}/a.txt", dir.display())]
+        );
+        // a pattern that matches nothing is left as it is
+        assert_eq!(expand(&missing, &mut sh), vec![missing.clone()]);
+        assert_eq!(
+            expand(&format!("'{}'", found), &mut sh),
+            vec![found.clone()]
+        );
+
+        sh.vars.nullglob = true;
+        assert_eq!(expand(&missing, &mut sh), Vec::<String>::new());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,139 @@
+// sh のパス名展開. パターンに合うファイル名を辞書順に並べる.
+
+use std::fs;
+
+use crate::pattern;
+
+// The paths that match `pattern`, sorted. Quoted characters are escaped with
+// a backslash. A name starting with . only matches a pattern that starts
+// with one too.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let mut paths = vec![if pattern.starts_with('/') {
+        "/".to_string()
+    } else {
+        String::new()
+    }];
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+
+    for (n, component) in components.iter().enumerate() {
+        let mut next = Vec::new();
+        for path in &paths {
+            if !has_magic(component) {
+                next.push(format!("{}{}", path, unescape(component)));
+                continue;
+            }
+
+            let dir = if path.is_empty() { "." } else { path.as_str() };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let hidden = name.starts_with('.') && !unescape(component).starts_with('.');
+                if !hidden && pattern::matches(component, &name) {
+                    next.push(format!("{}{}", path, name));
+                }
+            }
+        }
+
+        // dir/*/ only matches directories
+        if n + 1 < components.len() || pattern.ends_with('/') {
+            for path in next.iter_mut() {
+                path.push('/');
+            }
+        }
+        paths = next;
+    }
+
+    // the components without a pattern were not looked up
+    paths.retain(|path| fs::symlink_metadata(path).is_ok());
+    paths.sort();
+    paths
+}
+
+// Whether a component has an unescaped * ? or [.
+fn has_magic(component: &str) -> bool {
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(component: &str) -> String {
+    let mut text = String::new();
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    fn tree(name: &str, files: &[&str]) -> PathBuf {
+        let base = env::temp_dir().join(format!("sh-glob-test-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&base).ok();
+        for file in files {
+            let path = base.join(file);
+            if file.ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, "").unwrap();
+            }
+        }
+        base
+    }
+
+    // the matches with `base/` taken off
+    fn glob_in(base: &Path, pattern: &str) -> Vec<String> {
+        let prefix = format!("{}/", base.display());
+        glob(&format!("{}{}", prefix, pattern))
+            .iter()
+            .map(|path| path[prefix.len()..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn patterns() {
+        let base = tree(
+            "patterns",
+            &["a.rs", "b.rs", "c.txt", "d/e.rs", "f/", "[x]"],
+        );
+        assert_eq!(glob_in(&base, "*.rs"), ["a.rs", "b.rs"]);
+        assert_eq!(glob_in(&base, "?.*"), ["a.rs", "b.rs", "c.txt"]);
+        assert_eq!(glob_in(&base, "[a-b].rs"), ["a.rs", "b.rs"]);
+        assert_eq!(glob_in(&base, "[!a].rs"), ["b.rs"]);
+        assert_eq!(glob_in(&base, "*/*.rs"), ["d/e.rs"]);
+        assert_eq!(glob_in(&base, "*/"), ["d/", "f/"]);
+        assert_eq!(glob_in(&base, "\\[x]"), ["[x]"]);
+        assert_eq!(glob_in(&base, "\\*.rs"), Vec::<String>::new());
+        assert_eq!(glob_in(&base, "*.none"), Vec::<String>::new());
+        fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn hidden_files() {
+        let base = tree("hidden", &[".hidden", "shown", ".dir/x"]);
+        assert_eq!(glob_in(&base, "*"), ["shown"]);
+        assert_eq!(glob_in(&base, "?hidden"), Vec::<String>::new());
+        assert_eq!(glob_in(&base, ".*"), [".dir", ".hidden"]);
+        assert_eq!(glob_in(&base, ".d*/*"), [".dir/x"]);
+        fs::remove_dir_all(&base).ok();
+    }
+}
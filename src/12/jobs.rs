@@ -0,0 +1,338 @@
+// sh のジョブ管理. バックグラウンドや停止中のパイプラインを番号で扱う.
+
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::ffi::CStr;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    Stopped,
+    Exited(i32),
+    Killed(Signal),
+}
+
+pub struct Job {
+    pub id: usize,
+    pub pgid: Pid,
+    pub procs: Vec<(Pid, State)>,
+    // the command as typed, for jobs and the notifications
+    pub text: String,
+    // the state last reported to the user
+    reported: State,
+}
+
+pub struct Jobs {
+    jobs: Vec<Job>,
+    // ids from the least to the most recently started or stopped: the last
+    // one is the current job %+, the one before it the previous job %-
+    recent: Vec<usize>,
+}
+
+impl State {
+    fn is_done(self) -> bool {
+        matches!(self, State::Exited(_) | State::Killed(_))
+    }
+
+    // the exit status the shell sees, as $? or from wait
+    pub fn status(self) -> i32 {
+        match self {
+            State::Running => 0,
+            State::Stopped => 128 + Signal::SIGTSTP as i32,
+            State::Exited(code) => code,
+            State::Killed(sig) => 128 + sig as i32,
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            State::Running => "Running".to_string(),
+            State::Stopped => "Stopped".to_string(),
+            State::Exited(0) => "Done".to_string(),
+            State::Exited(code) => format!("Exit {}", code),
+            // e.g. "Terminated" or "Killed"
+            State::Killed(sig) => unsafe { CStr::from_ptr(libc::strsignal(sig as i32)) }
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+}
+
+impl Job {
+    pub fn new(pgid: Pid, pids: &[Pid], text: String) -> Job {
+        Job {
+            id: 0,
+            pgid,
+            procs: pids.iter().map(|&pid| (pid, State::Running)).collect(),
+            text,
+            reported: State::Running,
+        }
+    }
+
+    // Stopped if any process is, done with the status of the last one when
+    // all are, running otherwise.
+    pub fn state(&self) -> State {
+        if self.procs.iter().any(|&(_, state)| state == State::Stopped) {
+            return State::Stopped;
+        }
+        match self.procs.last() {
+            Some(&(_, last)) if self.procs.iter().all(|&(_, state)| state.is_done()) => last,
+            _ => State::Running,
+        }
+    }
+
+    pub fn continued(&mut self) {
+        for (_, state) in self.procs.iter_mut() {
+            if *state == State::Stopped {
+                *state = State::Running;
+            }
+        }
+        self.reported = State::Running;
+    }
+
+    // Wait until the job has finished or stopped. With `block` false, only
+    // collect what has happened so far.
+    pub fn wait(&mut self, block: bool) {
+        let mut flags = WaitPidFlag::WUNTRACED;
+        if !block {
+            flags |= WaitPidFlag::WNOHANG | WaitPidFlag::WCONTINUED;
+        }
+
+        for i in 0..self.procs.len() {
+            let (pid, _) = self.procs[i];
+            while !self.procs[i].1.is_done() {
+                let state = match waitpid(pid, Some(flags)) {
+                    Ok(WaitStatus::Exited(_, code)) => State::Exited(code),
+                    Ok(WaitStatus::Signaled(_, sig, _)) => State::Killed(sig),
+                    Ok(WaitStatus::Stopped(..)) => State::Stopped,
+                    Ok(WaitStatus::Continued(_)) => State::Running,
+                    Ok(_) => break,
+                    Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                    // not a child of this process, e.g. in a subshell
+                    Err(_) => State::Exited(127),
+                };
+                self.procs[i].1 = state;
+                if !block || state == State::Stopped {
+                    break;
+                }
+            }
+            // a stop stops the whole job
+            if block && self.procs[i].1 == State::Stopped {
+                return;
+            }
+        }
+    }
+}
+
+impl Jobs {
+    pub fn new() -> Jobs {
+        Jobs {
+            jobs: Vec::new(),
+            recent: Vec::new(),
+        }
+    }
+
+    // Add a job under the next free number, or back under its own number
+    // after fg. Returns the number.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.last().map(|job| job.id + 1).unwrap_or(1);
+        }
+        job.reported = job.state();
+        let id = job.id;
+        let index = self.jobs.iter().position(|other| other.id > id);
+        self.jobs.insert(index.unwrap_or(self.jobs.len()), job);
+        self.touch(id);
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        self.recent.retain(|&recent| recent != id);
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    // Make `id` the current job.
+    pub fn touch(&mut self, id: usize) {
+        self.recent.retain(|&recent| recent != id);
+        self.recent.push(id);
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    pub fn by_pid(&self, pid: Pid) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.procs.iter().any(|&(proc_pid, _)| proc_pid == pid))
+            .map(|job| job.id)
+    }
+
+    // %n, %+ or %% (the current job), %- (the previous job), or %string
+    // (the job whose command starts with string).
+    pub fn find(&self, spec: &str) -> Result<usize, String> {
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        let found = match name {
+            "" | "+" | "%" => self.recent.last().copied(),
+            "-" => self.recent.iter().rev().nth(1).copied(),
+            _ => match name.parse::<usize>() {
+                Ok(id) => self.jobs.iter().find(|job| job.id == id).map(|job| job.id),
+                Err(_) => self
+                    .jobs
+                    .iter()
+                    .find(|job| job.text.starts_with(name))
+                    .map(|job| job.id),
+            },
+        };
+        found.ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    // A line of jobs: [1]+  Running                 sleep 10 &
+    pub fn format(&self, id: usize) -> String {
+        let job = match self.jobs.iter().find(|job| job.id == id) {
+            Some(job) => job,
+            None => return String::new(),
+        };
+        let marker = if self.recent.last() == Some(&id) {
+            '+'
+        } else if self.recent.iter().rev().nth(1) == Some(&id) {
+            '-'
+        } else {
+            ' '
+        };
+        let state = job.state();
+        let amp = if state == State::Running { " &" } else { "" };
+
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.id,
+            marker,
+            state.describe(),
+            job.text,
+            amp
+        )
+    }
+
+    // Collect what has happened to every job, without blocking.
+    pub fn poll(&mut self) {
+        for job in self.jobs.iter_mut() {
+            job.wait(false);
+        }
+    }
+
+    // The line of jobs for a job, which then counts as reported: a job that
+    // has finished is removed.
+    pub fn report(&mut self, id: usize) -> String {
+        let line = self.format(id);
+        if let Some(job) = self.get_mut(id) {
+            job.reported = job.state();
+            if job.reported.is_done() {
+                self.remove(id);
+            }
+        }
+        line
+    }
+
+    // The lines to tell the user about before the next prompt: jobs that
+    // finished, stopped or were continued since they were last reported.
+    pub fn notifications(&mut self) -> Vec<String> {
+        self.poll();
+        let changed: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|job| job.state() != job.reported)
+            .map(|job| job.id)
+            .collect();
+        changed.into_iter().map(|id| self.report(id)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // jobs whose processes are never waited for: the pids are made up
+    fn jobs(texts: &[&str]) -> Jobs {
+        let mut jobs = Jobs::new();
+        for (n, text) in texts.iter().enumerate() {
+            let pid = Pid::from_raw(1_000_000 + n as i32);
+            jobs.add(Job::new(pid, &[pid], text.to_string()));
+        }
+        jobs
+    }
+
+    #[test]
+    fn find() {
+        let mut jobs = jobs(&["sleep 10", "vi notes", "sleep 20"]);
+        assert_eq!(jobs.find("%+"), Ok(3));
+        assert_eq!(jobs.find("%%"), Ok(3));
+        assert_eq!(jobs.find("%"), Ok(3));
+        assert_eq!(jobs.find("%-"), Ok(2));
+        assert_eq!(jobs.find("%1"), Ok(1));
+        assert_eq!(jobs.find("2"), Ok(2));
+        assert_eq!(jobs.find("%vi"), Ok(2));
+        // the first job, not the current one, whose command matches
+        assert_eq!(jobs.find("%sleep"), Ok(1));
+        assert_eq!(jobs.find("%4"), Err("%4: no such job".to_string()));
+        assert_eq!(jobs.find("%cat"), Err("%cat: no such job".to_string()));
+
+        jobs.touch(1);
+        assert_eq!(jobs.find("%+"), Ok(1));
+        assert_eq!(jobs.find("%-"), Ok(3));
+        jobs.remove(1);
+        assert_eq!(jobs.find("%+"), Ok(3));
+        assert_eq!(jobs.find("%-"), Ok(2));
+    }
+
+    #[test]
+    fn format_markers() {
+        let mut jobs = jobs(&["a", "b", "c"]);
+        assert_eq!(jobs.format(3), format!("[3]+  {:<24}c &", "Running"));
+        assert_eq!(jobs.format(2), format!("[2]-  {:<24}b &", "Running"));
+        assert_eq!(jobs.format(1), format!("[1]   {:<24}a &", "Running"));
+        assert_eq!(jobs.format(4), "");
+
+        jobs.get_mut(1).unwrap().procs[0].1 = State::Stopped;
+        jobs.touch(1);
+        assert_eq!(jobs.format(1), format!("[1]+  {:<24}a", "Stopped"));
+        assert_eq!(jobs.format(3), format!("[3]-  {:<24}c &", "Running"));
+
+        jobs.get_mut(2).unwrap().procs[0].1 = State::Exited(0);
+        assert_eq!(jobs.format(2), format!("[2]   {:<24}b", "Done"));
+        jobs.get_mut(3).unwrap().procs[0].1 = State::Exited(3);
+        assert!(jobs.format(3).contains("Exit 3"));
+    }
+
+    #[test]
+    fn job_state() {
+        let pids = [Pid::from_raw(1_000_000), Pid::from_raw(1_000_001)];
+        let mut job = Job::new(pids[0], &pids, "a | b".to_string());
+        assert!(job.state() == State::Running);
+        job.procs[1].1 = State::Exited(1);
+        assert!(job.state() == State::Running);
+        job.procs[0].1 = State::Killed(Signal::SIGPIPE);
+        // the status of the last process
+        assert!(job.state() == State::Exited(1));
+        job.procs[1].1 = State::Stopped;
+        assert!(job.state() == State::Stopped);
+        job.continued();
+        assert!(job.state() == State::Running);
+    }
+
+    #[test]
+    fn status() {
+        assert_eq!(State::Running.status(), 0);
+        assert_eq!(State::Exited(0).status(), 0);
+        assert_eq!(State::Exited(42).status(), 42);
+        assert_eq!(State::Killed(Signal::SIGTERM).status(), 143);
+        assert_eq!(State::Killed(Signal::SIGKILL).status(), 137);
+        assert_eq!(State::Stopped.status(), 148);
+    }
+}
@@ -0,0 +1,548 @@
+// sh の字句解析. 入力を単語と演算子に分ける.
+//
+// Words keep their quotes: what a quote means depends on the expansion
+// that is applied to the word later, so they are only removed then.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Pipe,
+    AndIf,
+    OrIf,
+    Semi,
+    Amp,
+    Less,
+    Great,
+    DGreat,
+    // <<, <<-, <<<
+    DLess,
+    DLessDash,
+    TLess,
+    // <&, >&, <>, &>
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    AndGreat,
+    LParen,
+    RParen,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Word(String),
+    // the digits of 2>file: the descriptor a redirection applies to
+    IoNumber(i32),
+    Op(Op),
+    Newline,
+    // takes the place of the delimiter after << and <<-
+    HereDoc(HereDoc),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HereDoc {
+    pub body: String,
+    // false when any part of the delimiter was quoted
+    pub expand: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LexError {
+    // an open quote or a trailing backslash: more input is needed
+    Incomplete,
+}
+
+// longest first, so that ">>" is not read as two ">"
+const OPERATORS: [(&str, Op); 17] = [
+    ("<<-", Op::DLessDash),
+    ("<<<", Op::TLess),
+    ("&&", Op::AndIf),
+    ("||", Op::OrIf),
+    (">>", Op::DGreat),
+    ("<<", Op::DLess),
+    ("<&", Op::LessAnd),
+    (">&", Op::GreatAnd),
+    ("<>", Op::LessGreat),
+    ("&>", Op::AndGreat),
+    ("|", Op::Pipe),
+    (";", Op::Semi),
+    ("&", Op::Amp),
+    ("<", Op::Less),
+    (">", Op::Great),
+    ("(", Op::LParen),
+    (")", Op::RParen),
+];
+
+impl Op {
+    pub fn is_redirect(self) -> bool {
+        !matches!(
+            self,
+            Op::Pipe | Op::AndIf | Op::OrIf | Op::Semi | Op::Amp | Op::LParen | Op::RParen
+        )
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = OPERATORS
+            .iter()
+            .find(|(_, op)| op == self)
+            .map(|(text, _)| *text)
+            .unwrap_or("?");
+        write!(f, "{}", text)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::IoNumber(fd) => write!(f, "{}", fd),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::Newline => write!(f, "newline"),
+            Token::HereDoc(_) => write!(f, "here-document"),
+        }
+    }
+}
+
+fn is_operator_start(c: char) -> bool {
+    "|&;<>()".contains(c)
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    // here-documents whose bodies start after the next newline:
+    // (index of the delimiter token, whether <<- strips tabs)
+    let mut pending = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ' ' || c == '\t' {
+            i += 1;
+        } else if c == '\n' {
+            tokens.push(Token::Newline);
+            i += 1;
+            for (index, strip_tabs) in pending.drain(..) {
+                let (here_doc, next) = read_here_doc(&chars, i, &tokens[index], strip_tabs)?;
+                tokens[index] = Token::HereDoc(here_doc);
+                i = next;
+            }
+        } else if c == '#' {
+            // コメントは行末まで
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '\\' && chars.get(i + 1) == Some(&'\n') {
+            // line continuation between words
+            if i + 2 == chars.len() {
+                return Err(LexError::Incomplete);
+            }
+            i += 2;
+        } else if is_operator_start(c) {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let &(text, op) = OPERATORS
+                .iter()
+                .find(|(text, _)| rest.starts_with(text))
+                .unwrap();
+            tokens.push(Token::Op(op));
+            i += text.chars().count();
+        } else {
+            let (word, next) = read_word(&chars, i)?;
+            // 2>&1>f: the 1 is the target of >&, not the descriptor of >
+            let is_target = matches!(tokens.last(), Some(Token::Op(op)) if op.is_redirect());
+            let redirects_fd = !is_target && matches!(chars.get(next), Some('<') | Some('>'));
+            match word.parse() {
+                Ok(fd) if redirects_fd && word.chars().all(|c| c.is_ascii_digit()) => {
+                    tokens.push(Token::IoNumber(fd))
+                }
+                _ => {
+                    let strip_tabs = match tokens.last() {
+                        Some(Token::Op(Op::DLess)) => Some(false),
+                        Some(Token::Op(Op::DLessDash)) => Some(true),
+                        _ => None,
+                    };
+                    if let Some(strip_tabs) = strip_tabs {
+                        pending.push((tokens.len(), strip_tabs));
+                    }
+                    tokens.push(Token::Word(word));
+                }
+            }
+            i = next;
+        }
+    }
+
+    // the bodies have not been typed yet
+    if !pending.is_empty() {
+        return Err(LexError::Incomplete);
+    }
+
+    Ok(tokens)
+}
+
+// Read the lines of a here-document starting at `start`, up to the line
+// that is the delimiter. Returns the body and the index after that line.
+fn read_here_doc(
+    chars: &[char],
+    start: usize,
+    delimiter: &Token,
+    strip_tabs: bool,
+) -> Result<(HereDoc, usize), LexError> {
+    let (delimiter, expand) = match delimiter {
+        Token::Word(word) => here_doc_delimiter(word),
+        _ => unreachable!(),
+    };
+    let mut body = String::new();
+    let mut i = start;
+
+    loop {
+        // a line without its newline has not been read completely
+        let end = find(chars, i, '\n').ok_or(LexError::Incomplete)?;
+        let mut line = &chars[i..end];
+        i = end + 1;
+        if strip_tabs {
+            while line.first() == Some(&'\t') {
+                line = &line[1..];
+            }
+        }
+
+        let line: String = line.iter().collect();
+        if line == delimiter {
+            return Ok((HereDoc { body, expand }, i));
+        }
+        body.push_str(&line);
+        body.push('\n');
+    }
+}
+
+// The delimiter of a here-document is the word with its quotes removed.
+// Quoting any part of it keeps the body from being expanded.
+fn here_doc_delimiter(word: &str) -> (String, bool) {
+    let mut delimiter = String::new();
+    let mut expand = true;
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                delimiter.extend(chars.next());
+                expand = false;
+            }
+            '\'' | '"' => expand = false,
+            _ => delimiter.push(c),
+        }
+    }
+
+    (delimiter, expand)
+}
+
+// Read the word starting at `start` up to the first unquoted blank or
+// operator. Returns the word with its quotes and the index after it.
+fn read_word(chars: &[char], start: usize) -> Result<(String, usize), LexError> {
+    let mut word = String::new();
+    let mut i = start;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ' ' || c == '\t' || c == '\n' || is_operator_start(c) {
+            break;
+        }
+
+        match c {
+            '\\' => match chars.get(i + 1) {
+                // the line continues on input that has not been read yet
+                Some('\n') if i + 2 == chars.len() => return Err(LexError::Incomplete),
+                Some('\n') => i += 2,
+                Some(&next) => {
+                    word.push(c);
+                    word.push(next);
+                    i += 2;
+                }
+                None => return Err(LexError::Incomplete),
+            },
+            '\'' => {
+                let end = find(chars, i + 1, '\'').ok_or(LexError::Incomplete)?;
+                word.extend(&chars[i..=end]);
+                i = end + 1;
+            }
+            '"' => {
+                i = read_double_quoted(chars, i, &mut word)?;
+            }
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                i = read_braced(chars, i, false, &mut word)?;
+            }
+            '$' if chars.get(i + 1) == Some(&'(') => {
+                i = read_substitution(chars, i, &mut word)?;
+            }
+            '`' => {
+                i = read_backquoted(chars, i, &mut word)?;
+            }
+            _ => {
+                word.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    Ok((word, i))
+}
+
+// Copy a ${...} expansion, in which blanks and operators do not end the
+// word, up to its closing brace. Inside double quotes a ' is an ordinary
+// character.
+fn read_braced(
+    chars: &[char],
+    start: usize,
+    in_double: bool,
+    word: &mut String,
+) -> Result<usize, LexError> {
+    word.push_str("${");
+    let mut depth = 1;
+    let mut i = start + 2;
+
+    while depth > 0 {
+        match chars.get(i) {
+            None => return Err(LexError::Incomplete),
+            Some('\\') => {
+                word.extend(chars.get(i..i + 2).ok_or(LexError::Incomplete)?);
+                i += 2;
+            }
+            Some('\'') if !in_double => {
+                let end = find(chars, i + 1, '\'').ok_or(LexError::Incomplete)?;
+                word.extend(&chars[i..=end]);
+                i = end + 1;
+            }
+            Some('"') => i = read_double_quoted(chars, i, word)?,
+            Some('$') if chars.get(i + 1) == Some(&'(') => i = read_substitution(chars, i, word)?,
+            Some('`') => i = read_backquoted(chars, i, word)?,
+            Some(&c) => {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                word.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    Ok(i)
+}
+
+// Copy a double quoted string, quotes included, dropping line continuations.
+fn read_double_quoted(chars: &[char], start: usize, word: &mut String) -> Result<usize, LexError> {
+    word.push('"');
+    let mut i = start + 1;
+
+    loop {
+        match chars.get(i) {
+            None => return Err(LexError::Incomplete),
+            Some('"') => {
+                word.push('"');
+                return Ok(i + 1);
+            }
+            Some('\\') => match chars.get(i + 1) {
+                Some('\n') => i += 2,
+                Some(&next) => {
+                    word.push('\\');
+                    word.push(next);
+                    i += 2;
+                }
+                None => return Err(LexError::Incomplete),
+            },
+            Some('$') if chars.get(i + 1) == Some(&'{') => i = read_braced(chars, i, true, word)?,
+            Some('$') if chars.get(i + 1) == Some(&'(') => i = read_substitution(chars, i, word)?,
+            Some('`') => i = read_backquoted(chars, i, word)?,
+            Some(&c) => {
+                word.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+// Copy a $(command) or $((expression)) up to its closing parenthesis. The
+// quotes inside are those of the command, even within double quotes.
+fn read_substitution(chars: &[char], start: usize, word: &mut String) -> Result<usize, LexError> {
+    word.push_str("$(");
+    let mut depth = 1;
+    let mut i = start + 2;
+
+    while depth > 0 {
+        match chars.get(i) {
+            None => return Err(LexError::Incomplete),
+            Some('\\') => {
+                word.extend(chars.get(i..i + 2).ok_or(LexError::Incomplete)?);
+                i += 2;
+            }
+            Some('\'') => {
+                let end = find(chars, i + 1, '\'').ok_or(LexError::Incomplete)?;
+                word.extend(&chars[i..=end]);
+                i = end + 1;
+            }
+            Some('"') => i = read_double_quoted(chars, i, word)?,
+            Some('`') => i = read_backquoted(chars, i, word)?,
+            Some('$') if chars.get(i + 1) == Some(&'{') => i = read_braced(chars, i, false, word)?,
+            Some(&c) => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                word.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    Ok(i)
+}
+
+// Copy a `command` up to the closing backquote that is not escaped.
+fn read_backquoted(chars: &[char], start: usize, word: &mut String) -> Result<usize, LexError> {
+    word.push('`');
+    let mut i = start + 1;
+
+    loop {
+        match chars.get(i) {
+            None => return Err(LexError::Incomplete),
+            Some('`') => {
+                word.push('`');
+                return Ok(i + 1);
+            }
+            Some('\\') => {
+                word.extend(chars.get(i..i + 2).ok_or(LexError::Incomplete)?);
+                i += 2;
+            }
+            Some(&c) => {
+                word.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn find(chars: &[char], from: usize, target: char) -> Option<usize> {
+    chars[from..]
+        .iter()
+        .position(|&c| c == target)
+        .map(|pos| from + pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(input: &str) -> Vec<String> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Word(word) => Some(word),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quotes_are_kept() {
+        assert_eq!(
+            words(r#"echo 'a b' "c d" e\ f"#),
+            ["echo", "'a b'", "\"c d\"", "e\\ f"]
+        );
+        assert_eq!(words(r#"a"b;c"'|'d"#), [r#"a"b;c"'|'d"#]);
+        assert_eq!(words(r#""a \" b" 'c\'"#), [r#""a \" b""#, r"'c\'"]);
+        assert_eq!(words("'a\nb'"), ["'a\nb'"]);
+    }
+
+    #[test]
+    fn backslashes() {
+        assert_eq!(words(r"a\;b \|c \\"), [r"a\;b", r"\|c", r"\\"]);
+        assert_eq!(words(r"\'a \#b"), [r"\'a", r"\#b"]);
+        assert_eq!(
+            tokenize("a\\ \n").unwrap(),
+            [Token::Word("a\\ ".to_string()), Token::Newline]
+        );
+    }
+
+    #[test]
+    fn line_continuation() {
+        assert_eq!(words("echo a \\\nb\n"), ["echo", "a", "b"]);
+        // inside double quotes too, but not inside single quotes
+        assert_eq!(words("echo \"a\\\nb\"\n"), ["echo", "\"ab\""]);
+        assert_eq!(words("echo 'a\\\nb'\n"), ["echo", "'a\\\nb'"]);
+        assert_eq!(tokenize("echo a\\\n"), Err(LexError::Incomplete));
+        assert_eq!(tokenize("echo a \\\n"), Err(LexError::Incomplete));
+    }
+
+    #[test]
+    fn incomplete_quotes() {
+        assert_eq!(tokenize("echo 'a\n"), Err(LexError::Incomplete));
+        assert_eq!(tokenize("echo \"a\n"), Err(LexError::Incomplete));
+        assert_eq!(tokenize("echo \"a\\\"\n"), Err(LexError::Incomplete));
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(words("a#b '#c' #d e"), ["a#b", "'#c'"]);
+        assert_eq!(
+            tokenize("# only a comment\nx\n").unwrap(),
+            [Token::Newline, Token::Word("x".to_string()), Token::Newline]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            tokenize("a&&b||c;d&e|f\n").unwrap(),
+            [
+                Token::Word("a".to_string()),
+                Token::Op(Op::AndIf),
+                Token::Word("b".to_string()),
+                Token::Op(Op::OrIf),
+                Token::Word("c".to_string()),
+                Token::Op(Op::Semi),
+                Token::Word("d".to_string()),
+                Token::Op(Op::Amp),
+                Token::Word("e".to_string()),
+                Token::Op(Op::Pipe),
+                Token::Word("f".to_string()),
+                Token::Newline,
+            ]
+        );
+        assert_eq!(
+            tokenize("(a)").unwrap(),
+            [
+                Token::Op(Op::LParen),
+                Token::Word("a".to_string()),
+                Token::Op(Op::RParen)
+            ]
+        );
+        // the longest operator wins
+        assert_eq!(
+            tokenize(">>|").unwrap(),
+            [Token::Op(Op::DGreat), Token::Op(Op::Pipe)]
+        );
+    }
+
+    #[test]
+    fn substitutions() {
+        assert_eq!(
+            words("echo $(a $(b c) d) e"),
+            ["echo", "$(a $(b c) d)", "e"]
+        );
+        assert_eq!(words("x=$((1 + (2 * 3)))"), ["x=$((1 + (2 * 3)))"]);
+        assert_eq!(words("$(echo `date` ;)"), ["$(echo `date` ;)"]);
+        assert_eq!(
+            words("$(echo ')' \")\" \\)) x"),
+            ["$(echo ')' \")\" \\))", "x"]
+        );
+        assert_eq!(words("$(echo \")\")"), ["$(echo \")\")"]);
+        assert_eq!(words("\"$(echo \"a b\")\""), ["\"$(echo \"a b\")\""]);
+        assert_eq!(words("`echo \\` a` b"), ["`echo \\` a`", "b"]);
+        assert_eq!(tokenize("echo $(a\n"), Err(LexError::Incomplete));
+        assert_eq!(tokenize("echo `a\n"), Err(LexError::Incomplete));
+    }
+}
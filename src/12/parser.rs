@@ -0,0 +1,563 @@
+// sh の構文解析. トークン列から再帰下降で構文木を作る.
+//
+//   list     := and_or ((';' | '&' | newline) and_or)*
+//   and_or   := pipeline (('&&' | '||') newline* pipeline)*
+//   pipeline := ['!'] command ('|' newline* command)*
+//   command  := simple | '(' list ')' redirect* | '{' list '}' redirect*
+//   simple   := (word | redirect)+
+//   redirect := [io_number] redirect_op (word | here_doc)
+
+use crate::lexer::{HereDoc, Op, Token};
+use std::fmt;
+
+#[derive(Debug)]
+pub struct Redirect {
+    // the descriptor given before the operator, or the operator's default
+    pub fd: i32,
+    pub op: Op,
+    pub target: Target,
+}
+
+#[derive(Debug)]
+pub enum Target {
+    // a file name, a descriptor number or "-"
+    Word(String),
+    HereDoc(HereDoc),
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Simple {
+        words: Vec<String>,
+        redirects: Vec<Redirect>,
+    },
+    // ( list ): run in a forked copy of the shell
+    Subshell {
+        body: List,
+        redirects: Vec<Redirect>,
+    },
+    // { list; }: run in the shell itself
+    Group {
+        body: List,
+        redirects: Vec<Redirect>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Pipeline {
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    // ended with &
+    pub background: bool,
+}
+
+#[derive(Debug)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    // the input stops in the middle of a command: more input is needed
+    Incomplete,
+    Unexpected(Token),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+pub fn parse(tokens: Vec<Token>) -> Result<List, ParseError> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let list = parser.list()?;
+
+    match parser.next() {
+        None => Ok(list),
+        Some(token) => Err(ParseError::Unexpected(token)),
+    }
+}
+
+// reserved words are only recognized unquoted, as a whole word
+fn is_word(token: Option<&Token>, reserved: &str) -> bool {
+    matches!(token, Some(Token::Word(word)) if word == reserved)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    // An error for the token at the current position: running out of
+    // tokens only means that the rest has not been typed yet.
+    fn unexpected(&mut self) -> ParseError {
+        match self.next() {
+            Some(token) => ParseError::Unexpected(token),
+            None => ParseError::Incomplete,
+        }
+    }
+
+    // Commands up to the end of the input, a ')' or a '}'.
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                None | Some(Token::Op(Op::RParen)) => break,
+                token if is_word(token, "}") => break,
+                _ => {}
+            }
+
+            let mut and_or = self.and_or()?;
+            match self.peek() {
+                Some(Token::Op(Op::Amp)) => {
+                    and_or.background = true;
+                    self.pos += 1;
+                }
+                Some(Token::Op(Op::Semi)) | Some(Token::Newline) => self.pos += 1,
+                _ => {
+                    items.push(and_or);
+                    break;
+                }
+            }
+            items.push(and_or);
+        }
+
+        Ok(List { items })
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let connector = match self.peek() {
+                Some(Token::Op(Op::AndIf)) => Connector::And,
+                Some(Token::Op(Op::OrIf)) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+
+        Ok(AndOr {
+            first,
+            rest,
+            background: false,
+        })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = is_word(self.peek(), "!");
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut commands = vec![self.command()?];
+        while self.peek() == Some(&Token::Op(Op::Pipe)) {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        if self.peek() == Some(&Token::Op(Op::LParen)) {
+            self.pos += 1;
+            let body = self.compound_body()?;
+            if self.peek() != Some(&Token::Op(Op::RParen)) {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+            let redirects = self.redirects()?;
+            return Ok(Command::Subshell { body, redirects });
+        }
+
+        if is_word(self.peek(), "{") {
+            self.pos += 1;
+            let body = self.compound_body()?;
+            if !is_word(self.peek(), "}") {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+            let redirects = self.redirects()?;
+            return Ok(Command::Group { body, redirects });
+        }
+
+        self.simple_command()
+    }
+
+    fn compound_body(&mut self) -> Result<List, ParseError> {
+        let body = self.list()?;
+        if body.items.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(body)
+    }
+
+    fn simple_command(&mut self) -> Result<Command, ParseError> {
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                Some(Token::IoNumber(_)) => redirects.push(self.redirect()?),
+                Some(Token::Op(op)) if op.is_redirect() => redirects.push(self.redirect()?),
+                _ => break,
+            }
+        }
+
+        if words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(Command::Simple { words, redirects })
+    }
+
+    fn redirects(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::IoNumber(_)) => {}
+                Some(Token::Op(op)) if op.is_redirect() => {}
+                _ => break,
+            }
+            redirects.push(self.redirect()?);
+        }
+        Ok(redirects)
+    }
+
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let fd = match self.peek() {
+            Some(&Token::IoNumber(fd)) => {
+                self.pos += 1;
+                Some(fd)
+            }
+            _ => None,
+        };
+        // the lexer only makes an IoNumber in front of < or >
+        let op = match self.next() {
+            Some(Token::Op(op)) if op.is_redirect() => op,
+            _ => unreachable!(),
+        };
+        let fd = fd.unwrap_or_else(|| default_fd(op));
+
+        let target = match self.next() {
+            Some(Token::HereDoc(here_doc)) => Target::HereDoc(here_doc),
+            Some(Token::Word(word)) if op != Op::DLess && op != Op::DLessDash => Target::Word(word),
+            Some(token) => return Err(ParseError::Unexpected(token)),
+            None => return Err(ParseError::Incomplete),
+        };
+        Ok(Redirect { fd, op, target })
+    }
+}
+
+fn default_fd(op: Op) -> i32 {
+    match op {
+        Op::Less | Op::DLess | Op::DLessDash | Op::TLess | Op::LessAnd | Op::LessGreat => 0,
+        _ => 1,
+    }
+}
+
+// The commands are shown as typed, give or take blanks, by jobs.
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.fd != default_fd(self.op) {
+            write!(f, "{}", self.fd)?;
+        }
+        match self.target {
+            Target::Word(ref word) => write!(f, "{}{}", self.op, word),
+            Target::HereDoc(_) => write!(f, "{} ...", self.op),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let redirects = match self {
+            Command::Simple { words, redirects } => {
+                write!(f, "{}", words.join(" "))?;
+                if !words.is_empty() && !redirects.is_empty() {
+                    write!(f, " ")?;
+                }
+                return write_joined(f, redirects, " ");
+            }
+            Command::Subshell { body, redirects } => {
+                write!(f, "( {} )", body)?;
+                redirects
+            }
+            Command::Group { body, redirects } => {
+                write!(f, "{{ {}; }}", body)?;
+                redirects
+            }
+        };
+        for redirect in redirects {
+            write!(f, " {}", redirect)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        write_joined(f, &self.commands, " | ")
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            let op = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {} {}", op, pipeline)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, and_or) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", and_or)?;
+            if and_or.background {
+                write!(f, " &")?;
+            } else if i + 1 < self.items.len() {
+                write!(f, ";")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_joined<T: fmt::Display>(
+    f: &mut fmt::Formatter,
+    items: &[T],
+    separator: &str,
+) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    fn parse_str(input: &str) -> Result<List, ParseError> {
+        parse(tokenize(input).unwrap())
+    }
+
+    #[test]
+    fn and_or_lists() {
+        let list = parse_str("a && b || c; d &\n").unwrap();
+        assert_eq!(list.items.len(), 2);
+        let connectors: Vec<Connector> = list.items[0].rest.iter().map(|(c, _)| *c).collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or]);
+        assert!(!list.items[0].background);
+        assert!(list.items[1].background);
+        assert_eq!(list.to_string(), "a && b || c; d &");
+
+        // the next pipeline may be on the next line
+        assert_eq!(parse_str("a &&\nb\n").unwrap().to_string(), "a && b");
+        assert_eq!(parse_str("a ||").unwrap_err(), ParseError::Incomplete);
+        assert!(matches!(
+            parse_str("&& a\n"),
+            Err(ParseError::Unexpected(_))
+        ));
+        assert!(matches!(
+            parse_str("a && && b\n"),
+            Err(ParseError::Unexpected(_))
+        ));
+    }
+
+    #[test]
+    fn negation() {
+        let list = parse_str("! a | b |\nc\n").unwrap();
+        let pipeline = &list.items[0].first;
+        assert!(pipeline.negated);
+        assert_eq!(pipeline.commands.len(), 3);
+        assert_eq!(list.to_string(), "! a | b | c");
+
+        let list = parse_str("a && ! b\n").unwrap();
+        assert!(!list.items[0].first.negated);
+        assert!(list.items[0].rest[0].1.negated);
+        // only a whole unquoted word is the reserved word
+        assert!(!parse_str("'!' a\n").unwrap().items[0].first.negated);
+        assert!(!parse_str("!a\n").unwrap().items[0].first.negated);
+    }
+
+    #[test]
+    fn subshells() {
+        let list = parse_str("(a; b) | c\n").unwrap();
+        match &list.items[0].first.commands[0] {
+            Command::Subshell { body, .. } => assert_eq!(body.items.len(), 2),
+            command => panic!("not a subshell: {:?}", command),
+        }
+        assert_eq!(list.to_string(), "( a; b ) | c");
+        assert_eq!(
+            parse_str("((a) && b)\n").unwrap().to_string(),
+            "( ( a ) && b )"
+        );
+        assert_eq!(parse_str("(a\n").unwrap_err(), ParseError::Incomplete);
+        assert!(matches!(parse_str("a)\n"), Err(ParseError::Unexpected(_))));
+    }
+
+    #[test]
+    fn groups() {
+        let list = parse_str("{ a; b; } && { c\n}\n").unwrap();
+        assert!(matches!(
+            list.items[0].first.commands[0],
+            Command::Group { .. }
+        ));
+        assert!(matches!(
+            list.items[0].rest[0].1.commands[0],
+            Command::Group { .. }
+        ));
+        assert_eq!(list.to_string(), "{ a; b; } && { c; }");
+        // } is only a reserved word where a command starts
+        assert_eq!(parse_str("{ echo }\n").unwrap_err(), ParseError::Incomplete);
+        assert_eq!(parse_str("{ a;").unwrap_err(), ParseError::Incomplete);
+    }
+
+    fn redirects_of(command: &Command) -> &[Redirect] {
+        match command {
+            Command::Simple { redirects, .. }
+            | Command::Subshell { redirects, .. }
+            | Command::Group { redirects, .. } => redirects,
+        }
+    }
+
+    #[test]
+    fn redirections() {
+        let list = parse_str("cmd <in >out 2>>log 2>&1 arg 3<>rw 0<&3 4>&- &>all\n").unwrap();
+        let command = &list.items[0].first.commands[0];
+        match command {
+            Command::Simple { words, .. } => assert_eq!(words, &["cmd", "arg"]),
+            command => panic!("not a simple command: {:?}", command),
+        }
+        let parts: Vec<(i32, Op, &str)> = redirects_of(command)
+            .iter()
+            .map(|r| match &r.target {
+                Target::Word(word) => (r.fd, r.op, word.as_str()),
+                Target::HereDoc(_) => panic!("not a word: {:?}", r),
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                (0, Op::Less, "in"),
+                (1, Op::Great, "out"),
+                (2, Op::DGreat, "log"),
+                (2, Op::GreatAnd, "1"),
+                (3, Op::LessGreat, "rw"),
+                (0, Op::LessAnd, "3"),
+                (4, Op::GreatAnd, "-"),
+                (1, Op::AndGreat, "all"),
+            ]
+        );
+        assert_eq!(
+            list.to_string(),
+            "cmd arg <in >out 2>>log 2>&1 3<>rw <&3 4>&- &>all"
+        );
+
+        // compound commands take them after the closing word
+        let list = parse_str("( a ) >out && { b; } 2>err\n").unwrap();
+        assert_eq!(redirects_of(&list.items[0].first.commands[0]).len(), 1);
+        assert_eq!(redirects_of(&list.items[0].rest[0].1.commands[0])[0].fd, 2);
+
+        assert_eq!(
+            parse_str("cat >\n").unwrap_err(),
+            ParseError::Unexpected(Token::Newline)
+        );
+        assert_eq!(parse_str("cat <").unwrap_err(), ParseError::Incomplete);
+        assert!(matches!(
+            parse_str("cat > >out\n"),
+            Err(ParseError::Unexpected(_))
+        ));
+    }
+
+    #[test]
+    fn here_documents() {
+        let input = "cat <<EOF <<-'END' <<<word\n$x\nEOF\n\t\ty\n\tEND\n";
+        let list = parse_str(input).unwrap();
+        let command = &list.items[0].first.commands[0];
+        match redirects_of(command) {
+            [first, second, third] => {
+                assert_eq!((first.fd, first.op), (0, Op::DLess));
+                match &first.target {
+                    Target::HereDoc(here_doc) => {
+                        assert_eq!(here_doc.body, "$x\n");
+                        assert!(here_doc.expand);
+                    }
+                    target => panic!("not a here-document: {:?}", target),
+                }
+                assert_eq!((second.fd, second.op), (0, Op::DLessDash));
+                match &second.target {
+                    Target::HereDoc(here_doc) => {
+                        assert_eq!(here_doc.body, "y\n");
+                        assert!(!here_doc.expand);
+                    }
+                    target => panic!("not a here-document: {:?}", target),
+                }
+                assert_eq!((third.fd, third.op), (0, Op::TLess));
+                assert!(matches!(&third.target, Target::Word(word) if word == "word"));
+            }
+            redirects => panic!("not three redirects: {:?}", redirects),
+        }
+        assert_eq!(list.to_string(), "cat << ... <<- ... <<<word");
+
+        // the body is still to come
+        assert_eq!(
+            tokenize("cat <<EOF\nline\n").unwrap_err(),
+            crate::lexer::LexError::Incomplete
+        );
+    }
+}
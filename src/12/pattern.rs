@@ -0,0 +1,126 @@
+// sh のパターン照合. * は任意の文字列, ? は任意の 1 文字, [...] は文字クラス.
+
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // the last * seen and the position in the text it is tried from
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if pattern.get(p) == Some(&'*') {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some(next) = match_one(&pattern, p, text[t]) {
+            p = next;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // let the * take one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Match the element of the pattern at `p` other than * against `c`.
+// Returns the position of the next element.
+fn match_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match pattern.get(p)? {
+        '?' => Some(p + 1),
+        '[' => match match_class(pattern, p, c) {
+            Some((matched, next)) => Some(next).filter(|_| matched),
+            // an unterminated [ is an ordinary character
+            None => Some(p + 1).filter(|_| c == '['),
+        },
+        '\\' if p + 1 < pattern.len() => Some(p + 2).filter(|_| pattern[p + 1] == c),
+        &literal => Some(p + 1).filter(|_| literal == c),
+    }
+}
+
+// [abc], [a-z], [!a-z] or [^a-z]. Returns whether `c` is in the class and
+// the position after the closing ], or None if there is none.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut p = start + 1;
+    let negated = matches!(pattern.get(p), Some('!') | Some('^'));
+    if negated {
+        p += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(p)?;
+        // a ] right after the [ is part of the class
+        if low == ']' && !first {
+            return Some((matched != negated, p + 1));
+        }
+        first = false;
+        if low == '\\' {
+            p += 1;
+            low = *pattern.get(p)?;
+        }
+
+        let high = match (pattern.get(p + 1), pattern.get(p + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                p += 2;
+                high
+            }
+            _ => low,
+        };
+        if low <= c && c <= high {
+            matched = true;
+        }
+        p += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("*.rs", "sh.rs"));
+        assert!(!matches("*.rs", "sh.rs~"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("?", "é"));
+        assert!(!matches("?", ""));
+        assert!(matches("??.rs", "sh.rs"));
+        assert!(!matches("?.rs", "sh.rs"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[a-z]", "q"));
+        assert!(!matches("[a-z]", "Q"));
+        assert!(matches("[abc0-9]x", "7x"));
+        assert!(matches("[!x]", "y"));
+        assert!(!matches("[!x]", "x"));
+        assert!(!matches("[^a-c]", "b"));
+        // ] first in the class is a member
+        assert!(matches("[]]", "]"));
+        assert!(matches("[!]]", "a"));
+        assert!(!matches("[!]]", "]"));
+        assert!(matches("[a-]", "-"));
+        // an unterminated [ is itself
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn escaped() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+        assert!(matches("a\\?", "a?"));
+        assert!(!matches("a\\?", "ab"));
+        assert!(matches("\\[a]", "[a]"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("\\\\", "\\"));
+    }
+}
@@ -0,0 +1,411 @@
+// spawn --sandbox: 名前空間, no_new_privs, seccomp でコマンドを閉じ込める.
+//
+// Everything here runs in the forked child, before execvp. It works for an
+// unprivileged user as long as user namespaces are enabled.
+
+use nix::mount::{mount, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::signal::{kill, signal, SigHandler};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{chdir, chroot, fork, getgid, getpid, getuid, ForkResult, Pid};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::nix_to_io;
+
+// whether the seccomp filter knows this architecture: --sandbox fails
+// elsewhere, while the rest of spawn works as usual
+pub const SUPPORTED: bool = cfg!(any(target_arch = "x86_64", target_arch = "aarch64"));
+
+// offsets into struct seccomp_data
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const SECCOMP_DATA_NR: u32 = 0;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+// x32 system calls share the x86_64 architecture value
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// system calls that can be named in --seccomp-deny
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const SYSCALLS: [(&str, libc::c_long); 0] = [];
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const SYSCALLS: [(&str, libc::c_long); 40] = [
+    ("acct", libc::SYS_acct),
+    ("add_key", libc::SYS_add_key),
+    ("bind", libc::SYS_bind),
+    ("bpf", libc::SYS_bpf),
+    ("chroot", libc::SYS_chroot),
+    ("clock_settime", libc::SYS_clock_settime),
+    ("connect", libc::SYS_connect),
+    ("delete_module", libc::SYS_delete_module),
+    ("execve", libc::SYS_execve),
+    ("execveat", libc::SYS_execveat),
+    ("fanotify_init", libc::SYS_fanotify_init),
+    ("finit_module", libc::SYS_finit_module),
+    ("init_module", libc::SYS_init_module),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("kexec_load", libc::SYS_kexec_load),
+    ("keyctl", libc::SYS_keyctl),
+    ("kill", libc::SYS_kill),
+    ("listen", libc::SYS_listen),
+    ("mount", libc::SYS_mount),
+    ("name_to_handle_at", libc::SYS_name_to_handle_at),
+    ("open_by_handle_at", libc::SYS_open_by_handle_at),
+    ("perf_event_open", libc::SYS_perf_event_open),
+    ("personality", libc::SYS_personality),
+    ("pivot_root", libc::SYS_pivot_root),
+    ("process_vm_readv", libc::SYS_process_vm_readv),
+    ("process_vm_writev", libc::SYS_process_vm_writev),
+    ("ptrace", libc::SYS_ptrace),
+    ("quotactl", libc::SYS_quotactl),
+    ("reboot", libc::SYS_reboot),
+    ("request_key", libc::SYS_request_key),
+    ("setdomainname", libc::SYS_setdomainname),
+    ("sethostname", libc::SYS_sethostname),
+    ("setns", libc::SYS_setns),
+    ("settimeofday", libc::SYS_settimeofday),
+    ("socket", libc::SYS_socket),
+    ("swapoff", libc::SYS_swapoff),
+    ("swapon", libc::SYS_swapon),
+    ("umount2", libc::SYS_umount2),
+    ("unshare", libc::SYS_unshare),
+    ("userfaultfd", libc::SYS_userfaultfd),
+];
+
+// denied when --seccomp-deny is not given: ways out of the sandbox and
+// ways to reach the kernel's less exercised corners
+pub const DEFAULT_DENY: [&str; 25] = [
+    "acct",
+    "add_key",
+    "bpf",
+    "delete_module",
+    "fanotify_init",
+    "finit_module",
+    "init_module",
+    "kexec_load",
+    "keyctl",
+    "mount",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "perf_event_open",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "setns",
+    "swapoff",
+    "swapon",
+    "umount2",
+    "unshare",
+];
+
+pub struct SandboxOptions {
+    // bind-mounted read-only and made the root directory
+    pub root: Option<PathBuf>,
+    // system calls that fail with EPERM
+    pub deny: Vec<libc::c_long>,
+}
+
+// Parse a comma separated list of system call names; "none" denies nothing.
+pub fn parse_syscalls(list: &str) -> Result<Vec<libc::c_long>, String> {
+    if list == "none" {
+        return Ok(Vec::new());
+    }
+
+    list.split(',')
+        .map(|name| {
+            SYSCALLS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|&(_, nr)| nr)
+                .ok_or(format!("unknown system call {:?}", name))
+        })
+        .collect()
+}
+
+// Move into new user, mount, PID, network and IPC namespaces and set up
+// the file system. A new PID namespace only applies to children, so this
+// forks: the caller continues as PID 1 of the namespace while the original
+// process waits for it and exits the same way.
+pub fn enter(opts: &SandboxOptions) -> io::Result<()> {
+    let (uid, gid) = (getuid(), getgid());
+    unshare(
+        CloneFlags::CLONE_NEWUSER
+            | CloneFlags::CLONE_NEWNS
+            | CloneFlags::CLONE_NEWPID
+            | CloneFlags::CLONE_NEWNET
+            | CloneFlags::CLONE_NEWIPC,
+    )
+    .map_err(nix_to_io)?;
+
+    // root inside is the calling user outside
+    fs::write("/proc/self/setgroups", "deny")?;
+    fs::write("/proc/self/uid_map", format!("0 {} 1", uid))?;
+    fs::write("/proc/self/gid_map", format!("0 {} 1", gid))?;
+
+    match unsafe { fork() }.map_err(nix_to_io)? {
+        ForkResult::Parent { child } => mirror(child),
+        ForkResult::Child => {}
+    }
+
+    // nothing may outlive the process spawn is waiting for
+    if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    mount_file_systems(opts)
+}
+
+// Wait for the namespace's PID 1 and end this process the same way, so
+// that spawn sees the command's own status.
+fn mirror(child: Pid) -> ! {
+    loop {
+        match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, code)) => process::exit(code),
+            Ok(WaitStatus::Signaled(_, sig, _)) => {
+                unsafe { signal(sig, SigHandler::SigDfl) }.ok();
+                kill(getpid(), sig).ok();
+                process::exit(128 + sig as i32);
+            }
+            Ok(_) => {}
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+            Err(_) => process::exit(125),
+        }
+    }
+}
+
+fn mount_file_systems(opts: &SandboxOptions) -> io::Result<()> {
+    let none: Option<&str> = None;
+
+    // keep every mount made below inside the namespace
+    mount(none, "/", none, MsFlags::MS_REC | MsFlags::MS_PRIVATE, none).map_err(nix_to_io)?;
+
+    let cwd = env::current_dir()?;
+    let root = match opts.root {
+        Some(ref root) => {
+            mount(
+                Some(root),
+                root,
+                none,
+                MsFlags::MS_BIND | MsFlags::MS_REC,
+                none,
+            )
+            .map_err(nix_to_io)?;
+            // mounts under /dev must be carried over before the root turns read-only
+            let dev = root.join("dev");
+            if dev.is_dir() {
+                mount(
+                    Some("/dev"),
+                    &dev,
+                    none,
+                    MsFlags::MS_BIND | MsFlags::MS_REC,
+                    none,
+                )
+                .map_err(nix_to_io)?;
+            }
+            remount_read_only(root, &dev)?;
+            root.clone()
+        }
+        None => PathBuf::from("/"),
+    };
+
+    mount(
+        Some("proc"),
+        &root.join("proc"),
+        Some("proc"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        none,
+    )
+    .map_err(nix_to_io)?;
+    mount(
+        Some("tmpfs"),
+        &root.join("tmp"),
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some("mode=1777"),
+    )
+    .map_err(nix_to_io)?;
+
+    if opts.root.is_some() {
+        chroot(&root).map_err(nix_to_io)?;
+        // stay in the same directory if the new root has it
+        if chdir(&cwd).is_err() {
+            chdir("/").map_err(nix_to_io)?;
+        }
+    }
+
+    Ok(())
+}
+
+// A bind mount ignores MS_RDONLY until it is remounted, and a recursive one
+// brings along mounts below it that each have to be remounted as well.
+// Those under `skip` stay writable: /dev/null and the like must work.
+fn remount_read_only(root: &Path, skip: &Path) -> io::Result<()> {
+    let root = fs::canonicalize(root)?;
+    let skip = fs::canonicalize(skip).unwrap_or_else(|_| skip.to_path_buf());
+
+    for point in mount_points()? {
+        if point.starts_with(&root) && !(point != root && point.starts_with(&skip)) {
+            remount(&point)?;
+        }
+    }
+    Ok(())
+}
+
+// Inside a user namespace the flags locked by the outer mount must be
+// repeated.
+fn remount(path: &Path) -> io::Result<()> {
+    let st = statvfs(path).map_err(nix_to_io)?;
+    let locked = [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ];
+
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    for &(st_flag, ms_flag) in &locked {
+        if st.flags().contains(st_flag) {
+            flags |= ms_flag;
+        }
+    }
+
+    let none: Option<&str> = None;
+    mount(none, path, none, flags, none).map_err(nix_to_io)
+}
+
+// The fifth field of each line of /proc/self/mountinfo, where a space, tab,
+// newline or backslash in the path is written as \NNN in octal.
+fn mount_points() -> io::Result<Vec<PathBuf>> {
+    let info = fs::read("/proc/self/mountinfo")?;
+    let mut points = Vec::new();
+
+    for line in info.split(|&b| b == b'\n') {
+        let field = match line.split(|&b| b == b' ').nth(4) {
+            Some(field) => field,
+            None => continue,
+        };
+        let mut path = Vec::new();
+        let mut i = 0;
+        while i < field.len() {
+            let octal = field.get(i + 1..i + 4).and_then(|digits| {
+                let digits = std::str::from_utf8(digits).ok()?;
+                u8::from_str_radix(digits, 8).ok()
+            });
+            match octal {
+                Some(b) if field[i] == b'\\' => {
+                    path.push(b);
+                    i += 4;
+                }
+                _ => {
+                    path.push(field[i]);
+                    i += 1;
+                }
+            }
+        }
+        points.push(PathBuf::from(OsString::from_vec(path)));
+    }
+
+    Ok(points)
+}
+
+// Last step before execvp: no_new_privs, which an unprivileged process
+// needs before installing a filter, then the filter itself.
+pub fn lock_down(opts: &SandboxOptions) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    install_filter(&opts.deny)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn install_filter(_deny: &[libc::c_long]) -> io::Result<()> {
+    Err(io::Error::other("unsupported architecture"))
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn install_filter(deny: &[libc::c_long]) -> io::Result<()> {
+    let mut filter = build_filter(deny);
+    let prog = libc::sock_fprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_mut_ptr(),
+    };
+    let ret = unsafe {
+        libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Classic BPF: kill anything from a foreign architecture, make each denied
+// system call fail with EPERM and allow the rest.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn build_filter(deny: &[libc::c_long]) -> Vec<libc::sock_filter> {
+    let stmt = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jeq = |k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let load = |offset: u32| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset);
+    let ret = |k: u32| stmt(libc::BPF_RET | libc::BPF_K, k);
+
+    let mut filter = vec![
+        load(SECCOMP_DATA_ARCH),
+        jeq(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(SECCOMP_DATA_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    filter.extend_from_slice(&[
+        libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+            jt: 0,
+            jf: 1,
+            k: X32_SYSCALL_BIT,
+        },
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+    ]);
+
+    for &nr in deny {
+        filter.push(jeq(nr as u32, 0, 1));
+        filter.push(ret(
+            libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA)
+        ));
+    }
+    filter.push(ret(libc::SECCOMP_RET_ALLOW));
+
+    filter
+}
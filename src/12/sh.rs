@@ -1,288 +1,842 @@
-use nix::fcntl::{open, OFlag};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+use nix::sys::signal::{killpg, signal, SigHandler, Signal};
 use nix::sys::stat::Mode;
-use nix::sys::wait::{waitpid, WaitStatus};
-use nix::unistd::{chdir, close, dup2, execvp, fork, pipe, ForkResult, Pid};
-use std::env::current_dir;
+use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::{
+    close, dup2, execvpe, fork, getpgrp, getpid, isatty, lseek, pipe, read, setpgid, tcgetpgrp,
+    tcsetpgrp, write, ForkResult, Pid, Whence,
+};
+use std::env;
 use std::ffi::CString;
-use std::io;
-use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
+use std::os::unix::io::RawFd;
 use std::process::exit;
-use whoami;
 
-enum RedirectCategory<'a> {
-    Append(&'a str),
-    Overwrite(&'a str),
-    None,
+mod arith;
+mod builtins;
+mod editor;
+mod expand;
+mod glob;
+mod jobs;
+mod lexer;
+mod parser;
+mod pattern;
+mod vars;
+
+use builtins::is_builtin;
+use editor::{Editor, Input};
+use jobs::{Job, Jobs, State};
+use lexer::{LexError, Op};
+use parser::{AndOr, Command, Connector, List, ParseError, Pipeline, Redirect, Target};
+use vars::Variables;
+
+// what a redirection points its descriptor at
+enum Source {
+    // opened for the redirection: closed once it has been duplicated
+    File(RawFd),
+    // an existing descriptor, for n>&m
+    Fd(RawFd),
+    Close,
 }
 
-struct RedirectInfo<'a> {
-    category: RedirectCategory<'a>,
+// NAME=value words in front of a command
+type Assignments = Vec<(String, String)>;
+
+// ignored by an interactive shell, which leaves them to the foreground job
+const JOB_SIGNALS: [Signal; 5] = [
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+struct Shell {
+    vars: Variables,
+    jobs: Jobs,
+    // the shell owns the terminal: every pipeline gets a process group of
+    // its own and the foreground one is handed the terminal
+    job_control: bool,
+    // the shell's own process group
+    pgid: Pid,
+    // terminal modes to restore once a foreground job stops or ends
+    tmodes: Option<Termios>,
+    // the status of the last $(command), which a command that only assigns
+    // ends with
+    substituted: Option<i32>,
 }
 
 fn main() {
-    let stdin = io::stdin();
-    let stdout = io::stdout();
-    let mut buf_in = BufReader::new(stdin.lock());
+    let mut editor = Editor::new();
     let mut input_string = String::new();
-    let mut buf_out = BufWriter::new(stdout.lock());
+    let hostname = whoami::fallible::hostname().unwrap_or_else(|_| "localhost".to_string());
+    let mut shell = Shell::new();
 
     loop {
-        buf_out
-            .write(format!("{}@{}$ ", whoami::username(), whoami::hostname()).as_bytes())
-            .unwrap_or_else(|why| panic!("aborted: {}", why.to_string()));
-        buf_out
-            .flush()
-            .unwrap_or_else(|why| panic!("aborted: {}", why.to_string()));
+        if input_string.is_empty() {
+            shell.notify_jobs();
+        }
+        let prompt = if input_string.is_empty() {
+            format!("{}@{}$ ", whoami::username(), hostname)
+        } else {
+            // 引用符や括弧が閉じていない, または行末が \ や && なので続きを読む
+            "> ".to_string()
+        };
+        let path = shell.vars.get("PATH").unwrap_or_default();
+
+        let input = match editor.read_line(&prompt, &path) {
+            Ok(input) => input,
+            Err(why) => {
+                eprintln!("sh: {}", why);
+                exit(1);
+            }
+        };
+        match input {
+            Input::Line(line) => input_string.push_str(&line),
+            Input::Cancelled => {
+                input_string.clear();
+                continue;
+            }
+            Input::Eof => break,
+        }
+
+        let tokens = match lexer::tokenize(&input_string) {
+            Ok(tokens) => tokens,
+            Err(LexError::Incomplete) => continue,
+        };
+        let list = match parser::parse(tokens) {
+            Ok(list) => list,
+            Err(ParseError::Incomplete) => continue,
+            Err(ParseError::Unexpected(token)) => {
+                eprintln!("sh: syntax error near unexpected token `{}'", token);
+                input_string.clear();
+                shell.vars.status = 2;
+                continue;
+            }
+        };
+        input_string.clear();
 
-        buf_in
-            .read_line(&mut input_string)
-            .unwrap_or_else(|why| panic!("aborted: {}", why.to_string()));
+        shell.run_list(&list);
+    }
 
-        input_string.remove(input_string.len() - 1);
+    exit(shell.vars.status);
+}
 
-        let args: Vec<&str> = input_string.split_whitespace().collect();
+impl Shell {
+    fn new() -> Shell {
+        let mut shell = Shell {
+            vars: Variables::from_env(),
+            jobs: Jobs::new(),
+            job_control: false,
+            pgid: getpgrp(),
+            tmodes: None,
+            substituted: None,
+        };
+        if isatty(0).unwrap_or(false) {
+            shell.init_job_control();
+        }
+        shell
+    }
 
-        if args.len() == 0 {
-            continue;
+    // Put the shell in a process group of its own in the foreground of the
+    // terminal.
+    fn init_job_control(&mut self) {
+        // started in the background: wait until put in the foreground
+        loop {
+            match tcgetpgrp(0) {
+                Ok(pgrp) if pgrp == getpgrp() => break,
+                Ok(_) => {
+                    killpg(getpgrp(), Signal::SIGTTIN).ok();
+                }
+                Err(_) => return,
+            }
         }
 
-        if args.len() == 1 && args[0] == "exit" {
-            break;
+        for &sig in &JOB_SIGNALS {
+            unsafe { signal(sig, SigHandler::SigIgn) }.ok();
+        }
+        let pid = getpid();
+        // a session leader already leads its process group
+        if setpgid(pid, pid).is_err() && getpgrp() != pid {
+            return;
+        }
+        if tcsetpgrp(0, pid).is_err() {
+            return;
         }
 
-        let (pipe_args, redirect_info) = parse_redirect_info(slice_vec_with_str(args, "|"));
+        self.pgid = pid;
+        self.job_control = true;
+        self.tmodes = tcgetattr(0).ok();
+    }
+
+    // Before a prompt: tell about the jobs that finished or stopped.
+    fn notify_jobs(&mut self) {
+        if self.job_control {
+            for line in self.jobs.notifications() {
+                eprintln!("{}", line);
+            }
+        } else {
+            self.jobs.poll();
+        }
+    }
 
-        if pipe_args.len() == 1 {
-            if pipe_args[0][0] == "cd" {
-                exec_chdir(&pipe_args[0]).unwrap();
+    fn run_list(&mut self, list: &List) {
+        for and_or in &list.items {
+            if and_or.background {
+                self.run_background(and_or);
             } else {
-                singlestage_pipe(pipe_args, redirect_info).unwrap();
+                self.run_and_or(and_or);
+            }
+        }
+    }
+
+    // Start `and_or` as a job of its own and go on without waiting for it.
+    fn run_background(&mut self, and_or: &AndOr) {
+        let job_control = self.job_control;
+        let mut pgid = None;
+        match self.fork_process(&mut pgid, false) {
+            Ok(ForkResult::Parent { child, .. }) => {
+                let id = self.jobs.add(Job::new(child, &[child], and_or.to_string()));
+                self.vars.last_background = Some(child);
+                self.vars.status = 0;
+                if job_control {
+                    eprintln!("[{}] {}", id, child);
+                }
+            }
+            Ok(ForkResult::Child) => {
+                if !job_control {
+                    // without job control, keep the job off the terminal
+                    unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.ok();
+                    unsafe { signal(Signal::SIGQUIT, SigHandler::SigIgn) }.ok();
+                    if let Ok(null) = open("/dev/null", OFlag::O_RDONLY, Mode::empty()) {
+                        dup2(null, 0).ok();
+                        close(null).ok();
+                    }
+                }
+
+                let first = &and_or.first;
+                if and_or.rest.is_empty() && !first.negated && first.commands.len() == 1 {
+                    self.exec_command(&first.commands[0]);
+                }
+                self.run_and_or(and_or);
+                exit(self.vars.status);
+            }
+            Err(why) => {
+                eprintln!("sh: {}", describe(why));
+                self.vars.status = 1;
+            }
+        }
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr) {
+        self.run_pipeline(&and_or.first);
+        for (connector, pipeline) in &and_or.rest {
+            // && は成功したとき, || は失敗したときだけ次を実行する
+            let run = match connector {
+                Connector::And => self.vars.status == 0,
+                Connector::Or => self.vars.status != 0,
+            };
+            if run {
+                self.run_pipeline(pipeline);
             }
+        }
+    }
+
+    // $? becomes the status of the last command of the pipeline.
+    fn run_pipeline(&mut self, pipeline: &Pipeline) {
+        let result = if pipeline.commands.len() == 1 {
+            self.run_command(&pipeline.commands[0])
         } else {
-            multistage_pipe(pipe_args, redirect_info).unwrap();
+            self.multistage_pipe(&pipeline.commands)
+        };
+        self.vars.status = result.unwrap_or_else(|why| {
+            eprintln!("sh: {}", describe(why));
+            1
+        });
+
+        if pipeline.negated {
+            self.vars.status = if self.vars.status == 0 { 1 } else { 0 };
         }
+    }
 
-        input_string.clear();
+    // Run a command on its own. Builtins and { } groups run in the shell
+    // itself, so that cd and exit affect it; everything else in a child.
+    fn run_command(&mut self, command: &Command) -> nix::Result<i32> {
+        match command {
+            Command::Simple { words, redirects } => {
+                self.substituted = None;
+                let (assignments, args) = match self.expand_simple(words) {
+                    Ok(expanded) => expanded,
+                    Err(msg) => {
+                        eprintln!("sh: {}", msg);
+                        return Ok(1);
+                    }
+                };
+                if args.is_empty() {
+                    self.assign(&assignments);
+                    let status = self.substituted.unwrap_or(0);
+                    return self.with_redirects(redirects, |_| status);
+                }
+                if is_builtin(&args[0]) {
+                    if builtins::is_special(&args[0]) {
+                        self.assign(&assignments);
+                        return self.with_redirects(redirects, |shell| shell.run_builtin(&args));
+                    }
+                    // any other builtin only sees them while it runs
+                    let saved: Vec<(String, Option<String>)> = assignments
+                        .iter()
+                        .map(|(name, _)| (name.clone(), self.vars.get(name)))
+                        .collect();
+                    self.assign(&assignments);
+                    let status = self.with_redirects(redirects, |shell| shell.run_builtin(&args));
+                    for (name, value) in saved.iter().rev() {
+                        self.vars.restore(name, value.as_deref());
+                    }
+                    return status;
+                }
+
+                let mut pgid = None;
+                match self.fork_process(&mut pgid, true)? {
+                    ForkResult::Parent { child, .. } => {
+                        let job =
+                            Job::new(pgid.unwrap_or(self.pgid), &[child], command.to_string());
+                        Ok(self.wait_foreground(job))
+                    }
+                    ForkResult::Child => self.exec_simple(&assignments, &args, redirects),
+                }
+            }
+            Command::Group { body, redirects } => self.with_redirects(redirects, |shell| {
+                shell.run_list(body);
+                shell.vars.status
+            }),
+            Command::Subshell { .. } => self.fork_and_wait(command),
+        }
     }
-}
 
-fn exec_chdir(args: &Vec<&str>) -> nix::Result<()> {
-    if args.len() == 2 {
-        chdir(args[1])?;
-    } else if args.len() > 2 {
-        eprintln!(
-            "{}: no such file or directory: {:?}",
-            args[0],
-            current_dir().unwrap()
-        );
+    fn fork_and_wait(&mut self, command: &Command) -> nix::Result<i32> {
+        let mut pgid = None;
+        match self.fork_process(&mut pgid, true)? {
+            ForkResult::Parent { child, .. } => {
+                let job = Job::new(pgid.unwrap_or(self.pgid), &[child], command.to_string());
+                Ok(self.wait_foreground(job))
+            }
+            ForkResult::Child => self.exec_command(command),
+        }
     }
 
-    Ok(())
-}
+    // Fork a process of a pipeline. Under job control, and always for a
+    // background job, the processes of a pipeline share a process group
+    // led by the first one, for which `pgid` is still None.
+    fn fork_process(
+        &mut self,
+        pgid: &mut Option<Pid>,
+        foreground: bool,
+    ) -> nix::Result<ForkResult> {
+        let new_group = self.job_control || !foreground;
+        let result = unsafe { fork() }?;
+
+        match result {
+            ForkResult::Parent { child, .. } => {
+                if new_group {
+                    let group = pgid.unwrap_or(child);
+                    // the child does the same: whichever runs first wins the race
+                    setpgid(child, group).ok();
+                    *pgid = Some(group);
+                }
+            }
+            ForkResult::Child => {
+                if new_group {
+                    let group = pgid.unwrap_or_else(getpid);
+                    setpgid(Pid::from_raw(0), group).ok();
+                    if self.job_control && foreground {
+                        tcsetpgrp(0, group).ok();
+                    }
+                }
+                if self.job_control {
+                    for &sig in &JOB_SIGNALS {
+                        unsafe { signal(sig, SigHandler::SigDfl) }.ok();
+                    }
+                }
+                // a subshell runs everything in the job's process group
+                self.job_control = false;
+                self.jobs = Jobs::new();
+            }
+        }
 
-fn parse_redirect_info<'a>(mut args: Vec<Vec<&'a str>>) -> (Vec<Vec<&'a str>>, RedirectInfo<'a>) {
-    let args_len = args.len();
-    let last_arg_len = args[args_len - 1].len();
-    if last_arg_len < 3 {
-        return (
-            args,
-            RedirectInfo {
-                category: RedirectCategory::None,
-            },
-        );
+        Ok(result)
     }
-    match args[args_len - 1][last_arg_len - 2] {
-        ">" => {
-            let path = args[args_len - 1].pop().unwrap();
-            args[args_len - 1].pop().unwrap();
-            (
-                args,
-                RedirectInfo {
-                    category: RedirectCategory::Overwrite(path),
-                },
-            )
+
+    // Wait for a foreground job to finish or stop, with the terminal handed
+    // to it under job control. A stopped job goes to the job table.
+    fn wait_foreground(&mut self, mut job: Job) -> i32 {
+        if self.job_control {
+            tcsetpgrp(0, job.pgid).ok();
+        }
+        job.wait(true);
+        if self.job_control {
+            tcsetpgrp(0, self.pgid).ok();
+            if let Some(ref tmodes) = self.tmodes {
+                tcsetattr(0, SetArg::TCSADRAIN, tmodes).ok();
+            }
         }
 
-        ">>" => {
-            let path = args[args_len - 1].pop().unwrap();
-            args[args_len - 1].pop().unwrap();
-            (
-                args,
-                RedirectInfo {
-                    category: RedirectCategory::Append(path),
-                },
-            )
+        let state = job.state();
+        if state == State::Stopped {
+            let id = self.jobs.add(job);
+            eprintln!("\n{}", self.jobs.format(id));
+        } else if self.job_control && state == State::Killed(Signal::SIGINT) {
+            // ^C の後のプロンプトを次の行に出す
+            eprintln!();
         }
-        _ => (
-            args,
-            RedirectInfo {
-                category: RedirectCategory::None,
-            },
-        ),
+        state.status()
+    }
+
+    // $(command): run `command` in a forked child and collect what it writes
+    // to its standard output through a pipe, without the trailing newlines.
+    fn substitute(&mut self, command: &str) -> Result<String, String> {
+        let syntax_error =
+            |token: String| format!("syntax error near unexpected token `{}'", token);
+        let tokens = lexer::tokenize(&format!("{}\n", command))
+            .map_err(|_| syntax_error("newline".to_string()))?;
+        let list = match parser::parse(tokens) {
+            Ok(list) => list,
+            Err(ParseError::Incomplete) => return Err(syntax_error("newline".to_string())),
+            Err(ParseError::Unexpected(token)) => return Err(syntax_error(token.to_string())),
+        };
+
+        let (read_end, write_end) = pipe().map_err(describe)?;
+        let mut pgid = None;
+        let child = match self.fork_process(&mut pgid, true) {
+            Ok(ForkResult::Parent { child, .. }) => child,
+            Ok(ForkResult::Child) => {
+                close(read_end).ok();
+                dup2(write_end, 1).unwrap_or_else(|_| exit(1));
+                close(write_end).ok();
+                self.run_list(&list);
+                exit(self.vars.status);
+            }
+            Err(why) => {
+                close(read_end).ok();
+                close(write_end).ok();
+                return Err(describe(why));
+            }
+        };
+        close(write_end).ok();
+
+        let mut output = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            match read(read_end, &mut buf) {
+                Ok(0) => break,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(_) => break,
+            }
+        }
+        close(read_end).ok();
+
+        let job = Job::new(
+            pgid.unwrap_or(self.pgid),
+            &[child],
+            format!("$({})", command),
+        );
+        let status = self.wait_foreground(job);
+        self.vars.status = status;
+        self.substituted = Some(status);
+
+        // a C string cannot hold them, so no argument could either
+        if output.contains(&0) {
+            eprintln!("sh: warning: command substitution: ignored null byte in input");
+            output.retain(|&b| b != 0);
+        }
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        Ok(output)
+    }
+
+    // Run `command` in a forked child and end the child with its status.
+    fn exec_command(&mut self, command: &Command) -> ! {
+        let (body, redirects) = match command {
+            Command::Simple { words, redirects } => {
+                let (assignments, args) = self.expand_simple(words).unwrap_or_else(|msg| {
+                    eprintln!("sh: {}", msg);
+                    exit(1);
+                });
+                if args.is_empty() || is_builtin(&args[0]) {
+                    self.assign(&assignments);
+                    self.redirect_or_exit(redirects);
+                    exit(self.run_builtin(&args));
+                }
+                self.exec_simple(&assignments, &args, redirects)
+            }
+            Command::Subshell { body, redirects } | Command::Group { body, redirects } => {
+                (body, redirects)
+            }
+        };
+
+        self.redirect_or_exit(redirects);
+        self.run_list(body);
+        exit(self.vars.status);
     }
-}
 
-fn redicrect(redirect_info: RedirectInfo) -> nix::Result<()> {
-    match redirect_info.category {
-        RedirectCategory::Overwrite(path) => {
-            let mode = Mode::S_IRWXU;
-            let flag = OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC;
-            let file = open(path, flag, mode)?;
-            dup2(file, 1)?;
-            close(file)?;
+    // Replace the forked child with the command named by `args`.
+    fn exec_simple(
+        &mut self,
+        assignments: &[(String, String)],
+        args: &[String],
+        redirects: &[Redirect],
+    ) -> ! {
+        self.redirect_or_exit(redirects);
+
+        // execvpe looks the command up in the PATH of this process, not of `env`
+        let path = assignments
+            .iter()
+            .rev()
+            .find(|(name, _)| name == "PATH")
+            .map(|(_, value)| value.clone())
+            .or_else(|| self.vars.get("PATH"));
+        match path {
+            Some(path) => env::set_var("PATH", path),
+            None => env::remove_var("PATH"),
         }
-        RedirectCategory::Append(path) => {
-            let mode = Mode::S_IRWXU;
-            let flag = OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_APPEND;
-            let file = open(path, flag, mode)?;
-            dup2(file, 1)?;
-            close(file)?
+
+        let command_args = args
+            .iter()
+            .map(|arg| CString::new(arg.as_str()).map_err(|_| arg))
+            .collect::<Result<Vec<CString>, _>>()
+            .unwrap_or_else(|arg| {
+                eprintln!("sh: {}: invalid argument", arg);
+                exit(126);
+            });
+        let env = self.vars.environment(assignments);
+        match execvpe(&command_args[0], &command_args, &env).unwrap_err() {
+            nix::Error::Sys(Errno::ENOENT) => {
+                eprintln!("{}: command not found", args[0]);
+                exit(127);
+            }
+            why => {
+                eprintln!("{}: {}", args[0], describe(why));
+                exit(126);
+            }
         }
-        RedirectCategory::None => {}
-    };
+    }
 
-    Ok(())
-}
+    fn multistage_pipe(&mut self, commands: &[Command]) -> nix::Result<i32> {
+        let mut pipefd: Vec<(i32, i32)> = Vec::with_capacity(commands.len());
+        let mut children: Vec<Pid> = Vec::with_capacity(commands.len());
+        let mut pgid = None;
+
+        // [0, commands.len()-1]
+        for i in 0..commands.len() {
+            if i != commands.len() - 1 {
+                pipefd.push(pipe()?); //最後のコマンドでなければパイプを作成
+            }
 
-fn singlestage_pipe(pipe_args: Vec<Vec<&str>>, redirect_info: RedirectInfo) -> nix::Result<()> {
-    match unsafe { fork() }? {
-        ForkResult::Parent { child, .. } => {
-            match waitpid(child, None)? {
-                WaitStatus::Exited(pid, status) => {
-                    println!("child (PID={}) finished: exit, status={}", pid, status);
+            match self.fork_process(&mut pgid, true)? {
+                ForkResult::Parent { child, .. } => {
+                    children.push(child);
+
+                    // 親側から実行済みのパイプを消す
+                    if i > 0 {
+                        close(pipefd[i - 1].0)?;
+                        close(pipefd[i - 1].1)?;
+                    }
                 }
-                WaitStatus::Signaled(pid, status, _) => {
-                    println!("child (PID={}) finished: signal, sig={}", pid, status);
+                ForkResult::Child => {
+                    if i > 0 {
+                        // 0から取り出す(読み込み)
+                        dup2(pipefd[i - 1].0, 0).unwrap_or_else(|_| exit(1));
+                        close(pipefd[i - 1].0).unwrap_or_else(|_| exit(1));
+                        close(pipefd[i - 1].1).unwrap_or_else(|_| exit(1));
+                    }
+                    if i != commands.len() - 1 {
+                        // 1に入れる(書き込み)
+                        dup2(pipefd[i].1, 1).unwrap_or_else(|_| exit(1));
+                        close(pipefd[i].0).unwrap_or_else(|_| exit(1));
+                        close(pipefd[i].1).unwrap_or_else(|_| exit(1));
+                    }
+
+                    self.exec_command(&commands[i]);
                 }
-                _ => println!("abnoraml exit"),
             };
         }
-        ForkResult::Child => {
-            let first_args = pipe_args[0].clone();
-            let command = CString::new(first_args[0].to_string()).unwrap();
-            let command_args: Vec<CString> = first_args
-                .iter()
-                .map(|arg| CString::new(*arg).unwrap())
-                .collect();
-
-            redicrect(redirect_info)?;
-
-            match execvp(&command, &command_args) {
-                Ok(_) => exit(0),
-                Err(_) => {
-                    eprintln!("{}: command not found", first_args[0]);
-                    exit(1);
+
+        let text: Vec<String> = commands.iter().map(|command| command.to_string()).collect();
+        let job = Job::new(pgid.unwrap_or(self.pgid), &children, text.join(" | "));
+        Ok(self.wait_foreground(job))
+    }
+
+    // Run `run` with `redirects` applied, then put the shell's own file
+    // descriptors back.
+    fn with_redirects(
+        &mut self,
+        redirects: &[Redirect],
+        run: impl FnOnce(&mut Shell) -> i32,
+    ) -> nix::Result<i32> {
+        let mut saved = Vec::new();
+        let status = match self.redirect(redirects, Some(&mut saved)) {
+            Ok(()) => run(self),
+            Err(msg) => {
+                eprintln!("sh: {}", msg);
+                1
+            }
+        };
+
+        for (fd, copy) in saved.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    dup2(copy, fd)?;
+                    close(copy)?;
                 }
-            };
+                None => {
+                    close(fd).ok();
+                }
+            }
         }
-    };
-
-    Ok(())
-}
 
-fn multistage_pipe(pipe_args: Vec<Vec<&str>>, redirect_info: RedirectInfo) -> nix::Result<()> {
-    let mut pipefd: Vec<(i32, i32)> = Vec::with_capacity(pipe_args.len());
-    let mut children: Vec<Pid> = Vec::with_capacity(pipe_args.len());
+        Ok(status)
+    }
 
-    // [0, pipe_args.len()-1]
-    for i in 0..pipe_args.len() {
-        if i != pipe_args.len() - 1 {
-            pipefd.push(pipe()?); //最後のコマンドでなければパイプを作成
+    fn redirect_or_exit(&mut self, redirects: &[Redirect]) {
+        if let Err(msg) = self.redirect(redirects, None) {
+            eprintln!("sh: {}", msg);
+            exit(1);
         }
+    }
 
-        match unsafe { fork()? } {
-            ForkResult::Parent { child, .. } => {
-                children.push(child);
+    // Point the descriptors named by `redirects` at their files. With
+    // `saved`, a copy of each descriptor replaced is kept there, or None if
+    // it was not open.
+    fn redirect(
+        &mut self,
+        redirects: &[Redirect],
+        mut saved: Option<&mut Vec<(RawFd, Option<RawFd>)>>,
+    ) -> Result<(), String> {
+        for redirect in redirects {
+            let source = self.redirect_source(redirect)?;
+            // &> は標準出力と標準エラー出力の両方
+            let fds = if redirect.op == Op::AndGreat {
+                vec![1, 2]
+            } else {
+                vec![redirect.fd]
+            };
 
-                // 親側から実行済みのパイプを消す
-                if i > 0 {
-                    close(pipefd[i - 1].0)?;
-                    close(pipefd[i - 1].1)?;
+            if let Some(saved) = saved.as_mut() {
+                for &fd in &fds {
+                    // 10 以上に退避して, コマンドには引き継がせない
+                    let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(10)).ok();
+                    saved.push((fd, copy));
                 }
             }
-            ForkResult::Child => {
-                let first_args = pipe_args[i].clone();
-                let command = CString::new(first_args[0].to_string()).unwrap();
-                let command_args: Vec<CString> = first_args
-                    .iter()
-                    .map(|arg| CString::new(*arg).unwrap())
-                    .collect();
-
-                if i == 0 {
-                    dup2(pipefd[i].1, 1).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i].0).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i].1).unwrap_or_else(|_| exit(1));
-                } else if i == pipe_args.len() - 1 {
-                    dup2(pipefd[i - 1].0, 0).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i - 1].0).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i - 1].1).unwrap_or_else(|_| exit(1));
-
-                    redicrect(redirect_info)?;
-                } else {
-                    // 0から取り出す(読み込み)
-                    dup2(pipefd[i - 1].0, 0).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i - 1].0).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i - 1].1).unwrap_or_else(|_| exit(1));
-
-                    // 1に入れる(書き込み)
-                    dup2(pipefd[i].1, 1).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i].0).unwrap_or_else(|_| exit(1));
-                    close(pipefd[i].1).unwrap_or_else(|_| exit(1));
-                }
 
-                match execvp(&command, &command_args) {
-                    Ok(_) => exit(0),
-                    Err(_) => {
-                        eprintln!("{}: command not found", first_args[0]);
-                        exit(1);
+            match source {
+                Source::File(file) => {
+                    for &fd in &fds {
+                        if file != fd {
+                            dup2(file, fd).map_err(describe)?;
+                        }
+                    }
+                    if !fds.contains(&file) {
+                        close(file).map_err(describe)?;
+                    }
+                }
+                Source::Fd(from) => {
+                    if from != redirect.fd {
+                        dup2(from, redirect.fd)
+                            .map_err(|why| format!("{}: {}", from, describe(why)))?;
                     }
+                }
+                Source::Close => {
+                    close(redirect.fd).ok();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn redirect_source(&mut self, redirect: &Redirect) -> Result<Source, String> {
+        let word = match &redirect.target {
+            Target::HereDoc(here_doc) => {
+                let body = if here_doc.expand {
+                    expand::expand_here_doc(&here_doc.body, self)?
+                } else {
+                    here_doc.body.clone()
                 };
+                return here_file(&body).map(Source::File).map_err(describe);
+            }
+            Target::Word(word) => {
+                let mut fields = expand::expand_word(word, self)?;
+                if fields.len() != 1 {
+                    return Err(format!("{}: ambiguous redirect", word));
+                }
+                fields.remove(0)
             }
         };
-    }
 
-    for child in children {
-        match waitpid(child, None)? {
-            WaitStatus::Exited(pid, status) => {
-                println!("child (PID={}) finished: exit, status={}", pid, status);
+        let flag = match redirect.op {
+            Op::Less => OFlag::O_RDONLY,
+            Op::Great | Op::AndGreat => OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC,
+            Op::DGreat => OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_APPEND,
+            Op::LessGreat => OFlag::O_CREAT | OFlag::O_RDWR,
+            Op::TLess => {
+                return here_file(&(word + "\n"))
+                    .map(Source::File)
+                    .map_err(describe)
             }
-            WaitStatus::Signaled(pid, status, _) => {
-                println!("child (PID={}) finished: signal, sig={}", pid, status);
+            // n>&m, n<&m, n>&-
+            _ if word == "-" => return Ok(Source::Close),
+            _ => {
+                return word
+                    .parse()
+                    .map(Source::Fd)
+                    .map_err(|_| format!("{}: ambiguous redirect", word))
             }
-            _ => println!("abnoraml exit"),
         };
+
+        // umask が掛かるので, 普通は 0644 になる
+        let mode = Mode::from_bits_truncate(0o666);
+        open(word.as_str(), flag, mode)
+            .map(Source::File)
+            .map_err(|why| format!("{}: {}", word, describe(why)))
+    }
+
+    // Leading NAME=value words are assignments, the rest expand into the
+    // arguments of the command.
+    fn expand_simple(&mut self, words: &[String]) -> Result<(Assignments, Vec<String>), String> {
+        let mut assignments = Vec::new();
+        let mut n = 0;
+        while let Some((name, value)) = words.get(n).and_then(|word| split_assignment(word)) {
+            let value = expand::expand_assignment(value, self)?;
+            assignments.push((name.to_string(), value));
+            n += 1;
+        }
+
+        let mut args = Vec::new();
+        for word in &words[n..] {
+            args.extend(expand::expand_word(word, self)?);
+        }
+
+        Ok((assignments, args))
     }
 
-    Ok(())
+    // Without a command, or in front of a builtin, NAME=value sets a shell
+    // variable; in front of a builtin that is not special, only until it
+    // returns.
+    fn assign(&mut self, assignments: &[(String, String)]) {
+        for (name, value) in assignments {
+            self.vars.set(name, value);
+        }
+    }
 }
 
-fn slice_vec_with_str<'a, 'b>(args: Vec<&'a str>, slice_key: &'b str) -> Vec<Vec<&'a str>> {
-    let positions: Vec<usize> = args
-        .iter()
-        .enumerate()
-        .filter(|&(_, s)| *s == slice_key)
-        .map(|(i, _)| i)
-        .collect();
+// NAME=value, with NAME unquoted
+fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_at(word.find('=')?);
+    if vars::is_name(name) {
+        Some((name, &value[1..]))
+    } else {
+        None
+    }
+}
 
-    slice_with_positions(args, positions)
+// An unnamed temporary file holding `content`, ready to be read from the
+// start: the body of a here-document or a here-string.
+fn here_file(content: &str) -> nix::Result<RawFd> {
+    let mode = Mode::S_IRUSR | Mode::S_IWUSR;
+    let fd = open(&env::temp_dir(), OFlag::O_TMPFILE | OFlag::O_RDWR, mode)?;
+    let mut data = content.as_bytes();
+    while !data.is_empty() {
+        let n = write(fd, data)?;
+        data = &data[n..];
+    }
+    lseek(fd, 0, Whence::SeekSet)?;
+
+    Ok(fd)
+}
+
+fn describe(why: nix::Error) -> String {
+    match why.as_errno() {
+        Some(errno) => errno.desc().to_string(),
+        None => why.to_string(),
+    }
 }
 
-fn slice_with_positions<T: Clone>(args: Vec<T>, mut positions: Vec<usize>) -> Vec<Vec<T>> {
-    positions.push(args.len());
-    let mut poslr: Vec<(usize, usize)> = Vec::with_capacity(positions.len());
-    poslr.push((0, positions[0]));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Shell {
+        // a shell that leaves the terminal alone, with `vars` set and the
+        // default IFS
+        pub fn for_test(vars: &[(&str, &str)]) -> Shell {
+            let mut shell = Shell {
+                vars: Variables::from_env(),
+                jobs: Jobs::new(),
+                job_control: false,
+                pgid: getpgrp(),
+                tmodes: None,
+                substituted: None,
+            };
+            shell.vars.unset("IFS");
+            for (name, value) in vars {
+                shell.vars.set(name, value);
+            }
+            shell
+        }
+    }
+
+    fn run(shell: &mut Shell, input: &str) {
+        let list = parser::parse(lexer::tokenize(input).unwrap()).unwrap();
+        shell.run_list(&list);
+    }
 
-    for i in 1..positions.len() {
-        poslr.push((positions[i - 1] + 1, positions[i]));
+    #[test]
+    fn prefix_assignments() {
+        let mut sh = Shell::for_test(&[("FOO", "old")]);
+        sh.vars.unset("BAR");
+
+        // only for the duration of a regular builtin
+        run(&mut sh, "FOO=new BAR=1 jobs\n");
+        assert_eq!(sh.vars.get("FOO").as_deref(), Some("old"));
+        assert_eq!(sh.vars.get("BAR"), None);
+
+        sh.vars.export("FOO");
+        run(&mut sh, "FOO=new jobs\n");
+        assert!(sh.vars.exported().contains(&("FOO", "old")));
+
+        // for good in front of a special builtin or without a command
+        run(&mut sh, "BAR=1 export QUX=2\n");
+        assert_eq!(sh.vars.get("BAR").as_deref(), Some("1"));
+        assert_eq!(sh.vars.get("QUX").as_deref(), Some("2"));
+        run(&mut sh, "FOO=new\n");
+        assert_eq!(sh.vars.get("FOO").as_deref(), Some("new"));
     }
 
-    poslr
-        .iter()
-        .map(|&(l, r)| {
-            let mut tmp = Vec::with_capacity(r - l);
-            tmp.extend_from_slice(&args[l..r]);
-            tmp
-        })
-        .collect::<Vec<Vec<T>>>()
+    #[test]
+    fn assignment_tildes() {
+        let mut sh = Shell::for_test(&[("HOME", "/home/me")]);
+        run(&mut sh, "P=~/bin:~:x~ Q=~ R='~' S=a:~root/x\n");
+        assert_eq!(
+            sh.vars.get("P").as_deref(),
+            Some("/home/me/bin:/home/me:x~")
+        );
+        assert_eq!(sh.vars.get("Q").as_deref(), Some("/home/me"));
+        assert_eq!(sh.vars.get("R").as_deref(), Some("~"));
+        assert_eq!(sh.vars.get("S").as_deref(), Some("a:/root/x"));
+    }
+
+    #[test]
+    fn ifs_splitting() {
+        let mut sh = Shell::for_test(&[("IFS", ":"), ("v", "a::b:")]);
+        // an assignment is not split; the fields of $v are
+        run(&mut sh, "w=$v x=${v}c\n");
+        assert_eq!(sh.vars.get("w").as_deref(), Some("a::b:"));
+        assert_eq!(sh.vars.get("x").as_deref(), Some("a::b:c"));
+        assert_eq!(expand::expand_word("$v", &mut sh).unwrap(), ["a", "", "b"]);
+        assert_eq!(
+            expand::expand_word("x${v}c", &mut sh).unwrap(),
+            ["xa", "", "b", "c"]
+        );
+    }
 }
@@ -1,36 +1,745 @@
-use nix::sys::wait::{waitpid, WaitStatus};
-use nix::unistd::{execv, fork, ForkResult};
+use getopts::{Matches, Options, ParsingStyle};
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::wait::{WaitPidFlag, WaitStatus};
+use nix::unistd::{chdir, execvp, fork, setpgid, ForkResult, Pid};
+use std::convert::TryFrom;
 use std::env;
 use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::path::PathBuf;
 use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod sandbox;
+
+use sandbox::SandboxOptions;
+
+// how often the child is checked on while a timeout runs
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// exit status when the command timed out, as in timeout(1)
+const EXIT_TIMEDOUT: i32 = 124;
+
+// a signal received while supervising, to be passed on to the child's group
+static FORWARD: AtomicI32 = AtomicI32::new(0);
+
+// the type setrlimit takes its resource as, which differs between C libraries
+#[cfg(any(target_env = "gnu", target_env = "uclibc"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(any(target_env = "gnu", target_env = "uclibc")))]
+type Resource = libc::c_int;
+
+struct Limit {
+    resource: Resource,
+    soft: libc::rlim_t,
+    hard: libc::rlim_t,
+}
+
+struct Timeout {
+    duration: Duration,
+    signal: Signal,
+    kill_after: Option<Duration>,
+}
+
+struct SpawnOptions {
+    quiet: bool,
+    verbose: bool,
+    ignore_env: bool,
+    unset: Vec<String>,
+    assignments: Vec<(String, String)>,
+    chdir: Option<String>,
+    limits: Vec<Limit>,
+    timeout: Option<Timeout>,
+    sandbox: Option<SandboxOptions>,
+}
+
+// (option name, resource, whether the value is a size in bytes)
+const LIMITS: [(&str, Resource, bool); 4] = [
+    ("limit-cpu", libc::RLIMIT_CPU, false),
+    ("limit-as", libc::RLIMIT_AS, true),
+    ("limit-nofile", libc::RLIMIT_NOFILE, false),
+    ("limit-core", libc::RLIMIT_CORE, true),
+];
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 3 {
-        eprintln!("Usage: {} <command> <arg>", args[0]);
-        process::exit(1);
+    let mut opts = Options::new();
+    // options after the command belong to the command
+    opts.parsing_style(ParsingStyle::StopAtFirstFree);
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("q", "quiet", "do not report how the child finished");
+    opts.optflag(
+        "v",
+        "verbose",
+        "print resource usage of the child when it finishes",
+    );
+    opts.optflag("i", "ignore-environment", "start with an empty environment");
+    opts.optmulti("u", "unset", "remove NAME from the environment", "NAME");
+    opts.optopt("C", "chdir", "run the command in DIR", "DIR");
+    opts.optopt("", "limit-cpu", "limit CPU time to SECS seconds", "SECS");
+    opts.optopt(
+        "",
+        "limit-as",
+        "limit the address space to SIZE bytes (K, M, G suffixes)",
+        "SIZE",
+    );
+    opts.optopt("", "limit-nofile", "limit open files to N", "N");
+    opts.optopt(
+        "",
+        "limit-core",
+        "limit core dumps to SIZE bytes (K, M, G suffixes)",
+        "SIZE",
+    );
+    opts.optopt(
+        "",
+        "timeout",
+        "signal the command if it still runs after DURATION (s, m, h, d suffixes)",
+        "DURATION",
+    );
+    opts.optopt(
+        "s",
+        "signal",
+        "with --timeout, the signal to send (default TERM)",
+        "SIG",
+    );
+    opts.optopt(
+        "k",
+        "kill-after",
+        "with --timeout, also send KILL if the command still runs DURATION after the signal",
+        "DURATION",
+    );
+    opts.optflag(
+        "",
+        "sandbox",
+        "run the command in new user, mount, PID, network and IPC namespaces behind a seccomp filter",
+    );
+    opts.optopt(
+        "",
+        "sandbox-root",
+        "with --sandbox, bind-mount DIR read-only as the root directory",
+        "DIR",
+    );
+    opts.optopt(
+        "",
+        "seccomp-deny",
+        "with --sandbox, comma separated system calls that fail with EPERM, or 'none'",
+        "LIST",
+    );
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    // env(1) と同じく, コマンドの前の NAME=VALUE は環境変数の設定
+    let mut free = matches.free.clone();
+    let mut assignments = Vec::new();
+    while let Some((name, value)) = free.first().and_then(|arg| assignment(arg)) {
+        assignments.push((name, value));
+        free.remove(0);
     }
 
-    match unsafe { fork() }.unwrap_or_else(|why| panic!("fork failed: {}", why.to_string())) {
-        ForkResult::Parent { child, .. } => {
-            match waitpid(child, None)
-                .unwrap_or_else(|why| panic!("waitpid failed: {}", why.to_string()))
-            {
-                WaitStatus::Exited(pid, status) => {
-                    println!("child (PID={}) finished: exit, status={}", pid, status);
-                }
-                WaitStatus::Signaled(pid, status, _) => {
-                    println!("child (PID={}) finished: signal, sig={}", pid, status);
+    if matches.opt_present("h") || free.is_empty() {
+        eprintln!(
+            "Usage: {:?} [-qvi] [-u NAME] [-C DIR] [--limit-RESOURCE=VALUE|SOFT:HARD] [--timeout=DURATION [-s SIG] [-k DURATION]] [--sandbox [--sandbox-root=DIR] [--seccomp-deny=LIST]] [NAME=VALUE]... COMMAND [ARG...]",
+            &args[0]
+        );
+        process::exit(if matches.opt_present("h") { 0 } else { 1 });
+    }
+
+    let argv = match free
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(argv) => argv,
+        Err(_) => {
+            eprintln!("{:?}: argument contains a NUL byte", &args[0]);
+            process::exit(1);
+        }
+    };
+
+    let limits = match parse_limits(&matches) {
+        Ok(limits) => limits,
+        Err(msg) => {
+            eprintln!("{:?}: {}", &args[0], msg);
+            process::exit(1);
+        }
+    };
+
+    let timeout = match parse_timeout(&matches) {
+        Ok(timeout) => timeout,
+        Err(msg) => {
+            eprintln!("{:?}: {}", &args[0], msg);
+            process::exit(1);
+        }
+    };
+
+    let sandbox = match parse_sandbox(&matches) {
+        Ok(sandbox) => sandbox,
+        Err(msg) => {
+            eprintln!("{:?}: {}", &args[0], msg);
+            process::exit(1);
+        }
+    };
+
+    let spawn_opts = SpawnOptions {
+        quiet: matches.opt_present("q"),
+        verbose: matches.opt_present("v"),
+        ignore_env: matches.opt_present("i"),
+        unset: matches.opt_strs("u"),
+        assignments,
+        chdir: matches.opt_str("C"),
+        limits,
+        timeout,
+        sandbox,
+    };
+
+    let started = Instant::now();
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child, .. }) => {
+            if spawn_opts.timeout.is_some() {
+                // also done in the child: whichever runs first, the group
+                // exists before anything is sent to it
+                setpgid(child, child).ok();
+                forward_signals();
+            }
+            let status = wait_child(child, &spawn_opts, &free, started);
+            process::exit(status);
+        }
+        Ok(ForkResult::Child) => run_child(&argv, &spawn_opts),
+        Err(why) => {
+            eprintln!("{:?}: fork failed: {}", &args[0], why);
+            process::exit(1);
+        }
+    }
+}
+
+fn assignment(arg: &str) -> Option<(String, String)> {
+    let eq = arg.find('=')?;
+    if eq == 0 {
+        return None;
+    }
+
+    Some((arg[..eq].to_string(), arg[eq + 1..].to_string()))
+}
+
+fn parse_limits(matches: &Matches) -> Result<Vec<Limit>, String> {
+    let mut limits = Vec::new();
+    for &(name, resource, is_size) in &LIMITS {
+        if let Some(spec) = matches.opt_str(name) {
+            match parse_limit(&spec, is_size) {
+                Some((soft, hard)) => limits.push(Limit {
+                    resource,
+                    soft,
+                    hard,
+                }),
+                None => return Err(format!("invalid value for --{}: {:?}", name, spec)),
+            }
+        }
+    }
+
+    Ok(limits)
+}
+
+// None without --timeout or when DURATION is 0
+fn parse_timeout(matches: &Matches) -> Result<Option<Timeout>, String> {
+    let duration = match matches.opt_str("timeout") {
+        Some(spec) => parse_duration(&spec).ok_or(format!("invalid time interval {:?}", spec))?,
+        None => return Ok(None),
+    };
+    if duration == Duration::from_secs(0) {
+        return Ok(None);
+    }
+
+    let signal = match matches.opt_str("s") {
+        Some(spec) => parse_signal(&spec).ok_or(format!("invalid signal {:?}", spec))?,
+        None => Signal::SIGTERM,
+    };
+    let kill_after = match matches.opt_str("k") {
+        Some(spec) => {
+            Some(parse_duration(&spec).ok_or(format!("invalid time interval {:?}", spec))?)
+        }
+        None => None,
+    };
+
+    Ok(Some(Timeout {
+        duration,
+        signal,
+        kill_after,
+    }))
+}
+
+// None without --sandbox
+fn parse_sandbox(matches: &Matches) -> Result<Option<SandboxOptions>, String> {
+    if !matches.opt_present("sandbox") {
+        if matches.opt_present("sandbox-root") || matches.opt_present("seccomp-deny") {
+            return Err("--sandbox-root and --seccomp-deny need --sandbox".to_string());
+        }
+        return Ok(None);
+    }
+
+    if !sandbox::SUPPORTED {
+        return Err("--sandbox: unsupported architecture".to_string());
+    }
+    let deny = match matches.opt_str("seccomp-deny") {
+        Some(list) => sandbox::parse_syscalls(&list)?,
+        None => sandbox::parse_syscalls(&sandbox::DEFAULT_DENY.join(","))?,
+    };
+
+    Ok(Some(SandboxOptions {
+        root: matches.opt_str("sandbox-root").map(PathBuf::from),
+        deny,
+    }))
+}
+
+// a floating point number of seconds, or of minutes, hours or days with
+// an m, h or d suffix
+fn parse_duration(spec: &str) -> Option<Duration> {
+    let (number, unit) = match spec.char_indices().last()? {
+        (i, 's') => (&spec[..i], 1.0),
+        (i, 'm') => (&spec[..i], 60.0),
+        (i, 'h') => (&spec[..i], 3600.0),
+        (i, 'd') => (&spec[..i], 86400.0),
+        _ => (spec, 1.0),
+    };
+
+    match number.parse::<f64>() {
+        Ok(n) if n >= 0.0 && (n * unit).is_finite() => Some(Duration::from_secs_f64(n * unit)),
+        _ => None,
+    }
+}
+
+// "TERM", "SIGTERM" or "15"
+fn parse_signal(spec: &str) -> Option<Signal> {
+    if let Ok(number) = spec.parse::<i32>() {
+        return Signal::try_from(number).ok();
+    }
+
+    let name = spec.to_ascii_uppercase();
+    if name.starts_with("SIG") {
+        Signal::from_str(&name).ok()
+    } else {
+        Signal::from_str(&format!("SIG{}", name)).ok()
+    }
+}
+
+// VALUE for both limits, or SOFT:HARD; the soft limit may not exceed the
+// hard one
+fn parse_limit(spec: &str, is_size: bool) -> Option<(libc::rlim_t, libc::rlim_t)> {
+    let (soft, hard) = match spec.find(':') {
+        Some(colon) => (
+            parse_limit_value(&spec[..colon], is_size)?,
+            parse_limit_value(&spec[colon + 1..], is_size)?,
+        ),
+        None => {
+            let value = parse_limit_value(spec, is_size)?;
+            (value, value)
+        }
+    };
+
+    // RLIM_INFINITY is the largest value
+    if soft > hard {
+        return None;
+    }
+    Some((soft, hard))
+}
+
+// "unlimited", or a number; sizes may end in K, M or G
+fn parse_limit_value(spec: &str, is_size: bool) -> Option<libc::rlim_t> {
+    if spec == "unlimited" {
+        return Some(libc::RLIM_INFINITY);
+    }
+
+    let (digits, unit) = match spec.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) if is_size => (&spec[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) if is_size => (&spec[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) if is_size => (&spec[..i], 1 << 30),
+        _ => (spec, 1),
+    };
+
+    digits.parse::<libc::rlim_t>().ok()?.checked_mul(unit)
+}
+
+// Set up the forked child and replace it with the command, searching PATH
+// like a shell. The environment is changed first, so that PATH=... given
+// on the command line is used for the search.
+fn run_child(argv: &[CString], opts: &SpawnOptions) -> ! {
+    // a group of its own, so that the timeout reaches everything it starts
+    if opts.timeout.is_some() {
+        setpgid(Pid::from_raw(0), Pid::from_raw(0)).ok();
+    }
+
+    if opts.ignore_env {
+        for (name, _) in env::vars_os() {
+            env::remove_var(name);
+        }
+    }
+    for name in &opts.unset {
+        env::remove_var(name);
+    }
+    for (name, value) in &opts.assignments {
+        env::set_var(name, value);
+    }
+
+    if let Some(sandbox) = &opts.sandbox {
+        if let Err(why) = sandbox::enter(sandbox) {
+            eprintln!("sandbox: {:?}", why.to_string());
+            process::exit(125);
+        }
+    }
+
+    if let Some(dir) = &opts.chdir {
+        if let Err(why) = chdir(dir.as_str()) {
+            eprintln!("{:?}: {:?}", dir, nix_to_io(why).to_string());
+            process::exit(125);
+        }
+    }
+
+    for limit in &opts.limits {
+        let rlim = libc::rlimit {
+            rlim_cur: limit.soft,
+            rlim_max: limit.hard,
+        };
+        if unsafe { libc::setrlimit(limit.resource, &rlim) } != 0 {
+            eprintln!("setrlimit: {}", io::Error::last_os_error());
+            process::exit(125);
+        }
+    }
+
+    if let Some(sandbox) = &opts.sandbox {
+        if let Err(why) = sandbox::lock_down(sandbox) {
+            eprintln!("seccomp: {:?}", why.to_string());
+            process::exit(125);
+        }
+    }
+
+    let why = match execvp(&argv[0], argv) {
+        Ok(never) => match never {},
+        Err(why) => nix_to_io(why),
+    };
+    eprintln!("{:?}: {:?}", argv[0], why.to_string());
+    // same exit codes as a shell: 127 when not found, 126 when not runnable
+    process::exit(if why.kind() == io::ErrorKind::NotFound {
+        127
+    } else {
+        126
+    });
+}
+
+fn nix_to_io(why: nix::Error) -> io::Error {
+    match why.as_errno() {
+        Some(errno) => io::Error::from(errno),
+        None => io::Error::other(why.to_string()),
+    }
+}
+
+// Wait until the child terminates, reporting stops and continues on the
+// way. Returns the exit status to pass on: the child's own, or 128 plus
+// the number of the signal that killed it, or 124 when the timeout sent
+// anything but KILL.
+fn wait_child(child: Pid, opts: &SpawnOptions, command: &[String], started: Instant) -> i32 {
+    let mut flags = WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+    if opts.timeout.is_some() {
+        flags |= WaitPidFlag::WNOHANG;
+    }
+    let mut deadline = opts.timeout.as_ref().map(|t| started + t.duration);
+    let mut timed_out = false;
+    let mut killed = false;
+
+    loop {
+        let (status, usage) = match wait4(child, flags) {
+            Ok(result) => result,
+            Err(ref why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => {
+                eprintln!("wait4 failed: {}", why);
+                return 1;
+            }
+        };
+
+        if status == WaitStatus::StillAlive {
+            if let Ok(sig) = Signal::try_from(FORWARD.swap(0, Ordering::SeqCst)) {
+                signal_group(child, sig);
+            }
+
+            let now = Instant::now();
+            match (deadline, opts.timeout.as_ref()) {
+                (Some(at), Some(timeout)) if now >= at => {
+                    let sig = if timed_out {
+                        Signal::SIGKILL
+                    } else {
+                        timeout.signal
+                    };
+                    signal_group(child, sig);
+                    killed = sig == Signal::SIGKILL;
+                    deadline = match timeout.kill_after {
+                        Some(after) if !timed_out && !killed => Some(now + after),
+                        _ => None,
+                    };
+                    timed_out = true;
                 }
-                _ => println!("abnoraml exit"),
-            };
+                (Some(at), _) => thread::sleep(POLL_INTERVAL.min(at - now)),
+                _ => thread::sleep(POLL_INTERVAL),
+            }
+            continue;
+        }
+
+        if !opts.quiet {
+            report(&status);
+        }
+        let code = match status {
+            WaitStatus::Exited(_, code) => code,
+            WaitStatus::Signaled(_, sig, _) => 128 + sig as i32,
+            _ => continue,
+        };
+
+        if opts.verbose {
+            report_usage(command, &usage, started.elapsed(), &status);
         }
-        ForkResult::Child => {
-            let path = CString::new(args[1].to_string()).unwrap();
-            let command_arg = CString::new(args[2].to_string()).unwrap();
-            execv(&path, &[path.clone(), command_arg])
-                .unwrap_or_else(|why| panic!("execv failed: {}", why.to_string()));
+        return if timed_out && !killed {
+            EXIT_TIMEDOUT
+        } else {
+            code
+        };
+    }
+}
+
+fn signal_group(child: Pid, sig: Signal) {
+    let group = Pid::from_raw(-child.as_raw());
+    kill(group, sig).ok();
+    // a stopped process would not act on the signal until continued
+    if sig != Signal::SIGKILL && sig != Signal::SIGCONT {
+        kill(group, Signal::SIGCONT).ok();
+    }
+}
+
+// The child is not in the terminal's foreground group any more, so pass
+// on the signals that would otherwise only stop spawn itself.
+fn forward_signals() {
+    extern "C" fn remember(sig: libc::c_int) {
+        FORWARD.store(sig, Ordering::SeqCst);
+    }
+
+    let action = SigAction::new(
+        SigHandler::Handler(remember),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    for &sig in &[
+        Signal::SIGINT,
+        Signal::SIGTERM,
+        Signal::SIGHUP,
+        Signal::SIGQUIT,
+    ] {
+        unsafe { sigaction(sig, &action) }.ok();
+    }
+}
+
+// waitpid(2) that also returns the resources used by the child
+fn wait4(child: Pid, flags: WaitPidFlag) -> io::Result<(WaitStatus, libc::rusage)> {
+    let mut status = 0;
+    let mut usage = MaybeUninit::<libc::rusage>::zeroed();
+    let pid = unsafe {
+        libc::wait4(
+            child.as_raw(),
+            &mut status,
+            flags.bits(),
+            usage.as_mut_ptr(),
+        )
+    };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // WNOHANG and nothing to report yet
+    if pid == 0 {
+        return Ok((WaitStatus::StillAlive, unsafe { usage.assume_init() }));
+    }
+
+    let status = WaitStatus::from_raw(Pid::from_raw(pid), status)
+        .map_err(|why| io::Error::other(why.to_string()))?;
+
+    Ok((status, unsafe { usage.assume_init() }))
+}
+
+fn report(status: &WaitStatus) {
+    match *status {
+        WaitStatus::Exited(pid, code) => {
+            eprintln!("child (PID={}) finished: exit, status={}", pid, code)
         }
+        WaitStatus::Signaled(pid, sig, core_dumped) => eprintln!(
+            "child (PID={}) finished: signal, sig={}{}",
+            pid,
+            sig,
+            if core_dumped { " (core dumped)" } else { "" }
+        ),
+        WaitStatus::Stopped(pid, sig) => eprintln!("child (PID={}) stopped: sig={}", pid, sig),
+        WaitStatus::Continued(pid) => eprintln!("child (PID={}) continued", pid),
+        _ => {}
+    }
+}
+
+// GNU time -v 形式の報告
+fn report_usage(command: &[String], usage: &libc::rusage, elapsed: Duration, status: &WaitStatus) {
+    let seconds = |tv: libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1e6;
+    let user = seconds(usage.ru_utime);
+    let system = seconds(usage.ru_stime);
+    let wall = elapsed.as_secs_f64();
+    let percent = if wall > 0.0 {
+        ((user + system) / wall * 100.0).round() as u64
+    } else {
+        0
+    };
+
+    let centis = elapsed.as_millis() / 10;
+    let (hours, minutes, secs) = (centis / 360_000, centis / 6000 % 60, centis % 6000);
+    let wall_clock = if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs / 100)
+    } else {
+        format!("{}:{:02}.{:02}", minutes, secs / 100, secs % 100)
     };
+
+    eprintln!("\tCommand being timed: {:?}", command.join(" "));
+    eprintln!("\tUser time (seconds): {:.2}", user);
+    eprintln!("\tSystem time (seconds): {:.2}", system);
+    eprintln!("\tPercent of CPU this job got: {}%", percent);
+    eprintln!(
+        "\tElapsed (wall clock) time (h:mm:ss or m:ss): {}",
+        wall_clock
+    );
+    eprintln!("\tMaximum resident set size (kbytes): {}", usage.ru_maxrss);
+    eprintln!("\tMajor (requiring I/O) page faults: {}", usage.ru_majflt);
+    eprintln!(
+        "\tMinor (reclaiming a frame) page faults: {}",
+        usage.ru_minflt
+    );
+    eprintln!("\tVoluntary context switches: {}", usage.ru_nvcsw);
+    eprintln!("\tInvoluntary context switches: {}", usage.ru_nivcsw);
+    eprintln!("\tFile system inputs: {}", usage.ru_inblock);
+    eprintln!("\tFile system outputs: {}", usage.ru_oublock);
+    match *status {
+        WaitStatus::Signaled(_, sig, _) => {
+            eprintln!("\tCommand terminated by signal {}", sig as i32)
+        }
+        WaitStatus::Exited(_, code) => eprintln!("\tExit status: {}", code),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let secs = |n: f64| Some(Duration::from_secs_f64(n));
+        assert_eq!(parse_duration("10"), secs(10.0));
+        assert_eq!(parse_duration("10s"), secs(10.0));
+        assert_eq!(parse_duration("2m"), secs(120.0));
+        assert_eq!(parse_duration("1h"), secs(3600.0));
+        assert_eq!(parse_duration("2d"), secs(172800.0));
+        assert_eq!(parse_duration("0.5"), secs(0.5));
+        assert_eq!(parse_duration(".25m"), secs(15.0));
+        assert_eq!(parse_duration("1.5h"), secs(5400.0));
+        assert_eq!(parse_duration("0"), secs(0.0));
+
+        for bad in &[
+            "", "s", "-1", "-0.5s", "1x", "1 s", "abc", "inf", "nan", "1e400",
+        ] {
+            assert_eq!(parse_duration(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn signals() {
+        assert_eq!(parse_signal("TERM"), Some(Signal::SIGTERM));
+        assert_eq!(parse_signal("SIGKILL"), Some(Signal::SIGKILL));
+        assert_eq!(parse_signal("int"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("sigHup"), Some(Signal::SIGHUP));
+        assert_eq!(parse_signal("9"), Some(Signal::SIGKILL));
+        assert_eq!(parse_signal("15"), Some(Signal::SIGTERM));
+
+        for bad in &["", "0", "-9", "999", "SIG", "NOSUCH", "SIGNOSUCH", "9x"] {
+            assert_eq!(parse_signal(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn limit_values() {
+        let unlimited = libc::RLIM_INFINITY;
+        assert_eq!(parse_limit("10", false), Some((10, 10)));
+        assert_eq!(
+            parse_limit("unlimited", false),
+            Some((unlimited, unlimited))
+        );
+        assert_eq!(parse_limit("4K", true), Some((4096, 4096)));
+        assert_eq!(parse_limit("2m", true), Some((2 << 20, 2 << 20)));
+        assert_eq!(parse_limit("1G", true), Some((1 << 30, 1 << 30)));
+        assert_eq!(parse_limit("64:1024", false), Some((64, 1024)));
+        assert_eq!(
+            parse_limit("1M:unlimited", true),
+            Some((1 << 20, unlimited))
+        );
+        assert_eq!(parse_limit("5:5", false), Some((5, 5)));
+
+        for bad in &[
+            "",
+            "-1",
+            "1.5",
+            "abc",
+            "Unlimited",
+            "4K",
+            "10:5",
+            "unlimited:10",
+            ":10",
+            "10:",
+            "1:2:3",
+            "99999999999G",
+        ] {
+            assert_eq!(parse_limit(bad, false), None, "{:?}", bad);
+        }
+        assert_eq!(parse_limit("99999999999G", true), None);
+        assert_eq!(parse_limit("4T", true), None);
+    }
+
+    #[test]
+    fn limit_options() {
+        let mut opts = Options::new();
+        for (name, _, _) in &LIMITS {
+            opts.optopt("", name, "", "VALUE");
+        }
+        let parse = |args: &[&str]| parse_limits(&opts.parse(args).unwrap());
+
+        let limits = parse(&["--limit-as=1M:2M", "--limit-nofile=64", "--limit-core=0"]).unwrap();
+        let got: Vec<(Resource, libc::rlim_t, libc::rlim_t)> = limits
+            .iter()
+            .map(|limit| (limit.resource, limit.soft, limit.hard))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                (libc::RLIMIT_AS, 1 << 20, 2 << 20),
+                (libc::RLIMIT_NOFILE, 64, 64),
+                (libc::RLIMIT_CORE, 0, 0),
+            ]
+        );
+        let limits = parse(&["--limit-cpu=unlimited"]).unwrap();
+        assert_eq!(limits[0].resource, libc::RLIMIT_CPU);
+        assert_eq!(limits[0].soft, libc::RLIM_INFINITY);
+
+        // only sizes take a suffix
+        assert!(parse(&["--limit-core=1K"]).is_ok());
+        assert_eq!(
+            parse(&["--limit-cpu=1K"]).err().as_deref(),
+            Some("invalid value for --limit-cpu: \"1K\"")
+        );
+        assert!(parse(&["--limit-nofile=2:1"]).is_err());
+    }
 }
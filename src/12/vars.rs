@@ -0,0 +1,136 @@
+// sh の変数. シェル変数と環境変数, 特殊パラメータを持つ.
+
+use nix::unistd::{getpid, Pid};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::CString;
+
+pub struct Variables {
+    values: HashMap<String, String>,
+    // names passed on to commands in their environment
+    exported: HashSet<String>,
+    // $?
+    pub status: i32,
+    // $$: the shell itself, even in a subshell
+    pid: Pid,
+    // $!
+    pub last_background: Option<Pid>,
+    // $0, and $1 $2 ...
+    name: String,
+    positional: Vec<String>,
+    // set -o nullglob: a pattern that matches no file expands to nothing
+    pub nullglob: bool,
+}
+
+// NAME: letters, digits and _, not starting with a digit
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+impl Variables {
+    // The environment of the shell becomes its exported variables; the
+    // arguments of the shell its positional parameters.
+    pub fn from_env() -> Variables {
+        let mut values = HashMap::new();
+        let mut exported = HashSet::new();
+        for (name, value) in env::vars_os() {
+            if let (Ok(name), Ok(value)) = (name.into_string(), value.into_string()) {
+                exported.insert(name.clone());
+                values.insert(name, value);
+            }
+        }
+
+        let mut args = env::args();
+        let name = args.next().unwrap_or_else(|| "sh".to_string());
+
+        Variables {
+            values,
+            exported,
+            status: 0,
+            pid: getpid(),
+            last_background: None,
+            name,
+            positional: args.collect(),
+            nullglob: false,
+        }
+    }
+
+    // The value of a variable or of a special parameter.
+    pub fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "0" => Some(self.name.clone()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            _ => match name.parse::<usize>() {
+                Ok(n) => self.positional.get(n.wrapping_sub(1)).cloned(),
+                Err(_) => self.values.get(name).cloned(),
+            },
+        }
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.values.remove(name);
+        self.exported.remove(name);
+    }
+
+    // Put back a value saved with get, or the lack of one. Whether the
+    // variable is exported stays as it is.
+    pub fn restore(&mut self, name: &str, value: Option<&str>) {
+        match value {
+            Some(value) => self.set(name, value),
+            None => {
+                self.values.remove(name);
+            }
+        }
+    }
+
+    pub fn export(&mut self, name: &str) {
+        self.exported.insert(name.to_string());
+    }
+
+    // Exported variables that have a value, sorted by name.
+    pub fn exported(&self) -> Vec<(&str, &str)> {
+        let mut vars: Vec<(&str, &str)> = self
+            .exported
+            .iter()
+            .filter_map(|name| Some((name.as_str(), self.values.get(name)?.as_str())))
+            .collect();
+        vars.sort();
+        vars
+    }
+
+    // The environment of a command: the exported variables, overridden by
+    // the assignments written in front of the command.
+    pub fn environment(&self, assignments: &[(String, String)]) -> Vec<CString> {
+        let mut env: Vec<(&str, &str)> = self
+            .exported()
+            .into_iter()
+            .filter(|(name, _)| assignments.iter().all(|(assigned, _)| assigned != name))
+            .collect();
+        env.extend(
+            assignments
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+
+        env.iter()
+            .filter_map(|(name, value)| CString::new(format!("{}={}", name, value)).ok())
+            .collect()
+    }
+}
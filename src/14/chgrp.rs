@@ -0,0 +1,7 @@
+mod ids;
+mod ownership;
+
+// chgrp GROUP は chown :GROUP と同じ
+fn main() {
+    ownership::run(true);
+}
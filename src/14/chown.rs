@@ -0,0 +1,6 @@
+mod ids;
+mod ownership;
+
+fn main() {
+    ownership::run(false);
+}
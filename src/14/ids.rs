@@ -0,0 +1,56 @@
+// ユーザ名, グループ名と ID の相互変換. lse と同じく nix::unistd の User, Group を使う.
+//
+// Not every binary uses every item in this module.
+#![allow(dead_code)]
+
+use nix::unistd::{Gid, Group, Uid, User};
+
+// A user given by name or, failing that, by number.
+pub fn parse_user(spec: &str) -> Result<User, String> {
+    if let Ok(Some(user)) = User::from_name(spec) {
+        return Ok(user);
+    }
+    match spec.parse::<u32>() {
+        Ok(uid) => match User::from_uid(Uid::from_raw(uid)) {
+            Ok(Some(user)) => Ok(user),
+            _ => Err(format!("no user with uid {}", uid)),
+        },
+        Err(_) => Err(format!("invalid user: {:?}", spec)),
+    }
+}
+
+// Like parse_user, but a number does not need a passwd entry.
+pub fn parse_uid(spec: &str) -> Result<Uid, String> {
+    match User::from_name(spec) {
+        Ok(Some(user)) => Ok(user.uid),
+        _ => spec
+            .parse::<u32>()
+            .map(Uid::from_raw)
+            .map_err(|_| format!("invalid user: {:?}", spec)),
+    }
+}
+
+pub fn parse_gid(spec: &str) -> Result<Gid, String> {
+    match Group::from_name(spec) {
+        Ok(Some(group)) => Ok(group.gid),
+        _ => spec
+            .parse::<u32>()
+            .map(Gid::from_raw)
+            .map_err(|_| format!("invalid group: {:?}", spec)),
+    }
+}
+
+// the name of `uid`, or the number when it has none
+pub fn user_name(uid: Uid) -> String {
+    match User::from_uid(uid) {
+        Ok(Some(user)) => user.name,
+        _ => uid.to_string(),
+    }
+}
+
+pub fn group_name(gid: Gid) -> String {
+    match Group::from_gid(gid) {
+        Ok(Some(group)) => group.name,
+        _ => gid.to_string(),
+    }
+}
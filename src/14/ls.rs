@@ -1,9 +1,11 @@
+mod ids;
+
 use anyhow::Result;
 use chrono::{DateTime, Local, TimeZone};
 use nix::dir::Dir;
 use nix::fcntl::OFlag;
 use nix::sys::stat::{stat, Mode};
-use nix::unistd::{Gid, Group, Uid, User};
+use nix::unistd::{Gid, Uid};
 use std::env;
 use std::process;
 
@@ -32,18 +34,16 @@ fn do_ls(path: &str) -> Result<()> {
         let filename = entry.file_name().to_str()?;
         let stat = stat(filename)?;
 
-        if let Some(user) = User::from_uid(Uid::from_raw(stat.st_uid))? {
-            if let Some(group) = Group::from_gid(Gid::from_raw(stat.st_gid))? {
-                let dt: DateTime<Local> = Local.timestamp(stat.st_mtime, stat.st_mtime_nsec as u32);
-                println!(
-                    "{: <10} {: >6} {: >8} {}",
-                    filename,
-                    user.name,
-                    group.name,
-                    dt.format("%Y-%m-%d %H:%M:%S").to_string()
-                );
-            }
-        }
+        let dt: DateTime<Local> = Local
+            .timestamp_opt(stat.st_mtime, stat.st_mtime_nsec as u32)
+            .unwrap();
+        println!(
+            "{: <10} {: >6} {: >8} {}",
+            filename,
+            ids::user_name(Uid::from_raw(stat.st_uid)),
+            ids::group_name(Gid::from_raw(stat.st_gid)),
+            dt.format("%Y-%m-%d %H:%M:%S")
+        );
     }
 
     Ok(())
@@ -0,0 +1,270 @@
+// chown と chgrp の共通実装. chgrp は chown :GROUP として振る舞う.
+
+use getopts::Options;
+use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process;
+
+use crate::ids;
+
+struct ChownOptions {
+    owner: Option<Uid>,
+    group: Option<Gid>,
+    from_owner: Option<Uid>,
+    from_group: Option<Gid>,
+    recursive: bool,
+    no_dereference: bool,
+}
+
+pub fn run(group_only: bool) {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optflag("", "help", "print this help menu");
+    opts.optflag(
+        "R",
+        "recursive",
+        "operate on files and directories recursively",
+    );
+    opts.optflag(
+        "h",
+        "no-dereference",
+        "affect symbolic links instead of any referenced file",
+    );
+    opts.optopt(
+        "",
+        "reference",
+        "use RFILE's owner and group rather than specifying values",
+        "RFILE",
+    );
+    if !group_only {
+        opts.optopt(
+            "",
+            "from",
+            "change only if the current owner and/or group match",
+            "CURRENT_OWNER:CURRENT_GROUP",
+        );
+    }
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{:?}: {}", &args[0], f);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("help") {
+        if group_only {
+            println!(
+                "Usage: {:?} [-Rh] GROUP FILE...\n       {:?} [-Rh] --reference=RFILE FILE...",
+                &args[0], &args[0]
+            );
+        } else {
+            println!(
+                "Usage: {:?} [-Rh] [--from=CURRENT_OWNER:CURRENT_GROUP] OWNER[:[GROUP]] FILE...\n       {:?} [-Rh] [--from=CURRENT_OWNER:CURRENT_GROUP] --reference=RFILE FILE...",
+                &args[0], &args[0]
+            );
+        }
+        process::exit(0);
+    }
+
+    let mut files = matches.free.clone();
+    let target = if let Some(reference) = matches.opt_str("reference") {
+        match fs::metadata(&reference) {
+            Ok(st) => {
+                let group = Some(Gid::from_raw(st.gid()));
+                if group_only {
+                    Ok((None, group))
+                } else {
+                    Ok((Some(Uid::from_raw(st.uid())), group))
+                }
+            }
+            Err(why) => Err(format!("{:?}: {}", reference, why)),
+        }
+    } else if files.is_empty() {
+        Err("missing operand".to_string())
+    } else {
+        let spec = files.remove(0);
+        if group_only {
+            ids::parse_gid(&spec).map(|gid| (None, Some(gid)))
+        } else {
+            parse_owner_spec(&spec, true)
+        }
+    };
+    // chgrp has no --from
+    let from = if group_only {
+        None
+    } else {
+        matches.opt_str("from")
+    };
+    let from = match from {
+        Some(spec) => parse_owner_spec(&spec, false),
+        None => Ok((None, None)),
+    };
+
+    let ((owner, group), (from_owner, from_group)) = match (target, from) {
+        (Ok(target), Ok(from)) => (target, from),
+        (Err(msg), _) | (_, Err(msg)) => {
+            eprintln!("{:?}: {}", &args[0], msg);
+            process::exit(1);
+        }
+    };
+
+    if files.is_empty() {
+        eprintln!("{:?}: missing operand", &args[0]);
+        process::exit(1);
+    }
+
+    let chown_opts = ChownOptions {
+        owner,
+        group,
+        from_owner,
+        from_group,
+        recursive: matches.opt_present("R"),
+        // -R never follows symbolic links, so it implies -h
+        no_dereference: matches.opt_present("h") || matches.opt_present("R"),
+    };
+
+    let mut status = 0;
+    for file in &files {
+        if !do_chown(Path::new(file), &chown_opts) {
+            status = 1;
+        }
+    }
+
+    process::exit(status);
+}
+
+// OWNER, OWNER:GROUP, OWNER: (the owner's login group) or :GROUP.
+// `login_group` enables the OWNER: form, which --from does not have.
+fn parse_owner_spec(spec: &str, login_group: bool) -> Result<(Option<Uid>, Option<Gid>), String> {
+    let (owner, group) = match spec.find(':') {
+        Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+        None => (spec, None),
+    };
+
+    match (owner, group) {
+        ("", None) | ("", Some("")) => Err(format!("invalid spec: {:?}", spec)),
+        ("", Some(group)) => Ok((None, Some(ids::parse_gid(group)?))),
+        (owner, None) => Ok((Some(ids::parse_uid(owner)?), None)),
+        (owner, Some("")) if login_group => {
+            let user = ids::parse_user(owner)?;
+            Ok((Some(user.uid), Some(user.gid)))
+        }
+        (owner, Some("")) => Ok((Some(ids::parse_uid(owner)?), None)),
+        (owner, Some(group)) => Ok((Some(ids::parse_uid(owner)?), Some(ids::parse_gid(group)?))),
+    }
+}
+
+// Change `path` and, with -R, everything below it; every failure is
+// reported and the rest still processed. Returns false when anything failed.
+fn do_chown(path: &Path, opts: &ChownOptions) -> bool {
+    let mut ok = match change_owner(path, opts) {
+        Ok(()) => true,
+        Err(why) => {
+            eprintln!("{:?}: {:?}", path, why.to_string());
+            false
+        }
+    };
+
+    let is_dir = fs::symlink_metadata(path)
+        .map(|st| st.is_dir())
+        .unwrap_or(false);
+    if !opts.recursive || !is_dir {
+        return ok;
+    }
+
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(why) => {
+            eprintln!("{:?}: {:?}", path, why.to_string());
+            return false;
+        }
+    };
+    for entry in entries {
+        match entry {
+            Ok(entry) => ok &= do_chown(&entry.path(), opts),
+            Err(why) => {
+                eprintln!("{:?}: {:?}", path, why.to_string());
+                ok = false;
+            }
+        }
+    }
+
+    ok
+}
+
+fn change_owner(path: &Path, opts: &ChownOptions) -> io::Result<()> {
+    let (st, flag) = if opts.no_dereference {
+        (fs::symlink_metadata(path)?, FchownatFlags::NoFollowSymlink)
+    } else {
+        (fs::metadata(path)?, FchownatFlags::FollowSymlink)
+    };
+
+    // --from: leave files owned by someone else alone
+    if opts.from_owner.is_some_and(|uid| uid.as_raw() != st.uid())
+        || opts.from_group.is_some_and(|gid| gid.as_raw() != st.gid())
+    {
+        return Ok(());
+    }
+
+    fchownat(None, path, opts.owner, opts.group, flag).map_err(|why| match why.as_errno() {
+        Some(errno) => io::Error::from(errno),
+        None => io::Error::other(why.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // root is the only account every system has
+    fn ids(uid: Option<u32>, gid: Option<u32>) -> Result<(Option<Uid>, Option<Gid>), String> {
+        Ok((uid.map(Uid::from_raw), gid.map(Gid::from_raw)))
+    }
+
+    #[test]
+    fn owner_specs() {
+        assert_eq!(parse_owner_spec("root", true), ids(Some(0), None));
+        assert_eq!(parse_owner_spec("root:root", true), ids(Some(0), Some(0)));
+        assert_eq!(parse_owner_spec(":root", true), ids(None, Some(0)));
+        // OWNER: also sets the login group
+        assert_eq!(parse_owner_spec("root:", true), ids(Some(0), Some(0)));
+        assert_eq!(parse_owner_spec("root:", false), ids(Some(0), None));
+    }
+
+    #[test]
+    fn numeric_ids() {
+        assert_eq!(parse_owner_spec("0:0", true), ids(Some(0), Some(0)));
+        // a number needs no passwd or group entry...
+        assert_eq!(
+            parse_owner_spec("4242424:4242425", true),
+            ids(Some(4242424), Some(4242425))
+        );
+        assert_eq!(parse_owner_spec(":4242425", true), ids(None, Some(4242425)));
+        // ...except to find the login group
+        assert_eq!(parse_owner_spec("0:", true), ids(Some(0), Some(0)));
+        assert!(parse_owner_spec("4242424:", true).is_err());
+    }
+
+    #[test]
+    fn invalid_specs() {
+        for spec in &[
+            "",
+            ":",
+            "no-such-user-xyz",
+            "no-such-user-xyz:root",
+            "root:no-such-group-xyz",
+            ":no-such-group-xyz",
+            "-1",
+            "root:0:0",
+        ] {
+            assert!(parse_owner_spec(spec, true).is_err(), "{:?}", spec);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// sh のジョブ制御の結合テスト. 擬似端末の上で対話シェルを動かす.
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::openpty;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::waitpid;
+use nix::unistd::{close, dup2, execve, fork, read, setsid, write, ForkResult, Pid};
+use std::ffi::CString;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+struct Terminal {
+    master: RawFd,
+    shell: Pid,
+    // for the history file
+    home: PathBuf,
+    // everything the shell has written so far
+    output: String,
+}
+
+impl Terminal {
+    // An interactive sh with the slave side as its controlling terminal.
+    fn start() -> Terminal {
+        let pty = openpty(None, None).unwrap();
+        let path = CString::new(env!("CARGO_BIN_EXE_sh")).unwrap();
+        let home = std::env::temp_dir().join(format!("sh-jobs-test-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        let env = [
+            CString::new(format!("HOME={}", home.display())).unwrap(),
+            CString::new("PATH=/usr/bin:/bin").unwrap(),
+            CString::new("TERM=dumb").unwrap(),
+        ];
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Parent { child } => {
+                close(pty.slave).unwrap();
+                fcntl(pty.master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+                Terminal {
+                    master: pty.master,
+                    shell: child,
+                    home,
+                    output: String::new(),
+                }
+            }
+            ForkResult::Child => {
+                setsid().unwrap();
+                unsafe { libc::ioctl(pty.slave, libc::TIOCSCTTY, 0) };
+                for fd in 0..3 {
+                    dup2(pty.slave, fd).unwrap();
+                }
+                close(pty.master).ok();
+                match execve(&path, std::slice::from_ref(&path), &env) {
+                    Ok(never) => match never {},
+                    Err(why) => {
+                        eprintln!("{:?}: {}", path, why);
+                        std::process::exit(127);
+                    }
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        write(self.master, format!("{}\r", line).as_bytes()).unwrap();
+    }
+
+    // Read until the output after `from` contains `text`. Returns where it
+    // ends.
+    fn expect(&mut self, from: usize, text: &str) -> usize {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(pos) = self.output[from..].find(text) {
+                return from + pos + text.len();
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            assert!(
+                !left.is_zero(),
+                "{:?} did not appear in {:?}",
+                text,
+                &self.output[from..]
+            );
+            let mut fds = [PollFd::new(self.master, PollFlags::POLLIN)];
+            poll(&mut fds, left.as_millis() as i32).ok();
+            let mut buf = [0; 1024];
+            if let Ok(n) = read(self.master, &mut buf) {
+                self.output.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        kill(self.shell, Signal::SIGKILL).ok();
+        waitpid(self.shell, None).ok();
+        close(self.master).ok();
+        std::fs::remove_dir_all(&self.home).ok();
+    }
+}
+
+#[test]
+fn background_jobs() {
+    let mut term = Terminal::start();
+    let at = term.expect(0, "$ ");
+
+    term.send("sleep 10 &");
+    let at = term.expect(at, "[1] ");
+    let at = term.expect(at, "$ ");
+
+    term.send("jobs");
+    let at = term.expect(at, &format!("[1]+  {:<24}sleep 10 &", "Running"));
+    let at = term.expect(at, "$ ");
+
+    // the notification comes before a later prompt once the job has gone
+    term.send("kill %1");
+    std::thread::sleep(Duration::from_millis(100));
+    term.send("");
+    let at = term.expect(at, &format!("[1]+  {:<24}sleep 10", "Terminated"));
+
+    // reported before the next prompt, or the one after, as it may not
+    // have exited yet
+    term.send("true &");
+    let at = term.expect(at, "[1] ");
+    std::thread::sleep(Duration::from_millis(100));
+    term.send("");
+    let at = term.expect(at, &format!("[1]+  {:<24}true", "Done"));
+
+    // a job is reported as finished only once
+    term.send("jobs");
+    let end = term.expect(at, "$ ");
+    let listed = &term.output[at..end];
+    assert!(!listed.contains("[1]"), "{:?}", listed);
+    term.send("exit");
+}
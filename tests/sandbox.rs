@@ -0,0 +1,53 @@
+// spawn --sandbox の結合テスト. ユーザー名前空間が使えない環境では飛ばす.
+
+use std::process::{Command, Output};
+
+fn spawn(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_spawn"))
+        .args(args)
+        .output()
+        .expect("spawn did not run")
+}
+
+// None when the sandbox cannot be entered here, e.g. without user namespaces
+fn sandboxed(args: &[&str]) -> Option<Output> {
+    let mut all = vec!["--sandbox", "--sandbox-root=/"];
+    all.extend_from_slice(args);
+    let output = spawn(&all);
+    if String::from_utf8_lossy(&output.stderr).contains("sandbox: ") {
+        eprintln!("skipped: user namespaces are unavailable");
+        return None;
+    }
+    Some(output)
+}
+
+#[test]
+fn root_is_read_only() {
+    let output = match sandboxed(&["sh", "-c", ": > /.sandbox-test"]) {
+        Some(output) => output,
+        None => return,
+    };
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Read-only file system"), "{}", stderr);
+}
+
+#[test]
+fn tmp_is_writable() {
+    let output = match sandboxed(&["sh", "-c", ": > /tmp/sandbox-test"]) {
+        Some(output) => output,
+        None => return,
+    };
+    assert!(output.status.success());
+}
+
+#[test]
+fn denied_system_call_fails() {
+    let output = match sandboxed(&["--seccomp-deny=kill", "sh", "-c", "kill -0 $$"]) {
+        Some(output) => output,
+        None => return,
+    };
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not permitted"), "{}", stderr);
+}
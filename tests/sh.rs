@@ -0,0 +1,108 @@
+// sh のリダイレクトの結合テスト. スクリプトを標準入力から読ませ, 書かれたファイルを見る.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+struct Dir(PathBuf);
+
+impl Dir {
+    fn new(name: &str) -> Dir {
+        let path = env::temp_dir().join(format!("sh-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        Dir(path)
+    }
+
+    fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.0.join(name)).unwrap()
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+// prompts go to stdout when it is not a terminal, so the script writes
+// what is checked into files in `dir`
+fn run(dir: &Path, script: &str) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sh"))
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("sh did not run");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn fd_duplication() {
+    let dir = Dir::new("dup");
+    run(
+        &dir.0,
+        "{ echo out; echo err >&2; } >both 2>&1\n\
+         { echo out; echo err >&2; } 2>&1 >first 2>stderr\n\
+         echo three 3>fd3 >&3\n\
+         echo a >in; cat 3<in <&3 >copy\n\
+         echo open >closed; echo closed >&- 2>/dev/null; echo $? >status\n",
+    );
+    assert_eq!(dir.read("both"), "out\nerr\n");
+    // redirections are done from left to right
+    assert_eq!(dir.read("first"), "out\n");
+    assert_eq!(dir.read("stderr"), "err\n");
+    assert_eq!(dir.read("fd3"), "three\n");
+    assert_eq!(dir.read("copy"), "a\n");
+    assert_eq!(dir.read("closed"), "open\n");
+    assert_ne!(dir.read("status"), "0\n");
+}
+
+#[test]
+fn here_documents() {
+    let dir = Dir::new("heredoc");
+    run(
+        &dir.0,
+        "X=value\n\
+         cat <<EOF >expanded\n$X $(echo sub) \\$X\nEOF\n\
+         cat <<'EOF' >quoted\n$X $(echo sub)\nEOF\n\
+         cat <<A >two; cat <<B >>two\nfirst\nA\nsecond\nB\n",
+    );
+    assert_eq!(dir.read("expanded"), "value sub $X\n");
+    assert_eq!(dir.read("quoted"), "$X $(echo sub)\n");
+    assert_eq!(dir.read("two"), "first\nsecond\n");
+}
+
+#[test]
+fn here_documents_strip_tabs() {
+    let dir = Dir::new("heredoc-tabs");
+    run(
+        &dir.0,
+        "cat <<-EOF >stripped\n\t\tindented\n  spaces\n\tEOF\n\
+         cat <<EOF >kept\n\tindented\nEOF\n",
+    );
+    // only leading tabs, on the delimiter line too
+    assert_eq!(dir.read("stripped"), "indented\n  spaces\n");
+    assert_eq!(dir.read("kept"), "\tindented\n");
+}
+
+#[test]
+fn here_strings() {
+    let dir = Dir::new("herestring");
+    run(
+        &dir.0,
+        "X='a  b'\n\
+         cat <<< \"$X\" >quoted\n\
+         tr a-z A-Z <<< word >upper\n",
+    );
+    assert_eq!(dir.read("quoted"), "a  b\n");
+    assert_eq!(dir.read("upper"), "WORD\n");
+}
@@ -0,0 +1,64 @@
+// spawn の終了ステータスの結合テスト. timeout(1) や env(1) と同じ値を返すことを見る.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+fn spawn(args: &[&str]) -> i32 {
+    let status = Command::new(env!("CARGO_BIN_EXE_spawn"))
+        .arg("-q")
+        .args(args)
+        .status()
+        .expect("spawn did not run");
+    status.code().expect("spawn was killed")
+}
+
+#[test]
+fn exit_status_is_passed_on() {
+    assert_eq!(spawn(&["true"]), 0);
+    assert_eq!(spawn(&["sh", "-c", "exit 3"]), 3);
+}
+
+#[test]
+fn timeout() {
+    let start = Instant::now();
+    assert_eq!(spawn(&["--timeout=0.2", "sleep", "10"]), 124);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    // a command that finishes in time keeps its own status
+    assert_eq!(spawn(&["--timeout=10", "sh", "-c", "exit 3"]), 3);
+}
+
+#[test]
+fn timeout_kill_after() {
+    // the command ignores SIGTERM, so only -k ends it
+    let start = Instant::now();
+    let status = spawn(&[
+        "--timeout=0.2",
+        "-k",
+        "0.2",
+        "sh",
+        "-c",
+        "trap '' TERM; sleep 10",
+    ]);
+    assert_eq!(status, 128 + libc::SIGKILL);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn signalled_child() {
+    assert_eq!(spawn(&["sh", "-c", "kill -USR1 $$"]), 128 + libc::SIGUSR1);
+    assert_eq!(spawn(&["sh", "-c", "kill -TERM $$"]), 128 + libc::SIGTERM);
+}
+
+#[test]
+fn command_not_run() {
+    assert_eq!(spawn(&["spawn-test-no-such-command"]), 127);
+
+    // found but not executable
+    let path = env::temp_dir().join(format!("spawn-test-{}", std::process::id()));
+    fs::write(&path, b"").unwrap();
+    let status = spawn(&[path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(status, 126);
+}